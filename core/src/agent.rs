@@ -0,0 +1,276 @@
+use std::{cell::Cell, rc::Rc};
+
+use burn::prelude::Backend;
+use rand::RngCore;
+
+use crate::{
+    Control, OUTPUT_SIZE, Observation,
+    bots::ScriptedBot,
+    model::DQN,
+    policy::{ActorCritic, policy_action},
+    recurrent::{RecurrentDQN, RecurrentState, select_recurrent_actions},
+    select_action,
+};
+
+/// Anything that can fight: trained models, scripted bots, humans, replays and ensembles.
+pub trait Agent {
+    fn act(&mut self, observation: &Observation, rng: &mut dyn RngCore) -> Control;
+
+    /// Called before every new fight
+    fn reset(&mut self) {}
+
+    fn name(&self) -> String;
+}
+
+/// Epsilon-greedy DQN fighter
+pub struct DqnAgent<B: Backend> {
+    pub model: DQN<B>,
+    pub epsilon: f32,
+    device: B::Device,
+    name: String,
+}
+
+impl<B: Backend> DqnAgent<B> {
+    pub fn new(name: impl Into<String>, model: DQN<B>, epsilon: f32, device: &B::Device) -> Self {
+        Self {
+            model,
+            epsilon,
+            device: device.clone(),
+            name: name.into(),
+        }
+    }
+}
+
+impl<B: Backend> Agent for DqnAgent<B> {
+    fn act(&mut self, observation: &Observation, rng: &mut dyn RngCore) -> Control {
+        Control::from_int(select_action(
+            *observation,
+            &self.model,
+            self.epsilon,
+            OUTPUT_SIZE,
+            rng,
+            &self.device,
+        ))
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+/// Actor-critic fighter, samples its actions from the policy unless `greedy`
+pub struct PolicyAgent<B: Backend> {
+    pub model: ActorCritic<B>,
+    pub greedy: bool,
+    device: B::Device,
+    name: String,
+}
+
+impl<B: Backend> PolicyAgent<B> {
+    pub fn new(
+        name: impl Into<String>,
+        model: ActorCritic<B>,
+        greedy: bool,
+        device: &B::Device,
+    ) -> Self {
+        Self {
+            model,
+            greedy,
+            device: device.clone(),
+            name: name.into(),
+        }
+    }
+}
+
+impl<B: Backend> Agent for PolicyAgent<B> {
+    fn act(&mut self, observation: &Observation, rng: &mut dyn RngCore) -> Control {
+        Control::from_int(policy_action(
+            *observation,
+            &self.model,
+            self.greedy,
+            rng,
+            &self.device,
+        ))
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+/// Epsilon-greedy recurrent DQN fighter, remembers the fight so far until `reset`
+pub struct RecurrentAgent<B: Backend> {
+    pub model: RecurrentDQN<B>,
+    pub epsilon: f32,
+    state: Option<RecurrentState<B>>,
+    device: B::Device,
+    name: String,
+}
+
+impl<B: Backend> RecurrentAgent<B> {
+    pub fn new(
+        name: impl Into<String>,
+        model: RecurrentDQN<B>,
+        epsilon: f32,
+        device: &B::Device,
+    ) -> Self {
+        Self {
+            model,
+            epsilon,
+            state: None,
+            device: device.clone(),
+            name: name.into(),
+        }
+    }
+}
+
+impl<B: Backend> Agent for RecurrentAgent<B> {
+    fn act(&mut self, observation: &Observation, rng: &mut dyn RngCore) -> Control {
+        let actions = select_recurrent_actions(
+            &[*observation],
+            &self.model,
+            &mut self.state,
+            self.epsilon,
+            rng,
+            &self.device,
+        );
+        Control::from_int(actions[0])
+    }
+
+    fn reset(&mut self) {
+        self.state = None;
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+impl Agent for ScriptedBot {
+    fn act(&mut self, observation: &Observation, rng: &mut dyn RngCore) -> Control {
+        ScriptedBot::act(self, observation, rng)
+    }
+
+    fn name(&self) -> String {
+        format!("bot_{}", ScriptedBot::name(self))
+    }
+}
+
+/// Plays whatever the front-end last wrote into its input, e.g. from a keyboard.
+pub struct HumanAgent {
+    input: Rc<Cell<Control>>,
+    name: String,
+}
+
+impl HumanAgent {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            input: Rc::new(Cell::new(Control::default())),
+            name: name.into(),
+        }
+    }
+
+    /// Shared handle the front-end writes the current control to
+    pub fn input(&self) -> Rc<Cell<Control>> {
+        self.input.clone()
+    }
+}
+
+impl Agent for HumanAgent {
+    fn act(&mut self, _observation: &Observation, _rng: &mut dyn RngCore) -> Control {
+        self.input.get()
+    }
+
+    fn reset(&mut self) {
+        self.input.set(Control::default());
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+/// Replays a recorded sequence of controls, then stands still.
+pub struct ReplayAgent {
+    controls: Vec<Control>,
+    position: usize,
+    name: String,
+}
+
+impl ReplayAgent {
+    pub fn new(name: impl Into<String>, controls: Vec<Control>) -> Self {
+        Self {
+            controls,
+            position: 0,
+            name: name.into(),
+        }
+    }
+}
+
+impl Agent for ReplayAgent {
+    fn act(&mut self, _observation: &Observation, _rng: &mut dyn RngCore) -> Control {
+        let control = self
+            .controls
+            .get(self.position)
+            .copied()
+            .unwrap_or_default();
+        self.position += 1;
+        control
+    }
+
+    fn reset(&mut self) {
+        self.position = 0;
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+/// Majority vote over its members' actions, ties go to the earliest member.
+pub struct EnsembleAgent {
+    members: Vec<Box<dyn Agent>>,
+    name: String,
+}
+
+impl EnsembleAgent {
+    pub fn new(name: impl Into<String>, members: Vec<Box<dyn Agent>>) -> Self {
+        assert!(!members.is_empty(), "An ensemble needs at least one member");
+        Self {
+            members,
+            name: name.into(),
+        }
+    }
+}
+
+impl Agent for EnsembleAgent {
+    fn act(&mut self, observation: &Observation, rng: &mut dyn RngCore) -> Control {
+        let actions: Vec<usize> = self
+            .members
+            .iter_mut()
+            .map(|member| member.act(observation, rng).to_int())
+            .collect();
+
+        let mut votes = [0; OUTPUT_SIZE];
+        for action in &actions {
+            votes[*action] += 1;
+        }
+        let most_votes = actions.iter().map(|action| votes[*action]).max();
+        let action = actions
+            .iter()
+            .find(|action| Some(votes[**action]) == most_votes)
+            .copied()
+            .unwrap_or_default();
+        Control::from_int(action)
+    }
+
+    fn reset(&mut self) {
+        for member in self.members.iter_mut() {
+            member.reset();
+        }
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
@@ -0,0 +1,160 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{Control, MoveX, MoveY, OUTPUT_SIZE, Observation, Player};
+
+/// Hand-written fighters used as fixed benchmarks and curriculum opponents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ScriptedBot {
+    /// Walks in and throws whenever the opponent is in reach
+    Pressure,
+    /// Waits for the opponent to commit to a punch, slips it and fires back
+    CounterPuncher,
+    /// Keeps the opponent at the edge of its reach, circling and jabbing
+    OutBoxer,
+    /// Uniformly random actions
+    Random,
+    /// Never moves or punches
+    Idle,
+}
+
+// Fist hits land when the fist gets within this distance of the opponent's center
+const HIT_DISTANCE: f32 = Player::RADIUS + Player::FIST_RADIUS;
+// Center to center distance where a punch can still connect
+const PUNCH_RANGE: f32 = Player::MAX_REACH + HIT_DISTANCE;
+// The out-boxer backs off whenever the opponent gets closer than this
+const KEEP_AWAY_DISTANCE: f32 = 150.0;
+// Same encoding as `FistState::to_int`
+const RESTING: usize = 0;
+const EXTENDING: usize = 1;
+const RETRACTING: usize = 2;
+
+impl ScriptedBot {
+    pub const ALL: [ScriptedBot; 5] = [
+        ScriptedBot::Pressure,
+        ScriptedBot::CounterPuncher,
+        ScriptedBot::OutBoxer,
+        ScriptedBot::Random,
+        ScriptedBot::Idle,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScriptedBot::Pressure => "pressure",
+            ScriptedBot::CounterPuncher => "counter_puncher",
+            ScriptedBot::OutBoxer => "out_boxer",
+            ScriptedBot::Random => "random",
+            ScriptedBot::Idle => "idle",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|bot| bot.name() == name)
+    }
+
+    pub fn act<R: Rng + ?Sized>(&self, observation: &Observation, rng: &mut R) -> Control {
+        match self {
+            ScriptedBot::Pressure => pressure(observation),
+            ScriptedBot::CounterPuncher => counter_puncher(observation, rng),
+            ScriptedBot::OutBoxer => out_boxer(observation),
+            ScriptedBot::Random => Control::from_int(rng.random_range(0..OUTPUT_SIZE)),
+            ScriptedBot::Idle => Control::default(),
+        }
+    }
+
+    /// Same as `act`, as an action index for code built around `select_action`
+    pub fn select_action<R: Rng + ?Sized>(&self, observation: &Observation, rng: &mut R) -> usize {
+        self.act(observation, rng).to_int()
+    }
+}
+
+fn opponent_distance(observation: &Observation) -> f32 {
+    let [x, y] = observation.op_position;
+    (x * x + y * y).sqrt()
+}
+
+fn can_punch(observation: &Observation) -> bool {
+    observation.left_fist_state == RESTING
+        && observation.right_fist_state == RESTING
+        && observation.energy > 1.0
+}
+
+fn is_opponent_punching(observation: &Observation) -> bool {
+    observation.op_left_fist_state == EXTENDING || observation.op_right_fist_state == EXTENDING
+}
+
+/// Straight punches from the resting position fall just short at the distance the players
+/// settle at, so punches are thrown as hooks: strafe one way and throw the opposite fist.
+fn throw_hook(observation: &Observation, control: &mut Control) {
+    if observation.op_position[0] >= 0.0 {
+        control.move_x = MoveX::Left;
+        control.right_punch = true;
+    } else {
+        control.move_x = MoveX::Right;
+        control.left_punch = true;
+    }
+}
+
+/// Keeps strafing while a hook is on its way out
+fn follow_through(observation: &Observation, control: &mut Control) {
+    if observation.right_fist_state == EXTENDING {
+        control.move_x = MoveX::Left;
+    } else if observation.left_fist_state == EXTENDING {
+        control.move_x = MoveX::Right;
+    }
+}
+
+fn pressure(observation: &Observation) -> Control {
+    let mut control = Control::default();
+    follow_through(observation, &mut control);
+    if opponent_distance(observation) < PUNCH_RANGE && can_punch(observation) {
+        throw_hook(observation, &mut control);
+    }
+    control
+}
+
+fn counter_puncher<R: Rng + ?Sized>(observation: &Observation, rng: &mut R) -> Control {
+    let mut control = Control::default();
+    let in_range = opponent_distance(observation) < PUNCH_RANGE;
+    follow_through(observation, &mut control);
+
+    if is_opponent_punching(observation) && in_range {
+        // Slip to a random side while stepping back
+        control.move_y = MoveY::Back;
+        control.move_x = if rng.random_bool(0.5) {
+            MoveX::Left
+        } else {
+            MoveX::Right
+        };
+    } else if in_range
+        && can_punch(observation)
+        && (observation.op_left_fist_state == RETRACTING
+            || observation.op_right_fist_state == RETRACTING)
+    {
+        // The opponent is recovering from a punch, fire back
+        throw_hook(observation, &mut control);
+    }
+    control
+}
+
+fn out_boxer(observation: &Observation) -> Control {
+    let distance = opponent_distance(observation);
+    // Circle when there's nothing else to do
+    let mut control = Control {
+        move_x: MoveX::Right,
+        ..Control::default()
+    };
+    follow_through(observation, &mut control);
+
+    if distance < KEEP_AWAY_DISTANCE {
+        control.move_y = MoveY::Back;
+    }
+    // Only punch with enough energy left for the next one
+    if distance < PUNCH_RANGE
+        && can_punch(observation)
+        && observation.energy > Player::MAX_ENERGY * 0.5
+    {
+        throw_hook(observation, &mut control);
+    }
+    control
+}
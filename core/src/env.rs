@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+
+use crate::{Control, GameState, OBSERVATION_LENGTH, OUTPUT_SIZE, Observation, StepResult};
+
+/// Continuous observation space, described by its shape and bounds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoxSpace {
+    pub shape: Vec<usize>,
+    pub low: f32,
+    pub high: f32,
+}
+
+impl BoxSpace {
+    /// Bounds of `Observation::normalize`, repeated for each of `frames` stacked observations
+    pub fn normalized(frames: usize) -> Self {
+        Self {
+            shape: vec![OBSERVATION_LENGTH * frames],
+            low: -1.0,
+            high: 1.0,
+        }
+    }
+}
+
+/// Discrete action space of `n` actions, indexed the same way as `Control::from_int`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiscreteSpace {
+    pub n: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StepInfo {
+    /// Set once the episode is over and one player has more health than the other
+    pub winner: Option<usize>,
+    pub health: [f32; 2],
+    pub num_punches: [usize; 2],
+    pub num_landed_punches: [usize; 2],
+    pub episode_iters: usize,
+}
+
+pub struct EnvStep<O> {
+    pub observations: [O; 2],
+    pub rewards: [f32; 2],
+    /// The fight ended inside the game (a knockout)
+    pub terminated: bool,
+    /// The fight was cut short from the outside, e.g. by a time limit
+    pub truncated: bool,
+    pub info: StepInfo,
+}
+
+impl<O> EnvStep<O> {
+    pub fn is_done(&self) -> bool {
+        self.terminated || self.truncated
+    }
+}
+
+/// Two-player, Gym-style environment. Both players act on every step.
+pub trait BoxingEnv {
+    type Obs: Clone;
+
+    /// Starts a new fight and returns the first observation of each player
+    fn reset(&mut self, seed: Option<u64>) -> [Self::Obs; 2];
+
+    fn step(&mut self, actions: [usize; 2]) -> EnvStep<Self::Obs>;
+
+    fn observation_space(&self) -> BoxSpace;
+
+    fn action_space(&self) -> DiscreteSpace {
+        DiscreteSpace { n: OUTPUT_SIZE }
+    }
+
+    /// The underlying game, for rendering and statistics
+    fn state(&self) -> &GameState;
+}
+
+/// The bare game. Never truncates, so wrap it in a `TimeLimit` when fights must end.
+pub struct GameEnv {
+    game_state: GameState,
+    episode_iters: usize,
+}
+
+impl GameEnv {
+    pub fn new() -> Self {
+        Self {
+            game_state: GameState::new(),
+            episode_iters: 0,
+        }
+    }
+}
+
+impl Default for GameEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BoxingEnv for GameEnv {
+    type Obs = Observation;
+
+    // NOTE: the physics are deterministic, the seed is only there for wrappers that randomise
+    fn reset(&mut self, _seed: Option<u64>) -> [Observation; 2] {
+        self.game_state = GameState::new();
+        self.episode_iters = 0;
+        [
+            self.game_state.get_observation(0),
+            self.game_state.get_observation(1),
+        ]
+    }
+
+    fn step(&mut self, actions: [usize; 2]) -> EnvStep<Observation> {
+        let StepResult {
+            observations,
+            rewards,
+            is_done,
+        } = self.game_state.step(actions.map(Control::from_int));
+        self.episode_iters += 1;
+
+        EnvStep {
+            observations,
+            rewards,
+            terminated: is_done,
+            truncated: false,
+            info: StepInfo {
+                winner: if is_done {
+                    self.game_state.winner()
+                } else {
+                    None
+                },
+                health: [
+                    self.game_state.players[0].health,
+                    self.game_state.players[1].health,
+                ],
+                num_punches: self.game_state.num_punches,
+                num_landed_punches: self.game_state.num_landed_punches,
+                episode_iters: self.episode_iters,
+            },
+        }
+    }
+
+    /// Raw `Observation` fields are world units, only their normalized form is bounded
+    fn observation_space(&self) -> BoxSpace {
+        BoxSpace {
+            shape: vec![OBSERVATION_LENGTH],
+            low: f32::NEG_INFINITY,
+            high: f32::INFINITY,
+        }
+    }
+
+    fn state(&self) -> &GameState {
+        &self.game_state
+    }
+}
+
+/// Truncates fights after `max_steps`, deciding the winner on remaining health.
+pub struct TimeLimit<E> {
+    pub env: E,
+    max_steps: usize,
+    elapsed: usize,
+}
+
+impl<E: BoxingEnv> TimeLimit<E> {
+    pub fn new(env: E, max_steps: usize) -> Self {
+        Self {
+            env,
+            max_steps,
+            elapsed: 0,
+        }
+    }
+}
+
+impl<E: BoxingEnv> BoxingEnv for TimeLimit<E> {
+    type Obs = E::Obs;
+
+    fn reset(&mut self, seed: Option<u64>) -> [E::Obs; 2] {
+        self.elapsed = 0;
+        self.env.reset(seed)
+    }
+
+    fn step(&mut self, actions: [usize; 2]) -> EnvStep<E::Obs> {
+        let mut step = self.env.step(actions);
+        self.elapsed += 1;
+        if self.elapsed >= self.max_steps && !step.terminated {
+            step.truncated = true;
+            step.info.winner = self.env.state().winner();
+        }
+        step
+    }
+
+    fn observation_space(&self) -> BoxSpace {
+        self.env.observation_space()
+    }
+
+    fn action_space(&self) -> DiscreteSpace {
+        self.env.action_space()
+    }
+
+    fn state(&self) -> &GameState {
+        self.env.state()
+    }
+}
+
+/// Multiplies both players' rewards by a constant.
+pub struct RewardScale<E> {
+    pub env: E,
+    scale: f32,
+}
+
+impl<E: BoxingEnv> RewardScale<E> {
+    pub fn new(env: E, scale: f32) -> Self {
+        Self { env, scale }
+    }
+}
+
+impl<E: BoxingEnv> BoxingEnv for RewardScale<E> {
+    type Obs = E::Obs;
+
+    fn reset(&mut self, seed: Option<u64>) -> [E::Obs; 2] {
+        self.env.reset(seed)
+    }
+
+    fn step(&mut self, actions: [usize; 2]) -> EnvStep<E::Obs> {
+        let mut step = self.env.step(actions);
+        step.rewards = step.rewards.map(|reward| reward * self.scale);
+        step
+    }
+
+    fn observation_space(&self) -> BoxSpace {
+        self.env.observation_space()
+    }
+
+    fn action_space(&self) -> DiscreteSpace {
+        self.env.action_space()
+    }
+
+    fn state(&self) -> &GameState {
+        self.env.state()
+    }
+}
+
+/// Concatenates the last `num_frames` normalized observations, oldest first.
+pub struct FrameStack<E> {
+    pub env: E,
+    num_frames: usize,
+    frames: [VecDeque<[f32; OBSERVATION_LENGTH]>; 2],
+}
+
+impl<E: BoxingEnv<Obs = Observation>> FrameStack<E> {
+    pub fn new(env: E, num_frames: usize) -> Self {
+        assert!(num_frames > 0, "FrameStack needs at least one frame");
+        Self {
+            env,
+            num_frames,
+            frames: [
+                VecDeque::with_capacity(num_frames),
+                VecDeque::with_capacity(num_frames),
+            ],
+        }
+    }
+
+    fn stacked(&self, player_i: usize) -> Vec<f32> {
+        self.frames[player_i].iter().flatten().copied().collect()
+    }
+}
+
+impl<E: BoxingEnv<Obs = Observation>> BoxingEnv for FrameStack<E> {
+    type Obs = Vec<f32>;
+
+    fn reset(&mut self, seed: Option<u64>) -> [Vec<f32>; 2] {
+        let observations = self.env.reset(seed);
+        for (frames, observation) in self.frames.iter_mut().zip(observations) {
+            // Pad with the first frame so the stack is always full
+            frames.clear();
+            frames.extend(std::iter::repeat_n(
+                observation.normalize(),
+                self.num_frames,
+            ));
+        }
+        [self.stacked(0), self.stacked(1)]
+    }
+
+    fn step(&mut self, actions: [usize; 2]) -> EnvStep<Vec<f32>> {
+        let EnvStep {
+            observations,
+            rewards,
+            terminated,
+            truncated,
+            info,
+        } = self.env.step(actions);
+        for (frames, observation) in self.frames.iter_mut().zip(observations) {
+            frames.pop_front();
+            frames.push_back(observation.normalize());
+        }
+
+        EnvStep {
+            observations: [self.stacked(0), self.stacked(1)],
+            rewards,
+            terminated,
+            truncated,
+            info,
+        }
+    }
+
+    fn observation_space(&self) -> BoxSpace {
+        BoxSpace::normalized(self.num_frames)
+    }
+
+    fn action_space(&self) -> DiscreteSpace {
+        self.env.action_space()
+    }
+
+    fn state(&self) -> &GameState {
+        self.env.state()
+    }
+}
@@ -2,15 +2,22 @@ use std::{cmp::Ordering, f32::consts::PI};
 
 use burn::{
     prelude::Backend,
-    tensor::{Float, Tensor, cast::ToElement},
+    tensor::{Float, Shape, Tensor, TensorData, cast::ToElement},
 };
 use parry2d::{math::Vector, na::Rotation2};
 
+pub mod agent;
+pub mod bots;
 pub mod control;
+pub mod env;
 pub mod model;
+pub mod policy;
+pub mod recurrent;
+pub mod vec_env;
 pub use control::*;
 pub mod utils;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 pub use utils::*;
 
 use crate::model::DQN;
@@ -61,6 +68,7 @@ impl Fist {
     }
 }
 
+#[derive(Clone)]
 pub struct Player {
     pub position: Vector<f32>,
     pub rotation: f32, // Radians
@@ -210,7 +218,7 @@ impl Player {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Observation {
     pub health: f32,
     pub op_health: f32,
@@ -282,7 +290,7 @@ impl Observation {
     }
 }
 
-pub fn select_action<B: Backend, R: Rng>(
+pub fn select_action<B: Backend, R: Rng + ?Sized>(
     observation: Observation,
     model: &DQN<B>,
     epsilon: f32,
@@ -292,21 +300,66 @@ pub fn select_action<B: Backend, R: Rng>(
 ) -> usize {
     let random: f32 = rng.random();
     if random < epsilon {
-        return rng.random_range(0..n_actions);
+        rng.random_range(0..n_actions)
     } else {
         let observation = observation.normalize();
         let obs_tensor = Tensor::<B, 1, Float>::from_floats(observation, device).unsqueeze_dim(0);
-        let all_qvalues: Tensor<B, 1, Float> = model.forward(obs_tensor).squeeze(0);
+        let all_qvalues: Tensor<B, 1, Float> = model.action_values(obs_tensor).squeeze(0);
         all_qvalues.clone().argmax(0).into_scalar().to_usize()
     }
 }
 
+/// Normalizes and stacks observations into a `[N, OBSERVATION_LENGTH]` tensor
+pub fn observations_to_tensor<B: Backend>(
+    observations: &[Observation],
+    device: &B::Device,
+) -> Tensor<B, 2, Float> {
+    let flattened: Vec<f32> = observations
+        .iter()
+        .flat_map(|observation| observation.normalize())
+        .collect();
+    let data = TensorData::new(
+        flattened,
+        Shape::new([observations.len(), OBSERVATION_LENGTH]),
+    );
+    Tensor::from_floats(data, device)
+}
+
+/// Epsilon-greedy action selection for a batch of observations in a single forward pass
+pub fn select_actions<B: Backend, R: Rng + ?Sized>(
+    observations: &[Observation],
+    model: &DQN<B>,
+    epsilon: f32,
+    n_actions: usize,
+    rng: &mut R,
+    device: &B::Device,
+) -> Vec<usize> {
+    if observations.is_empty() {
+        return vec![];
+    }
+    let obs_tensor = observations_to_tensor::<B>(observations, device);
+    let greedy_actions = model.action_values(obs_tensor).argmax(1).into_data();
+
+    greedy_actions
+        .iter::<i64>()
+        .map(|greedy_action| {
+            let random: f32 = rng.random();
+            if random < epsilon {
+                rng.random_range(0..n_actions)
+            } else {
+                greedy_action as usize
+            }
+        })
+        .collect()
+}
+
 pub struct StepResult {
     pub observations: [Observation; 2],
     pub rewards: [f32; 2],
     pub is_done: bool,
 }
 
+#[derive(Clone)]
 pub struct GameState {
     pub players: [Player; 2],
 
@@ -315,6 +368,12 @@ pub struct GameState {
     pub num_landed_punches: [usize; 2],
 }
 
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl GameState {
     pub const RING_SIZE: Vector<f32> = Vector::new(400.0, 400.0);
     pub const MIN_PLAYER_DISTANCE: f32 = 88.0;
@@ -576,16 +635,9 @@ impl GameState {
         let player_0_observation = self.get_observation(0);
         let player_1_observation = self.get_observation(1);
 
-        let is_done = self.players[0].health <= 0.0 || self.players[1].health <= 0.0;
-
-        let winner = match self.players[0].health.partial_cmp(&self.players[1].health) {
-            Some(Ordering::Less) => Some(1),
-            Some(Ordering::Equal) => None,
-            Some(Ordering::Greater) => Some(0),
-            None => None,
-        };
+        let is_done = self.is_done();
 
-        if is_done && let Some(win_i) = winner {
+        if is_done && let Some(win_i) = self.winner() {
             // NOTE: winner reward
             rewards[win_i] += 50.0;
         }
@@ -597,6 +649,20 @@ impl GameState {
         }
     }
 
+    pub fn is_done(&self) -> bool {
+        self.players[0].health <= 0.0 || self.players[1].health <= 0.0
+    }
+
+    /// The player with more health left, or `None` on a tie.
+    pub fn winner(&self) -> Option<usize> {
+        match self.players[0].health.partial_cmp(&self.players[1].health) {
+            Some(Ordering::Less) => Some(1),
+            Some(Ordering::Equal) => None,
+            Some(Ordering::Greater) => Some(0),
+            None => None,
+        }
+    }
+
     pub fn get_observation(&self, player_i: usize) -> Observation {
         let player = &self.players[player_i];
         let opponent = &self.players[1 - player_i];
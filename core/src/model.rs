@@ -1,43 +1,415 @@
-use burn::module::Module;
-use burn::nn::{Linear, LinearConfig, Relu};
+use burn::module::{ConstantRecord, Ignored, Module};
+use burn::nn::{
+    Dropout, DropoutConfig, LayerNorm, LayerNormConfig, Linear, LinearConfig, LinearRecord,
+};
 use burn::prelude::*;
+use burn::record::{Record, Recorder, RecorderError};
+use burn::tensor::activation;
+
+use crate::{
+    OBSERVATION_LENGTH, OUTPUT_SIZE,
+    agent::{Agent, DqnAgent, PolicyAgent, RecurrentAgent},
+    policy::{ACTOR_CRITIC_KIND, ActorCritic, load_actor_critic, save_actor_critic},
+    recurrent::{RECURRENT_DQN_KIND, RecurrentDQN, load_recurrent_dqn, save_recurrent_dqn},
+};
 
 #[derive(Module, Debug)]
 pub struct DQN<B: Backend> {
-    fc0: Linear<B>,
-    fc1: Linear<B>,
-    fc2: Linear<B>,
+    hidden: Vec<HiddenLayer<B>>,
+    /// Q values, or the advantage stream of a dueling network. One output per action and
+    /// atom in distributional networks.
     out: Linear<B>,
-    activation: Relu,
+    /// State value stream, only present in dueling networks
+    value: Option<Linear<B>>,
+    config: Ignored<DQNConfig>,
+}
+
+/// Linear layer followed by the optional layer norm, the activation and dropout
+#[derive(Module, Debug)]
+struct HiddenLayer<B: Backend> {
+    linear: Linear<B>,
+    norm: Option<LayerNorm<B>>,
+    dropout: Dropout,
+}
+
+#[derive(Config, Debug, Copy, PartialEq)]
+pub enum ActivationConfig {
+    Relu,
+    Gelu,
+    LeakyRelu,
+}
+
+/// How a distributional network describes the returns of each action
+#[derive(Config, Debug, Copy, PartialEq)]
+pub enum DistributionalHead {
+    /// C51: probabilities of `atoms` returns evenly spaced from `v_min` to `v_max`
+    Categorical {
+        atoms: usize,
+        v_min: f32,
+        v_max: f32,
+    },
+    /// QR-DQN: `quantiles` evenly spaced quantiles of the returns
+    Quantile { quantiles: usize },
+}
+
+impl DistributionalHead {
+    pub fn atoms(&self) -> usize {
+        match *self {
+            Self::Categorical { atoms, .. } => atoms,
+            Self::Quantile { quantiles } => quantiles,
+        }
+    }
+
+    /// The returns a categorical head's atoms stand for, `[atoms]`
+    pub fn support<B: Backend>(&self, device: &B::Device) -> Option<Tensor<B, 1>> {
+        let Self::Categorical {
+            atoms,
+            v_min,
+            v_max,
+        } = *self
+        else {
+            return None;
+        };
+        let spacing = (v_max - v_min) / (atoms - 1).max(1) as f32;
+        let indices = Tensor::<B, 1, Int>::arange(0..atoms as i64, device).float();
+        Some(indices * spacing + v_min)
+    }
+}
+
+/// Which part of the return distributions actions are picked by. Only distributional
+/// networks have anything but the mean to go on, plain ones always act neutrally.
+#[derive(Config, Debug, Copy, PartialEq)]
+pub enum Risk {
+    /// The expected return
+    Neutral,
+    /// The mean of the worst `alpha` of returns (CVaR), avoiding actions that can go badly
+    Cautious { alpha: f32 },
+    /// The mean of the best `alpha` of returns, chasing actions that can go well
+    Reckless { alpha: f32 },
+}
+
+impl ActivationConfig {
+    pub fn forward<B: Backend, const D: usize>(&self, x: Tensor<B, D>) -> Tensor<B, D> {
+        match self {
+            Self::Relu => activation::relu(x),
+            Self::Gelu => activation::gelu(x),
+            Self::LeakyRelu => activation::leaky_relu(x, 0.01),
+        }
+    }
 }
 
 #[derive(Config, Debug)]
 pub struct DQNConfig {
     input_size: usize,
     output_size: usize,
+    /// Widths of the hidden layers, from the input side
+    #[config(default = "vec![256, 128, 64]")]
+    pub hidden_sizes: Vec<usize>,
+    #[config(default = "ActivationConfig::Relu")]
+    pub activation: ActivationConfig,
+    /// Normalize each hidden layer before its activation
+    #[config(default = false)]
+    pub layer_norm: bool,
+    /// Only applied on autodiff backends, so it never affects inference in the arena or the web.
+    /// Trainers act and compute targets with `valid()` copies, leaving it to the loss.
+    #[config(default = 0.0)]
+    pub dropout: f64,
+    /// Split the head into a state value and per-action advantages
+    #[config(default = false)]
+    pub dueling: bool,
+    /// Predict a distribution of returns per action rather than just its mean
+    #[config(default = "None")]
+    pub distributional: Option<DistributionalHead>,
+    /// How a distributional network picks its actions, training targets stay risk neutral
+    #[config(default = "Risk::Neutral")]
+    pub risk: Risk,
 }
 
 impl DQNConfig {
     pub fn init<B: Backend>(&self, device: &B::Device) -> DQN<B> {
+        let mut input_size = self.input_size;
+        let mut hidden = Vec::with_capacity(self.hidden_sizes.len());
+        for &size in &self.hidden_sizes {
+            hidden.push(HiddenLayer {
+                linear: LinearConfig::new(input_size, size).init(device),
+                norm: self
+                    .layer_norm
+                    .then(|| LayerNormConfig::new(size).init(device)),
+                dropout: DropoutConfig::new(self.dropout).init(),
+            });
+            input_size = size;
+        }
+
+        let atoms = self.atoms();
         DQN {
-            fc0: LinearConfig::new(self.input_size, 256).init(device),
-            fc1: LinearConfig::new(256, 128).init(device),
-            fc2: LinearConfig::new(128, 64).init(device),
-            out: LinearConfig::new(64, self.output_size).init(device),
-            activation: Relu::new(),
+            hidden,
+            out: LinearConfig::new(input_size, self.output_size * atoms).init(device),
+            value: self
+                .dueling
+                .then(|| LinearConfig::new(input_size, atoms).init(device)),
+            config: Ignored(self.clone()),
         }
     }
+
+    /// Outputs per action, 1 for plain networks
+    pub fn atoms(&self) -> usize {
+        self.distributional.map_or(1, |head| head.atoms())
+    }
 }
 
 impl<B: Backend> DQN<B> {
+    /// Expected return of each action, `[batch, output_size]`
     pub fn forward(&self, obs_tensor: Tensor<B, 2>) -> Tensor<B, 2> {
-        let x = obs_tensor.clone();
-        let x = self.fc0.forward(x);
-        let x = self.activation.forward(x);
-        let x = self.fc1.forward(x);
-        let x = self.activation.forward(x);
-        let x = self.fc2.forward(x);
-        let x = self.activation.forward(x);
-        self.out.forward(x)
+        let outputs = self.head(obs_tensor);
+        match self.config.distributional {
+            None => outputs.squeeze(2),
+            Some(head @ DistributionalHead::Categorical { .. }) => {
+                let support = head
+                    .support::<B>(&outputs.device())
+                    .expect("Categorical heads should have a support");
+                let probabilities = activation::softmax(outputs, 2);
+                (probabilities * support.unsqueeze::<3>())
+                    .sum_dim(2)
+                    .squeeze(2)
+            }
+            Some(DistributionalHead::Quantile { .. }) => outputs.mean_dim(2).squeeze(2),
+        }
+    }
+
+    /// Return distribution of each action, `[batch, output_size, atoms]`: probabilities of
+    /// the support's returns for categorical heads, quantiles for quantile heads and the
+    /// Q values themselves for plain networks
+    pub fn distribution(&self, obs_tensor: Tensor<B, 2>) -> Tensor<B, 3> {
+        let outputs = self.head(obs_tensor);
+        match self.config.distributional {
+            Some(DistributionalHead::Categorical { .. }) => activation::softmax(outputs, 2),
+            _ => outputs,
+        }
+    }
+
+    /// What actions are picked by, `[batch, output_size]`: `forward` scored by the config's
+    /// `risk`
+    pub fn action_values(&self, obs_tensor: Tensor<B, 2>) -> Tensor<B, 2> {
+        let (Some(head), Risk::Cautious { alpha } | Risk::Reckless { alpha }) =
+            (self.config.distributional, self.config.risk)
+        else {
+            return self.forward(obs_tensor);
+        };
+        let distribution = self.distribution(obs_tensor);
+        let [batch_size, num_actions, atoms] = distribution.dims();
+        let device = distribution.device();
+        // Returns in ascending order with the probability of each
+        let (returns, probabilities) = match head.support::<B>(&device) {
+            Some(support) => (
+                support
+                    .unsqueeze::<3>()
+                    .repeat_dim(0, batch_size)
+                    .repeat_dim(1, num_actions),
+                distribution,
+            ),
+            None => (
+                distribution.sort(2),
+                Tensor::ones([batch_size, num_actions, atoms], &device) / atoms as f32,
+            ),
+        };
+
+        // Probability of each return and those below it
+        let cumulative = probabilities
+            .clone()
+            .reshape([batch_size * num_actions, atoms])
+            .matmul(Tensor::ones([atoms, atoms], &device).triu(0))
+            .reshape([batch_size, num_actions, atoms]);
+        // Probability of the returns ahead of each one on the way into the tail, which
+        // takes from each return whatever of `alpha` those leave
+        let outside = match self.config.risk {
+            Risk::Reckless { .. } => cumulative.neg() + 1.0,
+            _ => cumulative - probabilities.clone(),
+        };
+        let alpha = alpha.clamp(f32::EPSILON, 1.0);
+        let weights = (outside.neg() + alpha)
+            .clamp_min(0.0)
+            .min_pair(probabilities);
+        let tail = (weights.clone() * returns).sum_dim(2);
+        (tail / weights.sum_dim(2).clamp_min(f32::EPSILON)).squeeze(2)
+    }
+
+    pub fn config(&self) -> &DQNConfig {
+        &self.config.0
+    }
+
+    /// The same network picking its actions by `risk`
+    pub fn with_risk(mut self, risk: Risk) -> Self {
+        self.config.0.risk = risk;
+        self
+    }
+
+    /// Raw outputs, `[batch, output_size, atoms]`
+    fn head(&self, obs_tensor: Tensor<B, 2>) -> Tensor<B, 3> {
+        let mut x = obs_tensor;
+        for layer in &self.hidden {
+            x = layer.linear.forward(x);
+            if let Some(norm) = &layer.norm {
+                x = norm.forward(x);
+            }
+            x = self.config.activation.forward(x);
+            x = layer.dropout.forward(x);
+        }
+
+        let [batch_size, _] = x.dims();
+        let (num_actions, atoms) = (self.config.output_size, self.config.atoms());
+        let out = self
+            .out
+            .forward(x.clone())
+            .reshape([batch_size, num_actions, atoms]);
+        match &self.value {
+            Some(value) => {
+                // Q = V + A - mean(A), so V alone carries the value of the state
+                let value = value.forward(x).reshape([batch_size, 1, atoms]);
+                value + out.clone() - out.mean_dim(1)
+            }
+            None => out,
+        }
+    }
+}
+
+/// A saved model: the weights along with the config needed to rebuild the network
+#[derive(Record)]
+pub struct DQNFileRecord<B: Backend> {
+    config: String,
+    model: DQNRecord<B>,
+}
+
+/// Just the leading field of `DQNFileRecord`
+#[derive(Record)]
+struct DQNFileHeader {
+    config: String,
+}
+
+/// How models were saved before the config went along with them
+#[derive(Record)]
+struct LegacyDQNRecord<B: Backend> {
+    fc0: LinearRecord<B>,
+    fc1: LinearRecord<B>,
+    fc2: LinearRecord<B>,
+    out: LinearRecord<B>,
+    activation: ConstantRecord,
+}
+
+/// Saves a model so `load_dqn` can rebuild it without knowing its architecture
+pub fn save_dqn<B: Backend, R: Recorder<B>>(
+    model: DQN<B>,
+    recorder: &R,
+    args: R::RecordArgs,
+) -> Result<R::RecordOutput, RecorderError> {
+    let record = DQNFileRecord {
+        config: model.config().to_string(),
+        model: model.into_record(),
+    };
+    recorder.record(record, args)
+}
+
+/// Loads anything written by `save_dqn`, as well as the plain records older models were
+/// saved as, which all share the default ReLU 256-128-64 architecture
+pub fn load_dqn<B: Backend, R: Recorder<B>>(
+    recorder: &R,
+    args: R::LoadArgs,
+    device: &B::Device,
+) -> Result<DQN<B>, RecorderError>
+where
+    R::LoadArgs: Clone,
+{
+    // Binary recorders aren't self-describing and panic on a layout mismatch, so only the
+    // leading string is read first. Old records start with a parameter id there instead.
+    let config = recorder
+        .load::<DQNFileHeader>(args.clone(), device)
+        .ok()
+        .and_then(|header| DQNConfig::load_binary(header.config.as_bytes()).ok());
+    if let Some(config) = config {
+        let record: DQNFileRecord<B> = recorder.load(args, device)?;
+        return Ok(config.init(device).load_record(record.model));
+    }
+
+    let legacy: LegacyDQNRecord<B> = recorder.load(args, device)?;
+    let mut model = DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE).init(device);
+    for (layer, record) in model
+        .hidden
+        .iter_mut()
+        .zip([legacy.fc0, legacy.fc1, legacy.fc2])
+    {
+        layer.linear = layer.linear.clone().load_record(record);
+    }
+    model.out = model.out.load_record(legacy.out);
+    Ok(model)
+}
+
+/// Just the leading field of a saved actor-critic or recurrent DQN
+#[derive(Record)]
+struct KindHeader {
+    kind: String,
+}
+
+/// Any model a fighter can be loaded from
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum SavedModel<B: Backend> {
+    Dqn(DQN<B>),
+    ActorCritic(ActorCritic<B>),
+    Recurrent(RecurrentDQN<B>),
+}
+
+impl<B: Backend> SavedModel<B> {
+    pub fn save<R: Recorder<B>>(
+        self,
+        recorder: &R,
+        args: R::RecordArgs,
+    ) -> Result<R::RecordOutput, RecorderError> {
+        match self {
+            Self::Dqn(model) => save_dqn(model, recorder, args),
+            Self::ActorCritic(model) => save_actor_critic(model, recorder, args),
+            Self::Recurrent(model) => save_recurrent_dqn(model, recorder, args),
+        }
+    }
+
+    /// DQNs explore with `epsilon`, actor-critics sample from their policy instead. Recurrent
+    /// DQNs carry their state from one `act` to the next.
+    pub fn into_agent(
+        self,
+        name: impl Into<String>,
+        epsilon: f32,
+        device: &B::Device,
+    ) -> Box<dyn Agent>
+    where
+        B: 'static,
+    {
+        match self {
+            Self::Dqn(model) => Box::new(DqnAgent::new(name, model, epsilon, device)),
+            Self::ActorCritic(model) => Box::new(PolicyAgent::new(name, model, false, device)),
+            Self::Recurrent(model) => Box::new(RecurrentAgent::new(name, model, epsilon, device)),
+        }
+    }
+}
+
+/// Loads whichever kind of model was saved
+pub fn load_saved_model<B: Backend, R: Recorder<B>>(
+    recorder: &R,
+    args: R::LoadArgs,
+    device: &B::Device,
+) -> Result<SavedModel<B>, RecorderError>
+where
+    R::LoadArgs: Clone,
+{
+    // Like `load_dqn`, only the leading string is read before the layout is known
+    let kind = recorder
+        .load::<KindHeader>(args.clone(), device)
+        .map(|header| header.kind)
+        .unwrap_or_default();
+    match kind.as_str() {
+        ACTOR_CRITIC_KIND => Ok(SavedModel::ActorCritic(load_actor_critic(
+            recorder, args, device,
+        )?)),
+        RECURRENT_DQN_KIND => Ok(SavedModel::Recurrent(load_recurrent_dqn(
+            recorder, args, device,
+        )?)),
+        _ => Ok(SavedModel::Dqn(load_dqn(recorder, args, device)?)),
     }
 }
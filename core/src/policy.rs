@@ -0,0 +1,171 @@
+use burn::module::{Ignored, Module};
+use burn::nn::{Initializer, Linear, LinearConfig};
+use burn::prelude::*;
+use burn::record::{Record, Recorder, RecorderError};
+use burn::tensor::activation;
+use rand::Rng;
+
+use crate::{Observation, model::ActivationConfig, observations_to_tensor};
+
+/// Leads every saved actor-critic, so loaders can tell it apart from a DQN
+pub const ACTOR_CRITIC_KIND: &str = "actor_critic";
+
+/// Policy and value function over a shared torso, as trained by PPO
+#[derive(Module, Debug)]
+pub struct ActorCritic<B: Backend> {
+    torso: Vec<Linear<B>>,
+    /// Logits over the actions
+    policy: Linear<B>,
+    value: Linear<B>,
+    config: Ignored<ActorCriticConfig>,
+}
+
+#[derive(Config, Debug)]
+pub struct ActorCriticConfig {
+    input_size: usize,
+    output_size: usize,
+    /// Widths of the shared hidden layers, from the input side
+    #[config(default = "vec![128, 128]")]
+    pub hidden_sizes: Vec<usize>,
+    #[config(default = "ActivationConfig::Relu")]
+    pub activation: ActivationConfig,
+}
+
+impl ActorCriticConfig {
+    pub fn init<B: Backend>(&self, device: &B::Device) -> ActorCritic<B> {
+        let mut input_size = self.input_size;
+        let mut torso = Vec::with_capacity(self.hidden_sizes.len());
+        for &size in &self.hidden_sizes {
+            torso.push(LinearConfig::new(input_size, size).init(device));
+            input_size = size;
+        }
+
+        ActorCritic {
+            torso,
+            // Small logits start the policy close to uniform
+            policy: LinearConfig::new(input_size, self.output_size)
+                .with_initializer(Initializer::Normal {
+                    mean: 0.0,
+                    std: 0.01,
+                })
+                .init(device),
+            value: LinearConfig::new(input_size, 1).init(device),
+            config: Ignored(self.clone()),
+        }
+    }
+}
+
+impl<B: Backend> ActorCritic<B> {
+    /// Action logits `[N, output_size]` and state values `[N]`
+    pub fn forward(&self, obs_tensor: Tensor<B, 2>) -> (Tensor<B, 2>, Tensor<B, 1>) {
+        let mut x = obs_tensor;
+        for layer in &self.torso {
+            x = self.config.activation.forward(layer.forward(x));
+        }
+        let logits = self.policy.forward(x.clone());
+        let values = self.value.forward(x).squeeze(1);
+        (logits, values)
+    }
+
+    /// Action probabilities and state value of each observation
+    pub fn evaluate(
+        &self,
+        observations: &[Observation],
+        device: &B::Device,
+    ) -> (Vec<Vec<f32>>, Vec<f32>) {
+        let (logits, values) = self.forward(observations_to_tensor::<B>(observations, device));
+        let probabilities = activation::softmax(logits, 1)
+            .into_data()
+            .to_vec::<f32>()
+            .expect("Probabilities should be floats");
+        let values = values
+            .into_data()
+            .to_vec::<f32>()
+            .expect("Values should be floats");
+        let num_actions = self.config.output_size;
+        (
+            probabilities
+                .chunks(num_actions)
+                .map(<[f32]>::to_vec)
+                .collect(),
+            values,
+        )
+    }
+
+    pub fn config(&self) -> &ActorCriticConfig {
+        &self.config.0
+    }
+}
+
+/// Draws an index with the given probabilities
+pub fn sample_from<R: Rng + ?Sized>(probabilities: &[f32], rng: &mut R) -> usize {
+    let mut remaining: f32 = rng.random::<f32>() * probabilities.iter().sum::<f32>();
+    for (action, probability) in probabilities.iter().enumerate() {
+        remaining -= probability;
+        if remaining < 0.0 {
+            return action;
+        }
+    }
+    // Rounding can leave a sliver past the last action
+    probabilities.len() - 1
+}
+
+/// Samples an action from the policy, or takes the likeliest one when `greedy`
+pub fn policy_action<B: Backend, R: Rng + ?Sized>(
+    observation: Observation,
+    model: &ActorCritic<B>,
+    greedy: bool,
+    rng: &mut R,
+    device: &B::Device,
+) -> usize {
+    let (probabilities, _) = model.evaluate(&[observation], device);
+    let probabilities = &probabilities[0];
+    if greedy {
+        probabilities
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map_or(0, |(action, _)| action)
+    } else {
+        sample_from(probabilities, rng)
+    }
+}
+
+/// A saved actor-critic: the weights along with the config needed to rebuild the network
+#[derive(Record)]
+struct ActorCriticFileRecord<B: Backend> {
+    kind: String,
+    actor_critic_config: String,
+    model: ActorCriticRecord<B>,
+}
+
+/// Saves a model so `load_actor_critic` and `load_saved_model` can rebuild it
+pub fn save_actor_critic<B: Backend, R: Recorder<B>>(
+    model: ActorCritic<B>,
+    recorder: &R,
+    args: R::RecordArgs,
+) -> Result<R::RecordOutput, RecorderError> {
+    let record = ActorCriticFileRecord {
+        kind: ACTOR_CRITIC_KIND.to_string(),
+        actor_critic_config: model.config().to_string(),
+        model: model.into_record(),
+    };
+    recorder.record(record, args)
+}
+
+pub fn load_actor_critic<B: Backend, R: Recorder<B>>(
+    recorder: &R,
+    args: R::LoadArgs,
+    device: &B::Device,
+) -> Result<ActorCritic<B>, RecorderError> {
+    let record: ActorCriticFileRecord<B> = recorder.load(args, device)?;
+    if record.kind != ACTOR_CRITIC_KIND {
+        return Err(RecorderError::Unknown(format!(
+            "Expected an actor-critic, found {}",
+            record.kind
+        )));
+    }
+    let config = ActorCriticConfig::load_binary(record.actor_critic_config.as_bytes())
+        .map_err(|error| RecorderError::Unknown(format!("Bad actor-critic config: {error}")))?;
+    Ok(config.init(device).load_record(record.model))
+}
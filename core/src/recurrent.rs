@@ -0,0 +1,270 @@
+use burn::module::{Ignored, Module};
+use burn::nn::gru::{Gru, GruConfig};
+use burn::nn::{Linear, LinearConfig, Lstm, LstmConfig, LstmState};
+use burn::prelude::*;
+use burn::record::{Record, Recorder, RecorderError};
+use rand::Rng;
+
+use crate::{OBSERVATION_LENGTH, Observation, model::ActivationConfig};
+
+/// Leads every saved recurrent DQN, so loaders can tell it apart from the other models
+pub const RECURRENT_DQN_KIND: &str = "recurrent_dqn";
+
+#[derive(Config, Debug, Copy, PartialEq)]
+pub enum CellKind {
+    Gru,
+    Lstm,
+}
+
+/// Q network with a recurrent layer between its encoder and head, so its estimates can
+/// depend on what happened earlier in the fight
+#[derive(Module, Debug)]
+pub struct RecurrentDQN<B: Backend> {
+    encoder: Linear<B>,
+    /// Only present when the cell is a GRU
+    gru: Option<Gru<B>>,
+    /// Only present when the cell is an LSTM
+    lstm: Option<Lstm<B>>,
+    out: Linear<B>,
+    config: Ignored<RecurrentDQNConfig>,
+}
+
+#[derive(Config, Debug)]
+pub struct RecurrentDQNConfig {
+    input_size: usize,
+    output_size: usize,
+    /// Width of the layer between the observation and the recurrent cell
+    #[config(default = 128)]
+    pub encoder_size: usize,
+    /// Width of the recurrent cell's state
+    #[config(default = 128)]
+    pub hidden_size: usize,
+    #[config(default = "CellKind::Gru")]
+    pub cell: CellKind,
+    #[config(default = "ActivationConfig::Relu")]
+    pub activation: ActivationConfig,
+}
+
+impl RecurrentDQNConfig {
+    pub fn init<B: Backend>(&self, device: &B::Device) -> RecurrentDQN<B> {
+        RecurrentDQN {
+            encoder: LinearConfig::new(self.input_size, self.encoder_size).init(device),
+            gru: (self.cell == CellKind::Gru)
+                .then(|| GruConfig::new(self.encoder_size, self.hidden_size, true).init(device)),
+            lstm: (self.cell == CellKind::Lstm)
+                .then(|| LstmConfig::new(self.encoder_size, self.hidden_size, true).init(device)),
+            out: LinearConfig::new(self.hidden_size, self.output_size).init(device),
+            config: Ignored(self.clone()),
+        }
+    }
+
+    /// Floats in one row of a flattened `RecurrentState`
+    pub fn state_size(&self) -> usize {
+        match self.cell {
+            CellKind::Gru => self.hidden_size,
+            CellKind::Lstm => 2 * self.hidden_size,
+        }
+    }
+}
+
+/// What a recurrent network remembers between steps, one row per sequence
+#[derive(Clone, Debug)]
+pub struct RecurrentState<B: Backend> {
+    pub hidden: Tensor<B, 2>,
+    /// The LSTM's cell state, `None` for a GRU
+    pub cell: Option<Tensor<B, 2>>,
+}
+
+impl<B: Backend> RecurrentState<B> {
+    pub fn detach(self) -> Self {
+        Self {
+            hidden: self.hidden.detach(),
+            cell: self.cell.map(Tensor::detach),
+        }
+    }
+
+    /// Rows of `[hidden, cell]`, flattened, for storing next to replayed steps
+    pub fn to_vec(&self) -> Vec<f32> {
+        let state = match &self.cell {
+            Some(cell) => Tensor::cat(vec![self.hidden.clone(), cell.clone()], 1),
+            None => self.hidden.clone(),
+        };
+        state
+            .into_data()
+            .to_vec::<f32>()
+            .expect("States should be floats")
+    }
+}
+
+impl<B: Backend> RecurrentDQN<B> {
+    /// Q values `[N, T, output_size]` of `N` sequences of `T` normalized observations
+    /// `[N, T, input_size]`, and the state after their last step. Sequences start from
+    /// `state`, or from zeros when it's `None`.
+    pub fn forward(
+        &self,
+        obs_tensor: Tensor<B, 3>,
+        state: Option<RecurrentState<B>>,
+    ) -> (Tensor<B, 3>, RecurrentState<B>) {
+        let [batch_size, seq_length, input_size] = obs_tensor.dims();
+        let config = &self.config.0;
+        let x = self
+            .encoder
+            .forward(obs_tensor.reshape([batch_size * seq_length, input_size]));
+        let x = config
+            .activation
+            .forward(x)
+            .reshape([batch_size, seq_length, config.encoder_size]);
+
+        let (hidden_states, state) = match (&self.gru, &self.lstm) {
+            (Some(gru), _) => {
+                let hidden_states = gru.forward(x, state.map(|state| state.hidden));
+                let last = hidden_states
+                    .clone()
+                    .slice([0..batch_size, seq_length - 1..seq_length])
+                    .squeeze(1);
+                let state = RecurrentState {
+                    hidden: last,
+                    cell: None,
+                };
+                (hidden_states, state)
+            }
+            (_, Some(lstm)) => {
+                let state = state.map(|state| {
+                    let cell = state
+                        .cell
+                        .expect("An LSTM's state should carry its cell state");
+                    LstmState::new(cell, state.hidden)
+                });
+                let (hidden_states, state) = lstm.forward(x, state);
+                let state = RecurrentState {
+                    hidden: state.hidden,
+                    cell: Some(state.cell),
+                };
+                (hidden_states, state)
+            }
+            (None, None) => unreachable!("A recurrent DQN has a GRU or an LSTM"),
+        };
+
+        let q_values = self
+            .out
+            .forward(hidden_states.reshape([batch_size * seq_length, config.hidden_size]))
+            .reshape([batch_size, seq_length, config.output_size]);
+        (q_values, state)
+    }
+
+    /// Rebuilds `batch_size` rows flattened by `RecurrentState::to_vec`
+    pub fn state_from_vec(
+        &self,
+        data: Vec<f32>,
+        batch_size: usize,
+        device: &B::Device,
+    ) -> RecurrentState<B> {
+        let config = &self.config.0;
+        let state = Tensor::<B, 2>::from_floats(
+            TensorData::new(data, Shape::new([batch_size, config.state_size()])),
+            device,
+        );
+        match config.cell {
+            CellKind::Gru => RecurrentState {
+                hidden: state,
+                cell: None,
+            },
+            CellKind::Lstm => {
+                let [hidden, cell] = state
+                    .chunk(2, 1)
+                    .try_into()
+                    .expect("An LSTM state should have a hidden and a cell half");
+                RecurrentState {
+                    hidden,
+                    cell: Some(cell),
+                }
+            }
+        }
+    }
+
+    pub fn config(&self) -> &RecurrentDQNConfig {
+        &self.config.0
+    }
+}
+
+/// Epsilon-greedy actions for a batch of fighters, one step each. The network runs even
+/// when every action is random, so `state` always moves on to include these observations.
+pub fn select_recurrent_actions<B: Backend, R: Rng + ?Sized>(
+    observations: &[Observation],
+    model: &RecurrentDQN<B>,
+    state: &mut Option<RecurrentState<B>>,
+    epsilon: f32,
+    rng: &mut R,
+    device: &B::Device,
+) -> Vec<usize> {
+    let flattened: Vec<f32> = observations
+        .iter()
+        .flat_map(|observation| observation.normalize())
+        .collect();
+    let obs_tensor = Tensor::<B, 3>::from_floats(
+        TensorData::new(
+            flattened,
+            Shape::new([observations.len(), 1, OBSERVATION_LENGTH]),
+        ),
+        device,
+    );
+    let (q_values, next_state) = model.forward(obs_tensor, state.take());
+    *state = Some(next_state);
+
+    let num_actions = model.config().output_size;
+    let greedy_actions = q_values
+        .squeeze::<2>(1)
+        .argmax(1)
+        .into_data()
+        .iter::<i64>()
+        .collect::<Vec<_>>();
+    greedy_actions
+        .into_iter()
+        .map(|greedy_action| {
+            if rng.random::<f32>() < epsilon {
+                rng.random_range(0..num_actions)
+            } else {
+                greedy_action as usize
+            }
+        })
+        .collect()
+}
+
+/// A saved recurrent DQN: the weights along with the config needed to rebuild the network
+#[derive(Record)]
+struct RecurrentDQNFileRecord<B: Backend> {
+    kind: String,
+    recurrent_config: String,
+    model: RecurrentDQNRecord<B>,
+}
+
+/// Saves a model so `load_recurrent_dqn` and `load_saved_model` can rebuild it
+pub fn save_recurrent_dqn<B: Backend, R: Recorder<B>>(
+    model: RecurrentDQN<B>,
+    recorder: &R,
+    args: R::RecordArgs,
+) -> Result<R::RecordOutput, RecorderError> {
+    let record = RecurrentDQNFileRecord {
+        kind: RECURRENT_DQN_KIND.to_string(),
+        recurrent_config: model.config().to_string(),
+        model: model.into_record(),
+    };
+    recorder.record(record, args)
+}
+
+pub fn load_recurrent_dqn<B: Backend, R: Recorder<B>>(
+    recorder: &R,
+    args: R::LoadArgs,
+    device: &B::Device,
+) -> Result<RecurrentDQN<B>, RecorderError> {
+    let record: RecurrentDQNFileRecord<B> = recorder.load(args, device)?;
+    if record.kind != RECURRENT_DQN_KIND {
+        return Err(RecorderError::Unknown(format!(
+            "Expected a recurrent DQN, found {}",
+            record.kind
+        )));
+    }
+    let config = RecurrentDQNConfig::load_binary(record.recurrent_config.as_bytes())
+        .map_err(|error| RecorderError::Unknown(format!("Bad recurrent DQN config: {error}")))?;
+    Ok(config.init(device).load_record(record.model))
+}
@@ -0,0 +1,131 @@
+use burn::{
+    prelude::Backend,
+    tensor::{Float, Tensor},
+};
+
+use crate::{
+    Observation,
+    env::{BoxingEnv, EnvStep},
+    observations_to_tensor,
+};
+
+/// Steps `N` independent environments in lockstep.
+///
+/// Finished environments are reset automatically: the returned `EnvStep` still carries the
+/// final observations of the fight, while `observations()` already holds the first
+/// observations of the next one.
+pub struct VecBoxingEnv<E> {
+    envs: Vec<E>,
+    observations: Vec<[Observation; 2]>,
+    num_threads: usize,
+}
+
+impl<E: BoxingEnv<Obs = Observation> + Send> VecBoxingEnv<E> {
+    pub fn new(envs: Vec<E>) -> Self {
+        assert!(!envs.is_empty(), "VecBoxingEnv needs at least one env");
+        let mut envs = envs;
+        let observations = envs.iter_mut().map(|env| env.reset(None)).collect();
+        Self {
+            envs,
+            observations,
+            num_threads: 1,
+        }
+    }
+
+    /// Splits the physics across `num_threads` scoped threads on every step
+    pub fn with_num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    pub fn num_envs(&self) -> usize {
+        self.envs.len()
+    }
+
+    pub fn envs(&self) -> &[E] {
+        &self.envs
+    }
+
+    /// Current observations of both players in every env
+    pub fn observations(&self) -> &[[Observation; 2]] {
+        &self.observations
+    }
+
+    pub fn player_observations(&self, player_i: usize) -> Vec<Observation> {
+        self.observations
+            .iter()
+            .map(|observations| observations[player_i])
+            .collect()
+    }
+
+    /// Observations of one player across all envs as a `[N, OBSERVATION_LENGTH]` tensor
+    pub fn observation_tensor<B: Backend>(
+        &self,
+        player_i: usize,
+        device: &B::Device,
+    ) -> Tensor<B, 2, Float> {
+        observations_to_tensor(&self.player_observations(player_i), device)
+    }
+
+    pub fn reset(&mut self, seed: Option<u64>) -> &[[Observation; 2]] {
+        for (i, (env, observations)) in self
+            .envs
+            .iter_mut()
+            .zip(self.observations.iter_mut())
+            .enumerate()
+        {
+            *observations = env.reset(seed.map(|seed| seed + i as u64));
+        }
+        &self.observations
+    }
+
+    pub fn step(&mut self, actions: &[[usize; 2]]) -> Vec<EnvStep<Observation>> {
+        assert_eq!(
+            actions.len(),
+            self.envs.len(),
+            "Expected one pair of actions per env"
+        );
+
+        if self.num_threads == 1 {
+            return step_chunk(&mut self.envs, &mut self.observations, actions);
+        }
+
+        let chunk_size = self.envs.len().div_ceil(self.num_threads);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .envs
+                .chunks_mut(chunk_size)
+                .zip(self.observations.chunks_mut(chunk_size))
+                .zip(actions.chunks(chunk_size))
+                .map(|((envs, observations), actions)| {
+                    scope.spawn(move || step_chunk(envs, observations, actions))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("Env thread panicked"))
+                .collect()
+        })
+    }
+}
+
+fn step_chunk<E: BoxingEnv<Obs = Observation>>(
+    envs: &mut [E],
+    observations: &mut [[Observation; 2]],
+    actions: &[[usize; 2]],
+) -> Vec<EnvStep<Observation>> {
+    envs.iter_mut()
+        .zip(observations.iter_mut())
+        .zip(actions)
+        .map(|((env, observations), actions)| {
+            let step = env.step(*actions);
+            *observations = if step.is_done() {
+                env.reset(None)
+            } else {
+                step.observations
+            };
+            step
+        })
+        .collect()
+}
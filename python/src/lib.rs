@@ -0,0 +1,518 @@
+use boxing::{
+    Control, GameState, MoveX, MoveY, OBSERVATION_LENGTH, OUTPUT_SIZE, Observation, StepResult,
+    env::{BoxSpace, EnvStep},
+};
+
+use numpy::{PyArray1, PyArray3, PyArrayMethods};
+use pyo3::{
+    exceptions::{PyKeyError, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+
+use crate::parallel::{AGENTS, ParallelEnv};
+
+pub mod parallel;
+mod render;
+
+const RENDER_SIZE: usize = 400;
+
+#[pyclass(name = "Control", eq, frozen)]
+#[derive(Clone, PartialEq)]
+pub struct PyControl {
+    inner: Control,
+}
+
+#[pymethods]
+impl PyControl {
+    /// `move_x` is -1 (left), 0 or 1 (right) and `move_y` is -1 (back) or 0
+    #[new]
+    #[pyo3(signature = (move_x=0, move_y=0, left_punch=false, right_punch=false))]
+    fn new(move_x: i32, move_y: i32, left_punch: bool, right_punch: bool) -> PyResult<Self> {
+        let move_x = match move_x {
+            -1 => MoveX::Left,
+            0 => MoveX::None,
+            1 => MoveX::Right,
+            _ => return Err(PyValueError::new_err("move_x must be -1, 0 or 1")),
+        };
+        let move_y = match move_y {
+            -1 => MoveY::Back,
+            0 => MoveY::None,
+            _ => return Err(PyValueError::new_err("move_y must be -1 or 0")),
+        };
+        Ok(Self {
+            inner: Control {
+                move_x,
+                move_y,
+                left_punch,
+                right_punch,
+            },
+        })
+    }
+
+    #[staticmethod]
+    fn from_int(action: usize) -> PyResult<Self> {
+        if action >= OUTPUT_SIZE {
+            return Err(PyValueError::new_err(format!(
+                "action must be below {OUTPUT_SIZE}"
+            )));
+        }
+        Ok(Self {
+            inner: Control::from_int(action),
+        })
+    }
+
+    fn to_int(&self) -> usize {
+        self.inner.to_int()
+    }
+
+    #[getter]
+    fn move_x(&self) -> i32 {
+        self.inner.move_x.to_num()
+    }
+
+    #[getter]
+    fn move_y(&self) -> i32 {
+        self.inner.move_y.to_num()
+    }
+
+    #[getter]
+    fn left_punch(&self) -> bool {
+        self.inner.left_punch
+    }
+
+    #[getter]
+    fn right_punch(&self) -> bool {
+        self.inner.right_punch
+    }
+
+    fn __repr__(&self) -> String {
+        let Control {
+            move_x,
+            move_y,
+            left_punch,
+            right_punch,
+        } = self.inner;
+        format!(
+            "Control(move_x={}, move_y={}, left_punch={}, right_punch={})",
+            move_x.to_num(),
+            move_y.to_num(),
+            py_bool(left_punch),
+            py_bool(right_punch),
+        )
+    }
+}
+
+fn py_bool(value: bool) -> &'static str {
+    if value { "True" } else { "False" }
+}
+
+#[pyclass(name = "Observation", frozen)]
+#[derive(Clone, Copy)]
+pub struct PyObservation {
+    inner: Observation,
+}
+
+#[pymethods]
+impl PyObservation {
+    /// The exact vector the DQN fighters are fed, as a float32 array
+    fn normalize<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f32>> {
+        PyArray1::from_slice(py, &self.inner.normalize())
+    }
+
+    #[getter]
+    fn health(&self) -> f32 {
+        self.inner.health
+    }
+
+    #[getter]
+    fn op_health(&self) -> f32 {
+        self.inner.op_health
+    }
+
+    #[getter]
+    fn energy(&self) -> f32 {
+        self.inner.energy
+    }
+
+    #[getter]
+    fn op_energy(&self) -> f32 {
+        self.inner.op_energy
+    }
+
+    #[getter]
+    fn position(&self) -> [f32; 2] {
+        self.inner.position
+    }
+
+    #[getter]
+    fn rotation(&self) -> f32 {
+        self.inner.rotation
+    }
+
+    #[getter]
+    fn op_position(&self) -> [f32; 2] {
+        self.inner.op_position
+    }
+
+    #[getter]
+    fn fist_states(&self) -> [usize; 2] {
+        [self.inner.left_fist_state, self.inner.right_fist_state]
+    }
+
+    #[getter]
+    fn op_fist_states(&self) -> [usize; 2] {
+        [
+            self.inner.op_left_fist_state,
+            self.inner.op_right_fist_state,
+        ]
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.inner)
+    }
+}
+
+/// Direct access to the physics, stepped with a pair of `Control`s
+#[pyclass(name = "GameState")]
+pub struct PyGameState {
+    inner: GameState,
+}
+
+#[pymethods]
+impl PyGameState {
+    #[new]
+    fn new() -> Self {
+        Self {
+            inner: GameState::new(),
+        }
+    }
+
+    /// Returns `((obs0, obs1), (reward0, reward1), is_done)`
+    fn step(
+        &mut self,
+        control0: PyControl,
+        control1: PyControl,
+    ) -> ((PyObservation, PyObservation), (f32, f32), bool) {
+        let StepResult {
+            observations: [obs0, obs1],
+            rewards: [reward0, reward1],
+            is_done,
+        } = self.inner.step([control0.inner, control1.inner]);
+        (
+            (PyObservation { inner: obs0 }, PyObservation { inner: obs1 }),
+            (reward0, reward1),
+            is_done,
+        )
+    }
+
+    fn get_observation(&self, player_i: usize) -> PyResult<PyObservation> {
+        check_player(player_i)?;
+        Ok(PyObservation {
+            inner: self.inner.get_observation(player_i),
+        })
+    }
+
+    fn is_done(&self) -> bool {
+        self.inner.is_done()
+    }
+
+    fn winner(&self) -> Option<usize> {
+        self.inner.winner()
+    }
+
+    #[getter]
+    fn health(&self) -> [f32; 2] {
+        self.inner.players.each_ref().map(|player| player.health)
+    }
+
+    #[getter]
+    fn energy(&self) -> [f32; 2] {
+        self.inner.players.each_ref().map(|player| player.energy)
+    }
+
+    #[getter]
+    fn positions(&self) -> [[f32; 2]; 2] {
+        self.inner
+            .players
+            .each_ref()
+            .map(|player| [player.position.x, player.position.y])
+    }
+
+    #[pyo3(signature = (width=RENDER_SIZE, height=RENDER_SIZE))]
+    fn render<'py>(
+        &self,
+        py: Python<'py>,
+        width: usize,
+        height: usize,
+    ) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        rgb_array(py, &self.inner, width, height)
+    }
+}
+
+fn check_player(player_i: usize) -> PyResult<()> {
+    if player_i > 1 {
+        return Err(PyValueError::new_err("player index must be 0 or 1"));
+    }
+    Ok(())
+}
+
+fn rgb_array<'py>(
+    py: Python<'py>,
+    game_state: &GameState,
+    width: usize,
+    height: usize,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    let pixels = render::render_rgb(game_state, width, height);
+    PyArray1::from_vec(py, pixels).reshape([height, width, 3])
+}
+
+/// Two-agent environment following the PettingZoo parallel API.
+///
+/// Observations are the normalized float32 vectors the DQN fighters see and actions are the
+/// 24 discrete `Control` indices, so policies trained here can be compared on the same physics.
+#[pyclass(name = "BoxingEnv")]
+pub struct PyBoxingEnv {
+    env: ParallelEnv,
+    render_mode: Option<String>,
+    #[pyo3(get)]
+    possible_agents: Vec<String>,
+    /// `gymnasium.spaces.Box` per agent, built once so `reset(seed=...)` can seed them
+    #[pyo3(get)]
+    observation_spaces: Py<PyDict>,
+    /// `gymnasium.spaces.Discrete` per agent
+    #[pyo3(get)]
+    action_spaces: Py<PyDict>,
+}
+
+#[pymethods]
+impl PyBoxingEnv {
+    #[classattr]
+    fn metadata(py: Python<'_>) -> PyResult<Bound<'_, PyDict>> {
+        let metadata = PyDict::new(py);
+        metadata.set_item("name", "rust_boxing_v0")?;
+        metadata.set_item("render_modes", vec!["rgb_array"])?;
+        metadata.set_item("render_fps", 24)?;
+        metadata.set_item("is_parallelizable", true)?;
+        Ok(metadata)
+    }
+
+    #[new]
+    #[pyo3(signature = (max_steps=24 * 120, render_mode=None))]
+    fn new(py: Python<'_>, max_steps: usize, render_mode: Option<String>) -> PyResult<Self> {
+        if let Some(mode) = &render_mode
+            && mode != "rgb_array"
+        {
+            return Err(PyValueError::new_err(format!(
+                "Unsupported render_mode {mode:?}, only \"rgb_array\" is available"
+            )));
+        }
+        let env = ParallelEnv::new(max_steps);
+
+        let spaces = py.import("gymnasium.spaces")?;
+        let bounds = BoxSpace::normalized(1);
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("low", bounds.low)?;
+        kwargs.set_item("high", bounds.high)?;
+        kwargs.set_item("shape", (OBSERVATION_LENGTH,))?;
+        kwargs.set_item("dtype", py.import("numpy")?.getattr("float32")?)?;
+        let observation_spaces = PyDict::new(py);
+        let action_spaces = PyDict::new(py);
+        for agent in AGENTS {
+            observation_spaces.set_item(agent, spaces.getattr("Box")?.call((), Some(&kwargs))?)?;
+            action_spaces.set_item(
+                agent,
+                spaces.getattr("Discrete")?.call1((env.num_actions(),))?,
+            )?;
+        }
+
+        Ok(Self {
+            env,
+            render_mode,
+            possible_agents: AGENTS.map(String::from).to_vec(),
+            observation_spaces: observation_spaces.unbind(),
+            action_spaces: action_spaces.unbind(),
+        })
+    }
+
+    /// Agents still fighting, empty once the episode is over
+    #[getter]
+    fn agents(&self) -> Vec<String> {
+        self.env
+            .agents()
+            .iter()
+            .map(|&agent| agent.to_string())
+            .collect()
+    }
+
+    #[getter]
+    fn num_agents(&self) -> usize {
+        self.env.agents().len()
+    }
+
+    #[getter]
+    fn max_num_agents(&self) -> usize {
+        self.possible_agents.len()
+    }
+
+    #[getter]
+    fn render_mode(&self) -> Option<String> {
+        self.render_mode.clone()
+    }
+
+    /// Returns `(observations, infos)`. The physics are deterministic, so `seed` only seeds
+    /// the spaces' `sample()`, `player_1`'s with `seed + 1`. There are no `options`.
+    #[pyo3(signature = (seed=None, options=None))]
+    fn reset<'py>(
+        &mut self,
+        py: Python<'py>,
+        seed: Option<u64>,
+        options: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<(Bound<'py, PyDict>, Bound<'py, PyDict>)> {
+        if let Some(options) = options
+            && !options.is_empty()
+        {
+            return Err(PyValueError::new_err(format!(
+                "BoxingEnv.reset() takes no options, got {options}"
+            )));
+        }
+        if let Some(seed) = seed {
+            for (player_i, agent) in AGENTS.iter().enumerate() {
+                let agent_seed = seed.wrapping_add(player_i as u64);
+                for spaces in [&self.observation_spaces, &self.action_spaces] {
+                    spaces
+                        .bind(py)
+                        .as_any()
+                        .get_item(agent)?
+                        .call_method1("seed", (agent_seed,))?;
+                }
+            }
+        }
+        let observations = self.env.reset(seed);
+
+        let infos = PyDict::new(py);
+        for agent in AGENTS {
+            infos.set_item(agent, PyDict::new(py))?;
+        }
+        Ok((observation_dict(py, &observations)?, infos))
+    }
+
+    /// Takes `{agent: action}` and returns
+    /// `(observations, rewards, terminations, truncations, infos)`
+    #[allow(clippy::type_complexity)]
+    fn step<'py>(
+        &mut self,
+        py: Python<'py>,
+        actions: &Bound<'py, PyDict>,
+    ) -> PyResult<(
+        Bound<'py, PyDict>,
+        Bound<'py, PyDict>,
+        Bound<'py, PyDict>,
+        Bound<'py, PyDict>,
+        Bound<'py, PyDict>,
+    )> {
+        let mut env_actions = [0; 2];
+        for (player_i, agent) in AGENTS.iter().enumerate() {
+            env_actions[player_i] = actions
+                .get_item(agent)?
+                .ok_or_else(|| PyKeyError::new_err(format!("Missing action for {agent}")))?
+                .extract()?;
+        }
+
+        let EnvStep {
+            observations,
+            rewards,
+            terminated,
+            truncated,
+            info,
+        } = self.env.step(env_actions).map_err(PyValueError::new_err)?;
+        let is_done = terminated || truncated;
+
+        let reward_dict = PyDict::new(py);
+        let terminations = PyDict::new(py);
+        let truncations = PyDict::new(py);
+        let infos = PyDict::new(py);
+        for (player_i, agent) in AGENTS.iter().enumerate() {
+            reward_dict.set_item(agent, rewards[player_i])?;
+            terminations.set_item(agent, terminated)?;
+            truncations.set_item(agent, truncated)?;
+
+            let agent_info = PyDict::new(py);
+            agent_info.set_item("health", info.health[player_i])?;
+            agent_info.set_item("num_punches", info.num_punches[player_i])?;
+            agent_info.set_item("num_landed_punches", info.num_landed_punches[player_i])?;
+            agent_info.set_item("episode_iters", info.episode_iters)?;
+            if is_done {
+                agent_info.set_item("winner", info.winner.map(|winner| AGENTS[winner]))?;
+            }
+            infos.set_item(agent, agent_info)?;
+        }
+
+        Ok((
+            observation_dict(py, &observations)?,
+            reward_dict,
+            terminations,
+            truncations,
+            infos,
+        ))
+    }
+
+    /// `gymnasium.spaces.Box` of the normalized observation vector
+    fn observation_space<'py>(&self, py: Python<'py>, agent: &str) -> PyResult<Bound<'py, PyAny>> {
+        check_agent(agent)?;
+        self.observation_spaces.bind(py).as_any().get_item(agent)
+    }
+
+    /// `gymnasium.spaces.Discrete` over the `Control` indices
+    fn action_space<'py>(&self, py: Python<'py>, agent: &str) -> PyResult<Bound<'py, PyAny>> {
+        check_agent(agent)?;
+        self.action_spaces.bind(py).as_any().get_item(agent)
+    }
+
+    fn render<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyArray3<u8>>>> {
+        match self.render_mode.as_deref() {
+            Some("rgb_array") => {
+                rgb_array(py, self.env.state(), RENDER_SIZE, RENDER_SIZE).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The underlying physics state, e.g. to step a scripted opponent from Python
+    fn state(&self) -> PyGameState {
+        PyGameState {
+            inner: self.env.state().clone(),
+        }
+    }
+
+    fn close(&mut self) {}
+}
+
+fn check_agent(agent: &str) -> PyResult<()> {
+    if !AGENTS.contains(&agent) {
+        return Err(PyKeyError::new_err(format!("Unknown agent {agent}")));
+    }
+    Ok(())
+}
+
+fn observation_dict<'py>(
+    py: Python<'py>,
+    observations: &[Observation; 2],
+) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    for (agent, observation) in AGENTS.iter().zip(observations) {
+        dict.set_item(agent, PyArray1::from_slice(py, &observation.normalize()))?;
+    }
+    Ok(dict)
+}
+
+#[pymodule]
+fn rust_boxing(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("OBSERVATION_LENGTH", OBSERVATION_LENGTH)?;
+    m.add("NUM_ACTIONS", OUTPUT_SIZE)?;
+    m.add_class::<PyControl>()?;
+    m.add_class::<PyObservation>()?;
+    m.add_class::<PyGameState>()?;
+    m.add_class::<PyBoxingEnv>()?;
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use boxing::{
+    GameState, OUTPUT_SIZE, Observation,
+    env::{BoxingEnv, EnvStep, GameEnv, TimeLimit},
+};
+
+/// Agent names used by the PettingZoo-style environment, indexed by player
+pub const AGENTS: [&str; 2] = ["player_0", "player_1"];
+
+/// The part of `BoxingEnv` that doesn't need Python: a time-limited fight between two
+/// agents that refuses to step once it's over
+pub struct ParallelEnv {
+    env: TimeLimit<GameEnv>,
+    is_done: bool,
+}
+
+impl ParallelEnv {
+    pub fn new(max_steps: usize) -> Self {
+        Self {
+            env: TimeLimit::new(GameEnv::new(), max_steps),
+            is_done: true,
+        }
+    }
+
+    /// Agents still fighting, none before the first `reset` and once the episode is over
+    pub fn agents(&self) -> &'static [&'static str] {
+        if self.is_done { &[] } else { &AGENTS }
+    }
+
+    /// The physics are deterministic, so `seed` only matters to wrappers that randomise
+    pub fn reset(&mut self, seed: Option<u64>) -> [Observation; 2] {
+        self.is_done = false;
+        self.env.reset(seed)
+    }
+
+    pub fn step(&mut self, actions: [usize; 2]) -> Result<EnvStep<Observation>, String> {
+        if self.is_done {
+            return Err("Episode is over, call reset() before step()".to_string());
+        }
+        if let Some((agent, action)) = AGENTS
+            .iter()
+            .zip(actions)
+            .find(|&(_, action)| action >= OUTPUT_SIZE)
+        {
+            return Err(format!(
+                "Action {action} for {agent} must be below {OUTPUT_SIZE}"
+            ));
+        }
+
+        let step = self.env.step(actions);
+        self.is_done = step.is_done();
+        Ok(step)
+    }
+
+    pub fn num_actions(&self) -> usize {
+        self.env.action_space().n
+    }
+
+    pub fn state(&self) -> &GameState {
+        self.env.state()
+    }
+}
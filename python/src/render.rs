@@ -0,0 +1,107 @@
+use boxing::{GameState, Player};
+
+type Rgb = [u8; 3];
+
+// Same palette as the macroquad windows in the train examples
+const BACKGROUND: Rgb = [80, 80, 80];
+const RING: Rgb = [255, 255, 255];
+const PLAYER_COLORS: [Rgb; 2] = [[230, 41, 55], [0, 121, 241]];
+const FIST_COLORS: [Rgb; 2] = [[253, 249, 0], [255, 161, 0]];
+const FACING: Rgb = [255, 255, 255];
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    scale: f32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        let scale = f32::min(
+            width as f32 / GameState::RING_SIZE.x,
+            height as f32 / GameState::RING_SIZE.y,
+        );
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for _ in 0..width * height {
+            pixels.extend_from_slice(&BACKGROUND);
+        }
+        Self {
+            width,
+            height,
+            scale,
+            pixels,
+        }
+    }
+
+    fn put(&mut self, x: i64, y: i64, color: Rgb) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+        let i = (y as usize * self.width + x as usize) * 3;
+        self.pixels[i..i + 3].copy_from_slice(&color);
+    }
+
+    /// Draws a filled circle, taking position and radius in game units
+    fn circle(&mut self, center: [f32; 2], radius: f32, color: Rgb) {
+        let [cx, cy] = center.map(|v| v * self.scale);
+        let r = radius * self.scale;
+        let (x0, x1) = ((cx - r).floor() as i64, (cx + r).ceil() as i64);
+        let (y0, y1) = ((cy - r).floor() as i64, (cy + r).ceil() as i64);
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+                if dx * dx + dy * dy <= r * r {
+                    self.put(x, y, color);
+                }
+            }
+        }
+    }
+
+    fn line(&mut self, from: [f32; 2], to: [f32; 2], color: Rgb) {
+        let [x0, y0] = from.map(|v| v * self.scale);
+        let [x1, y1] = to.map(|v| v * self.scale);
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as usize;
+        for i in 0..=steps {
+            let t = i as f32 / steps as f32;
+            self.put(
+                (x0 + (x1 - x0) * t) as i64,
+                (y0 + (y1 - y0) * t) as i64,
+                color,
+            );
+        }
+    }
+}
+
+/// Renders the ring to a row-major `height x width x 3` RGB buffer
+pub fn render_rgb(game_state: &GameState, width: usize, height: usize) -> Vec<u8> {
+    let mut canvas = Canvas::new(width, height);
+
+    let [ring_w, ring_h] = [GameState::RING_SIZE.x, GameState::RING_SIZE.y];
+    let corners = [[0.0, 0.0], [ring_w, 0.0], [ring_w, ring_h], [0.0, ring_h]];
+    for i in 0..corners.len() {
+        canvas.line(corners[i], corners[(i + 1) % corners.len()], RING);
+    }
+
+    for (i, player) in game_state.players.iter().enumerate() {
+        let center = [player.position.x, player.position.y];
+        canvas.circle(center, Player::RADIUS, PLAYER_COLORS[i]);
+
+        // Facing direction, up is a rotation of 0
+        let facing = [
+            center[0] + player.rotation.sin() * Player::RADIUS,
+            center[1] - player.rotation.cos() * Player::RADIUS,
+        ];
+        canvas.line(center, facing, FACING);
+
+        for (fist_i, fist) in player.fists.iter().enumerate() {
+            canvas.circle(
+                [fist.position.x, fist.position.y],
+                Player::FIST_RADIUS,
+                FIST_COLORS[fist_i],
+            );
+        }
+    }
+
+    canvas.pixels
+}
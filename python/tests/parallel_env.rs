@@ -0,0 +1,49 @@
+use boxing::OUTPUT_SIZE;
+use rust_boxing::parallel::{AGENTS, ParallelEnv};
+
+#[test]
+fn fights_run_from_reset_to_their_last_step() {
+    let mut env = ParallelEnv::new(10);
+    assert!(
+        env.agents().is_empty(),
+        "Nobody fights before the first reset"
+    );
+    assert!(env.step([0, 0]).is_err());
+
+    for seed in [None, Some(3)] {
+        let observations = env.reset(seed);
+        assert_eq!(env.agents(), AGENTS);
+        assert_eq!(observations[0].health, observations[1].health);
+
+        let mut steps = 0;
+        let last = loop {
+            let step = env.step([1, 2]).expect("Should step a running fight");
+            steps += 1;
+            if step.is_done() {
+                break step;
+            }
+            assert_eq!(env.agents(), AGENTS);
+        };
+        assert_eq!(steps, 10);
+        assert!(last.truncated && !last.terminated);
+        assert_eq!(last.info.episode_iters, 10);
+        assert_eq!(last.info.winner, env.state().winner());
+        assert!(env.agents().is_empty());
+        assert!(env.step([1, 2]).is_err(), "A finished fight shouldn't step");
+    }
+}
+
+#[test]
+fn out_of_range_actions_are_rejected() {
+    let mut env = ParallelEnv::new(10);
+    env.reset(None);
+    let Err(error) = env.step([0, OUTPUT_SIZE]) else {
+        panic!("Action {OUTPUT_SIZE} should be rejected");
+    };
+    assert!(error.contains("player_1"), "{error}");
+    assert_eq!(
+        env.agents(),
+        AGENTS,
+        "A rejected step shouldn't end the fight"
+    );
+}
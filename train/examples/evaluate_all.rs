@@ -1,19 +1,18 @@
-use ::rand::rng;
+#![recursion_limit = "256"]
+
 use ::rand::Rng;
-use core::model::{DQN, DQNConfig};
-use core::{Control, GameState};
-use std::path::PathBuf;
-use train::train::select_action;
+use ::rand::rng;
+use core::env::{BoxingEnv, GameEnv, TimeLimit};
+use core::model::{SavedModel, load_saved_model};
+use core::{agent::Agent, bots::ScriptedBot};
+use std::path::{Path, PathBuf};
 
-use burn::record::Recorder;
 use burn::{
-    backend::Wgpu,
     prelude::*,
     record::{FullPrecisionSettings, NamedMpkFileRecorder},
 };
 use std::fs;
-
-type MyBackend = Wgpu<f32, i32>;
+use train::backend::{BackendKind, BackendTask};
 
 struct EvaluationResult {
     model1_name: String,
@@ -23,31 +22,27 @@ struct EvaluationResult {
     model2_health: f32,
 }
 
-fn load_model(
-    model_path: &PathBuf,
-    device: &<MyBackend as Backend>::Device,
-) -> Result<DQN<MyBackend>, Box<dyn std::error::Error>> {
-    let record =
-        NamedMpkFileRecorder::<FullPrecisionSettings>::new().load(model_path.clone(), device)?;
-    let model: DQN<MyBackend> = DQNConfig::new(23, 24).init(device).load_record(record);
-    Ok(model)
+fn load_model<B: Backend>(
+    model_path: &Path,
+    device: &B::Device,
+) -> Result<SavedModel<B>, Box<dyn std::error::Error>> {
+    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    Ok(load_saved_model(
+        &recorder,
+        model_path.to_path_buf(),
+        device,
+    )?)
 }
 
-fn evaluate_models(
-    model1: &DQN<MyBackend>,
-    model2: &DQN<MyBackend>,
+fn evaluate_models<'a>(
+    model1: &'a mut dyn Agent,
+    model2: &'a mut dyn Agent,
     model1_name: &str,
     model2_name: &str,
-    device: &<MyBackend as Backend>::Device,
 ) -> EvaluationResult {
-    let mut game_state = GameState::new();
+    let mut env = TimeLimit::new(GameEnv::new(), 24 * 120);
     let mut rng = rng();
 
-    // Parameters
-    let epsilon = 0.005;
-    let n_actions = 24;
-    let max_iterations = 24 * 120;
-
     // Randomly swap model order with 50% chance to reduce position bias
     let swap_models = rng.random_bool(0.5);
     let (player0_model, player1_model, player0_name, player1_name) = if swap_models {
@@ -56,41 +51,30 @@ fn evaluate_models(
         (model1, model2, model1_name, model2_name)
     };
 
-    // Run the game
-    for _iteration in 0..max_iterations {
-        // Get observations for both players
-        let obs0 = game_state.get_observation(0);
-        let obs1 = game_state.get_observation(1);
-
+    // Run the game until a knockout or the time limit
+    player0_model.reset();
+    player1_model.reset();
+    let [mut obs0, mut obs1] = env.reset(None);
+    let step = loop {
         // Select actions using the models (potentially swapped)
-        let action0 = select_action(obs0, player0_model, epsilon, n_actions, &mut rng, device);
-        let action1 = select_action(obs1, player1_model, epsilon, n_actions, &mut rng, device);
-
-        // Convert actions to controls
-        let controls = [
-            Control::from_int(action0), // Player 0 
-            Control::from_int(action1), // Player 1 
-        ];
-
-        // Update game state
-        let step_result = game_state.step(controls);
+        let action0 = player0_model.act(&obs0, &mut rng).to_int();
+        let action1 = player1_model.act(&obs1, &mut rng).to_int();
 
-        // Check if game ended early (though we'll run full duration anyway)
-        if step_result.is_done {
-            break;
+        let step = env.step([action0, action1]);
+        if step.is_done() {
+            break step;
         }
-    }
+        [obs0, obs1] = step.observations;
+    };
 
     // Determine winner based on health (accounting for potential model swap)
-    let player0_health = game_state.players[0].health;
-    let player1_health = game_state.players[1].health;
+    let player0_health = env.state().players[0].health;
+    let player1_health = env.state().players[1].health;
 
-    let winner = if player0_health > player1_health {
-        player0_name.to_string()
-    } else if player1_health > player0_health {
-        player1_name.to_string()
-    } else {
-        "Tie".to_string()
+    let winner = match step.info.winner {
+        Some(0) => player0_name.to_string(),
+        Some(_) => player1_name.to_string(),
+        None => "Tie".to_string(),
     };
 
     // Return results in original model1/model2 order for consistency
@@ -109,26 +93,32 @@ fn evaluate_models(
     }
 }
 
+struct EvaluateAll;
+
+impl BackendTask for EvaluateAll {
+    type Output = Result<(), Box<dyn std::error::Error>>;
+
+    fn run<B: Backend>(self, device: B::Device) -> Self::Output {
+        evaluate_all::<B>(&device)
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize device
-    let device = Default::default();
+    BackendKind::from_env().run(EvaluateAll)
+}
 
+fn evaluate_all<B: Backend>(device: &B::Device) -> Result<(), Box<dyn std::error::Error>> {
     // Discover all .mpk files in the models directory
     let models_dir = PathBuf::from("./assets/models/");
     let mut model_files = Vec::new();
 
     if let Ok(entries) = fs::read_dir(&models_dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if let Some(extension) = path.extension() {
-                    if extension == "mpk" {
-                        if let Some(file_name) = path.file_name() {
-                            model_files
-                                .push((path.clone(), file_name.to_string_lossy().to_string()));
-                        }
-                    }
-                }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|extension| extension == "mpk")
+                && let Some(file_name) = path.file_name()
+            {
+                model_files.push((path.clone(), file_name.to_string_lossy().to_string()));
             }
         }
     }
@@ -145,11 +135,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!();
 
     // Load all models
-    let mut loaded_models = Vec::new();
+    let mut loaded_models: Vec<(Box<dyn Agent>, String)> = Vec::new();
     for (path, name) in &model_files {
-        match load_model(path, &device) {
+        match load_model::<B>(path, device) {
             Ok(model) => {
-                loaded_models.push((model, name.clone()));
+                let agent = model.into_agent(name.clone(), 0.005, device);
+                loaded_models.push((agent, name.clone()));
                 println!("Successfully loaded: {name}");
             }
             Err(e) => {
@@ -159,6 +150,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // Scripted bots give a fixed yardstick across runs
+    for bot in ScriptedBot::ALL {
+        loaded_models.push((Box::new(bot), Agent::name(&bot)));
+    }
+
     if loaded_models.len() < 2 {
         println!(
             "Need at least 2 models to evaluate. Found: {}",
@@ -173,12 +169,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut results = Vec::new();
     for i in 0..loaded_models.len() {
         for j in (i + 1)..loaded_models.len() {
-            let (model1, name1) = &loaded_models[i];
-            let (model2, name2) = &loaded_models[j];
+            let (head, tail) = loaded_models.split_at_mut(j);
+            let (model1, name1) = &mut head[i];
+            let (model2, name2) = &mut tail[0];
 
             println!("Evaluating {name1} vs {name2}...");
 
-            let result = evaluate_models(model1, model2, name1, name2, &device);
+            let result = evaluate_models(model1.as_mut(), model2.as_mut(), name1, name2);
             results.push(result);
         }
     }
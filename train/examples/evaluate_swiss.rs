@@ -1,27 +1,24 @@
+#![recursion_limit = "256"]
+
 use ::rand::Rng;
 use ::rand::rng;
-use core::model::{DQN, DQNConfig};
-use core::{Control, GameState};
+use core::env::{BoxingEnv, GameEnv, TimeLimit};
+use core::model::{SavedModel, load_saved_model};
+use core::{agent::Agent, bots::ScriptedBot};
 use rand::seq::SliceRandom;
-use std::path::PathBuf;
-use train::train::select_action;
+use std::path::{Path, PathBuf};
 
-use burn::record::Recorder;
 use burn::{
-    backend::Wgpu,
     prelude::*,
     record::{FullPrecisionSettings, NamedMpkFileRecorder},
 };
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 use std::fs;
-
-type MyBackend = Wgpu<f32, i32>;
+use train::backend::{BackendKind, BackendTask};
 
 #[derive(Debug, Clone)]
 struct Model {
-    id: usize,
     name: String,
-    model: Option<DQN<MyBackend>>, // Option because we can't clone DQN easily
     score: f32,
     opponents: HashSet<usize>,
     wins: usize,
@@ -29,39 +26,42 @@ struct Model {
     ties: usize,
 }
 
-#[derive(Debug)]
+impl Model {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            score: 0.0,
+            opponents: HashSet::new(),
+            wins: 0,
+            losses: 0,
+            ties: 0,
+        }
+    }
+}
+
 struct Tournament {
     models: Vec<Model>,
-    model_refs: Vec<DQN<MyBackend>>, // Store actual models separately
+    model_refs: Vec<Box<dyn Agent>>, // Store actual models separately
     rounds: Vec<Vec<(usize, usize)>>, // pairs of model indices
     current_round: usize,
 }
 
 impl Tournament {
-    fn new(
+    fn new<B: Backend>(
         model_files: Vec<(PathBuf, String)>,
-        device: &<MyBackend as Backend>::Device,
+        device: &B::Device,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let mut models = Vec::new();
-        let mut model_refs = Vec::new();
+        let mut model_refs: Vec<Box<dyn Agent>> = Vec::new();
 
         println!("Loading models for Swiss tournament...");
 
-        for (id, (path, name)) in model_files.iter().enumerate() {
-            match load_model(path, device) {
+        for (path, name) in model_files.iter() {
+            match load_model::<B>(path, device) {
                 Ok(model) => {
                     println!("✓ Loaded: {}", name);
-                    model_refs.push(model);
-                    models.push(Model {
-                        id,
-                        name: name.clone(),
-                        model: None, // We store models separately
-                        score: 0.0,
-                        opponents: HashSet::new(),
-                        wins: 0,
-                        losses: 0,
-                        ties: 0,
-                    });
+                    model_refs.push(model.into_agent(name.clone(), 0.005, device));
+                    models.push(Model::new(name.clone()));
                 }
                 Err(e) => {
                     println!("✗ Failed to load {}: {}", name, e);
@@ -70,6 +70,12 @@ impl Tournament {
             }
         }
 
+        // Scripted bots give a fixed yardstick across runs
+        for bot in ScriptedBot::ALL {
+            models.push(Model::new(Agent::name(&bot)));
+            model_refs.push(Box::new(bot));
+        }
+
         if models.len() < 2 {
             return Err("Need at least 2 models for Swiss tournament".into());
         }
@@ -112,7 +118,7 @@ impl Tournament {
     }
 
     fn pair_next_round(&mut self) -> Result<Vec<(usize, usize)>, Box<dyn std::error::Error>> {
-        let mut sorted_indices = self.get_sorted_model_indices();
+        let sorted_indices = self.get_sorted_model_indices();
         let mut pairs = Vec::new();
         let mut unpaired: HashSet<usize> = sorted_indices.iter().copied().collect();
 
@@ -258,10 +264,7 @@ impl Tournament {
         println!();
     }
 
-    fn run_tournament(
-        &mut self,
-        device: &<MyBackend as Backend>::Device,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    fn run_tournament(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         let num_rounds = self.calculate_rounds_needed();
         println!("=== SWISS TOURNAMENT START ===");
         println!(
@@ -286,13 +289,8 @@ impl Tournament {
 
                 println!("Match: {} vs {}", model1_name, model2_name);
 
-                let result = evaluate_models(
-                    &self.model_refs[model1_id],
-                    &self.model_refs[model2_id],
-                    model1_name,
-                    model2_name,
-                    device,
-                );
+                let (model1, model2) = pair_mut(&mut self.model_refs, model1_id, model2_id);
+                let result = evaluate_models(model1.as_mut(), model2.as_mut());
 
                 // Print match result
                 match result.game_result {
@@ -329,89 +327,71 @@ enum GameResult {
 }
 
 struct EvaluationResult {
-    model1_name: String,
-    model2_name: String,
     game_result: GameResult,
     model1_health: f32,
     model2_health: f32,
 }
 
-fn load_model(
-    model_path: &PathBuf,
-    device: &<MyBackend as Backend>::Device,
-) -> Result<DQN<MyBackend>, Box<dyn std::error::Error>> {
-    let record =
-        NamedMpkFileRecorder::<FullPrecisionSettings>::new().load(model_path.clone(), device)?;
-    let model: DQN<MyBackend> = DQNConfig::new(23, 24).init(device).load_record(record);
-    Ok(model)
+fn load_model<B: Backend>(
+    model_path: &Path,
+    device: &B::Device,
+) -> Result<SavedModel<B>, Box<dyn std::error::Error>> {
+    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    Ok(load_saved_model(
+        &recorder,
+        model_path.to_path_buf(),
+        device,
+    )?)
 }
 
-fn evaluate_models(
-    model1: &DQN<MyBackend>,
-    model2: &DQN<MyBackend>,
-    model1_name: &str,
-    model2_name: &str,
-    device: &<MyBackend as Backend>::Device,
-) -> EvaluationResult {
-    let mut game_state = GameState::new();
-    let mut rng = rng();
+/// Mutable references to two different entrants
+fn pair_mut<T>(items: &mut [T], i: usize, j: usize) -> (&mut T, &mut T) {
+    assert_ne!(i, j, "An entrant can't fight itself");
+    if i < j {
+        let (head, tail) = items.split_at_mut(j);
+        (&mut head[i], &mut tail[0])
+    } else {
+        let (head, tail) = items.split_at_mut(i);
+        (&mut tail[0], &mut head[j])
+    }
+}
 
-    // Parameters
-    let epsilon = 0.005;
-    let n_actions = 24;
-    let max_iterations = 24 * 120;
+fn evaluate_models<'a>(model1: &'a mut dyn Agent, model2: &'a mut dyn Agent) -> EvaluationResult {
+    let mut env = TimeLimit::new(GameEnv::new(), 24 * 120);
+    let mut rng = rng();
 
     // Randomly swap model order with 50% chance to reduce position bias
     let swap_models = rng.random_bool(0.5);
-    let (player0_model, player1_model, player0_name, player1_name) = if swap_models {
-        (model2, model1, model2_name, model1_name)
+    let (player0_model, player1_model) = if swap_models {
+        (model2, model1)
     } else {
-        (model1, model2, model1_name, model2_name)
+        (model1, model2)
     };
 
-    // Run the game
-    for _iteration in 0..max_iterations {
-        // Get observations for both players
-        let obs0 = game_state.get_observation(0);
-        let obs1 = game_state.get_observation(1);
-
+    // Run the game until a knockout or the time limit
+    player0_model.reset();
+    player1_model.reset();
+    let [mut obs0, mut obs1] = env.reset(None);
+    let step = loop {
         // Select actions using the models (potentially swapped)
-        let action0 = select_action(obs0, player0_model, epsilon, n_actions, &mut rng, device);
-        let action1 = select_action(obs1, player1_model, epsilon, n_actions, &mut rng, device);
-
-        // Convert actions to controls
-        let controls = [
-            Control::from_int(action0), // Player 0
-            Control::from_int(action1), // Player 1
-        ];
+        let action0 = player0_model.act(&obs0, &mut rng).to_int();
+        let action1 = player1_model.act(&obs1, &mut rng).to_int();
 
-        // Update game state
-        let step_result = game_state.step(controls);
-
-        // Check if game ended early (though we'll run full duration anyway)
-        if step_result.is_done {
-            break;
+        let step = env.step([action0, action1]);
+        if step.is_done() {
+            break step;
         }
-    }
+        [obs0, obs1] = step.observations;
+    };
 
     // Determine winner based on health (accounting for potential model swap)
-    let player0_health = game_state.players[0].health;
-    let player1_health = game_state.players[1].health;
+    let player0_health = env.state().players[0].health;
+    let player1_health = env.state().players[1].health;
 
-    let game_result = if player0_health > player1_health {
-        if swap_models {
-            GameResult::Model2Wins
-        } else {
-            GameResult::Model1Wins
-        }
-    } else if player1_health > player0_health {
-        if swap_models {
-            GameResult::Model1Wins
-        } else {
-            GameResult::Model2Wins
-        }
-    } else {
-        GameResult::Tie
+    let game_result = match (step.info.winner, swap_models) {
+        (Some(0), false) | (Some(1), true) => GameResult::Model1Wins,
+        (Some(_), _) => GameResult::Model2Wins,
+        (None, _) => GameResult::Tie,
     };
 
     // Return results in original model1/model2 order for consistency
@@ -422,34 +402,35 @@ fn evaluate_models(
     };
 
     EvaluationResult {
-        model1_name: model1_name.to_string(),
-        model2_name: model2_name.to_string(),
         game_result,
         model1_health,
         model2_health,
     }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize device
-    let device = Default::default();
+/// Loads the entrants on the backend picked by `BOXING_BACKEND`
+struct LoadTournament(Vec<(PathBuf, String)>);
+
+impl BackendTask for LoadTournament {
+    type Output = Result<Tournament, Box<dyn std::error::Error>>;
+
+    fn run<B: Backend>(self, device: B::Device) -> Self::Output {
+        Tournament::new::<B>(self.0, &device)
+    }
+}
 
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Discover all .mpk files in the models directory
     let models_dir = PathBuf::from("./assets/models/");
     let mut model_files = Vec::new();
 
     if let Ok(entries) = fs::read_dir(&models_dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if let Some(extension) = path.extension() {
-                    if extension == "mpk" {
-                        if let Some(file_name) = path.file_name() {
-                            model_files
-                                .push((path.clone(), file_name.to_string_lossy().to_string()));
-                        }
-                    }
-                }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|extension| extension == "mpk")
+                && let Some(file_name) = path.file_name()
+            {
+                model_files.push((path.clone(), file_name.to_string_lossy().to_string()));
             }
         }
     }
@@ -469,8 +450,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!();
 
     // Create and run tournament
-    let mut tournament = Tournament::new(model_files, &device)?;
-    tournament.run_tournament(&device)?;
+    let mut tournament = BackendKind::from_env().run(LoadTournament(model_files))?;
+    tournament.run_tournament()?;
 
     // Print final results
     println!("=== FINAL TOURNAMENT RESULTS ===");
@@ -1,17 +1,18 @@
+#![recursion_limit = "256"]
+
 use ::rand::rng;
-use core::model::{DQN, DQNConfig};
-use core::{Control, GameState, Player, select_action};
+use core::agent::Agent;
+use core::env::{BoxingEnv, GameEnv};
+use core::model::{SavedModel, load_saved_model};
+use core::{GameState, Player};
 use std::path::PathBuf;
 
-use burn::record::{BinFileRecorder, Recorder};
 use burn::{
-    backend::Wgpu,
-    prelude::*,
+    prelude::Backend,
     record::{FullPrecisionSettings, NamedMpkFileRecorder},
 };
 use macroquad::prelude::*;
-
-type MyBackend = Wgpu<f32, i32>;
+use train::backend::{BackendKind, BackendTask};
 
 fn window_conf() -> Conf {
     Conf {
@@ -22,65 +23,72 @@ fn window_conf() -> Conf {
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
-    // Initialize device
-    let device = Default::default();
+/// Loads both fighters on the backend picked by `BOXING_BACKEND`
+struct LoadPlayers;
 
-    // Load model 0
-    let record0 = NamedMpkFileRecorder::<FullPrecisionSettings>::new()
-        .load(PathBuf::from("./assets/models/dqn025.mpk"), &device)
-        .expect("Should be able to load model 0 weights");
-    let model0: DQN<MyBackend> = DQNConfig::new(23, 24).init(&device).load_record(record0);
+impl BackendTask for LoadPlayers {
+    type Output = [Box<dyn Agent>; 2];
 
-    // Load model 1
-    let record1 = NamedMpkFileRecorder::<FullPrecisionSettings>::new()
-        .load(PathBuf::from("./assets/models/dqn011.mpk"), &device)
+    fn run<B: Backend>(self, device: B::Device) -> Self::Output {
+        // Load model 0
+        let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+        let model0: SavedModel<B> = load_saved_model(
+            &recorder,
+            PathBuf::from("./assets/models/dqn025.mpk"),
+            &device,
+        )
         .expect("Should be able to load model 0 weights");
-    let model1: DQN<MyBackend> = DQNConfig::new(23, 24).init(&device).load_record(record1);
 
-    let mut game_state = GameState::new();
+        // Load model 1
+        let model1: SavedModel<B> = load_saved_model(
+            &recorder,
+            PathBuf::from("./assets/models/dqn011.mpk"),
+            &device,
+        )
+        .expect("Should be able to load model 1 weights");
+
+        // Epsilon for action selection (0 for greedy play)
+        let epsilon = 0.005;
+        [
+            model0.into_agent("dqn025", epsilon, &device),
+            model1.into_agent("dqn011", epsilon, &device),
+        ]
+    }
+}
+
+#[macroquad::main(window_conf)]
+async fn main() {
+    let [mut player0, mut player1] = BackendKind::from_env().run(LoadPlayers);
+
+    let mut env = GameEnv::new();
+    let [mut obs0, mut obs1] = env.reset(None);
     let target_fps = 24.0;
     let frame_time = 1.0 / target_fps;
 
     let mut rng = rng();
 
-    // Epsilon for action selection (0 for greedy play)
-    let epsilon = 0.005;
-    let n_actions = 24;
-
     loop {
         let frame_start = get_time();
 
-        // Get observations for both players
-        let obs0 = game_state.get_observation(0);
-        let obs1 = game_state.get_observation(1);
-
         // Select actions using the models
-        let action0 = select_action(obs0, &model0, epsilon, n_actions, &mut rng, &device);
-        let action1 = select_action(obs1, &model1, epsilon, n_actions, &mut rng, &device);
+        let action0 = player0.act(&obs0, &mut rng).to_int();
+        let action1 = player1.act(&obs1, &mut rng).to_int();
 
         //println!("Action {} {}", action0, action1);
 
-        // Convert actions to controls
-        let controls = [
-            Control::from_int(action0), // Player 0
-            Control::from_int(action1), // Player 1
-        ];
-
         // Update game state
-        game_state.step(controls);
+        [obs0, obs1] = env.step([action0, action1]).observations;
 
         // Render
         clear_background(DARKGRAY);
-        draw_game(&game_state);
+        draw_game(env.state());
 
         // Check for manual controls to pause/unpause or exit
         if is_key_pressed(KeyCode::Space) {
             // Pause until space is pressed again
             while !is_key_pressed(KeyCode::Space) {
                 clear_background(DARKGRAY);
-                draw_game(&game_state);
+                draw_game(env.state());
                 draw_text(
                     "PAUSED - Press SPACE to continue",
                     screen_width() / 2.0 - 150.0,
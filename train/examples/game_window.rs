@@ -1,4 +1,7 @@
+use core::agent::{Agent, HumanAgent};
+use core::env::{BoxingEnv, GameEnv};
 use core::{Control, GameState, MoveX, MoveY, Player};
+use std::array;
 
 use macroquad::prelude::*;
 
@@ -13,7 +16,11 @@ fn window_conf() -> Conf {
 
 #[macroquad::main(window_conf)]
 async fn main() {
-    let mut game_state = GameState::new();
+    let mut env = GameEnv::new();
+    let mut observations = env.reset(None);
+    let mut players = [HumanAgent::new("player_1"), HumanAgent::new("player_2")];
+    let inputs = players.each_ref().map(|player| player.input());
+    let mut rng = ::rand::rng();
     let target_fps = 24.0;
     let frame_time = 1.0 / target_fps;
 
@@ -21,17 +28,18 @@ async fn main() {
         let frame_start = get_time();
 
         // Handle input for both players
-        let controls = [
-            get_player_controls(0), // Player 1
-            get_player_controls(1), // Player 2
-        ];
+        for (player_id, input) in inputs.iter().enumerate() {
+            input.set(get_player_controls(player_id));
+        }
+        let actions: [usize; 2] =
+            array::from_fn(|i| players[i].act(&observations[i], &mut rng).to_int());
 
         // Update game state
-        game_state.step(controls);
+        observations = env.step(actions).observations;
 
         // Render
         clear_background(DARKGRAY);
-        draw_game(&game_state);
+        draw_game(env.state());
 
         // Wait for the remaining frame time
         let elapsed = get_time() - frame_start;
@@ -1,30 +1,42 @@
-use core::model::{DQN, DQNConfig};
+#![recursion_limit = "256"]
+
+use core::model::{DQN, load_dqn, save_dqn};
 use std::path::PathBuf;
 
 use burn::{
-    backend::Wgpu,
-    module::Module,
-    record::{BinFileRecorder, FullPrecisionSettings, NamedMpkFileRecorder, Recorder},
-    tensor::Device,
+    prelude::Backend,
+    record::{BinFileRecorder, FullPrecisionSettings, NamedMpkFileRecorder},
 };
+use train::backend::{BackendKind, BackendTask};
+
+struct SaveModels;
 
-type MyBackend = Wgpu<f32, i32>;
+impl BackendTask for SaveModels {
+    type Output = ();
+
+    fn run<B: Backend>(self, device: B::Device) {
+        save_models::<B>(&device);
+    }
+}
 
 fn main() {
-    let device: Device<MyBackend> = Default::default();
+    BackendKind::from_env().run(SaveModels);
+}
+
+fn save_models<B: Backend>(device: &B::Device) {
     let models_to_save = vec![77, 5, 21, 55, 54, 0];
 
     for model_num in models_to_save {
         let source_file_name = format!("./assets/models/dqn{:03}.mpk", model_num);
 
-        let record = NamedMpkFileRecorder::<FullPrecisionSettings>::new()
-            .load(PathBuf::from(source_file_name), &device)
-            .expect("Should be able to load model 0 weights");
-        let model: DQN<MyBackend> = DQNConfig::new(23, 24).init(&device).load_record(record);
+        let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+        let model: DQN<B> = load_dqn(&recorder, PathBuf::from(source_file_name), device)
+            .expect("Should be able to load model weights");
 
         let dest_file_name = format!("./assets/binary_models/dqn{:03}.bin", model_num);
 
         let bin_record = BinFileRecorder::<FullPrecisionSettings>::new();
-        model.save_file(dest_file_name, &bin_record);
+        save_dqn(model, &bin_record, PathBuf::from(dest_file_name))
+            .expect("Should save binary model");
     }
 }
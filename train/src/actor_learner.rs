@@ -0,0 +1,428 @@
+use core::{
+    agent::Agent,
+    env::{BoxingEnv, GameEnv, TimeLimit},
+    model::{DQN, load_dqn, save_dqn},
+    select_action,
+};
+use std::{
+    fmt,
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use burn::{
+    module::AutodiffModule,
+    prelude::Backend,
+    record::{BinBytesRecorder, FullPrecisionSettings},
+    tensor::backend::AutodiffBackend,
+};
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    benchmark::{Benchmark, BenchmarkState, report},
+    checkpoint::TrainerRng,
+    evaluation::Entrant,
+    metrics::{EpisodeTracker, MetricsLogger},
+    replay_buffer::{Experience, NStepAccumulator, ReplayBuffer},
+    train::{
+        DqnLearner, MAX_EPISODE_ITERS, MEMORY_SIZE, NUM_ACTIONS, TRAIN_START, TargetUpdate,
+        TrainStats, TrainingConfig, get_epsilon, train_on_batch,
+    },
+};
+
+/// Exploration of the fixed opponents actors play against
+const OPPONENT_EPSILON: f32 = 0.05;
+
+/// Threads and hand-offs of `train_actor_learner`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActorLearnerConfig {
+    /// Threads stepping fights, each with its own copy of the policy
+    pub num_actors: usize,
+    /// Learner updates between publishing its weights to the actors
+    pub sync_interval: usize,
+    /// Transitions an actor collects before adding them to the shared buffer in one go
+    pub push_batch: usize,
+    /// Seconds between throughput reports
+    pub report_interval: f64,
+}
+
+impl Default for ActorLearnerConfig {
+    fn default() -> Self {
+        Self {
+            num_actors: 4,
+            sync_interval: 50,
+            push_batch: 64,
+            report_interval: 10.0,
+        }
+    }
+}
+
+/// Totals of an actor-learner run
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Throughput {
+    pub env_steps: usize,
+    pub updates: usize,
+    pub episodes: usize,
+    pub seconds: f64,
+}
+
+impl Throughput {
+    pub fn env_steps_per_sec(&self) -> f64 {
+        self.env_steps as f64 / self.seconds.max(f64::EPSILON)
+    }
+
+    pub fn updates_per_sec(&self) -> f64 {
+        self.updates as f64 / self.seconds.max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} env steps ({:.0}/s), {} updates ({:.1}/s), {} episodes in {:.1}s",
+            self.env_steps,
+            self.env_steps_per_sec(),
+            self.updates,
+            self.updates_per_sec(),
+            self.episodes,
+            self.seconds
+        )
+    }
+}
+
+/// Everything the actors and the learner hand each other
+struct Shared {
+    buffer: Mutex<ReplayBuffer>,
+    /// The latest policy as written by `save_dqn`, bytes travel between backends
+    policy: Mutex<Vec<u8>>,
+    policy_version: AtomicUsize,
+    /// The learner's latest step, so actors can log it with their episodes
+    stats: Mutex<Option<TrainStats>>,
+    metrics: Mutex<MetricsLogger>,
+    env_steps: AtomicUsize,
+    episodes: AtomicUsize,
+    stop: AtomicBool,
+}
+
+impl Shared {
+    fn is_finished(&self, config: &TrainingConfig) -> bool {
+        self.stop.load(Ordering::Relaxed)
+            || self.episodes.load(Ordering::Relaxed) >= config.num_episodes
+            || self.env_steps.load(Ordering::Relaxed) > config.max_iters
+    }
+
+    fn publish<B: Backend>(&self, policy_net: DQN<B>) {
+        let bytes = save_dqn(
+            policy_net,
+            &BinBytesRecorder::<FullPrecisionSettings>::default(),
+            (),
+        )
+        .expect("Should serialize policy");
+        *self.policy.lock().unwrap() = bytes;
+        self.policy_version.fetch_add(1, Ordering::Release);
+    }
+
+    fn fetch<A: Backend>(&self, device: &A::Device) -> DQN<A> {
+        let bytes = self.policy.lock().unwrap().clone();
+        load_dqn(
+            &BinBytesRecorder::<FullPrecisionSettings>::default(),
+            bytes,
+            device,
+        )
+        .expect("Should load published policy")
+    }
+}
+
+/// Trains one learner on experience from `num_actors` threads. Actors play the policy on
+/// `A`, usually the CPU backend, against `opponents`, or against themselves when there are
+/// none, and add their transitions to one replay buffer. The learner trains on `B` from
+/// that buffer as fast as it can while staying at most one update per
+/// `iters_per_training_step` env steps, and publishes its weights every `sync_interval`
+/// updates.
+///
+/// Episodes are logged to `metrics_dir` like the other trainers, with the learner's latest
+/// step standing in for the training stats, and benchmark bests go to `best/` there.
+/// Threads interleave freely, so runs aren't reproducible.
+pub fn train_actor_learner<B: AutodiffBackend, A: Backend>(
+    learner: DqnLearner<B>,
+    opponents: &[Entrant<A>],
+    config: &TrainingConfig,
+    device: &B::Device,
+    actor_device: &A::Device,
+    metrics_dir: Option<&Path>,
+) -> (DqnLearner<B>, Throughput) {
+    let actor_config = config.actor_learner;
+    let mut rng = TrainerRng::seed_from_u64(config.seed);
+    let shared = Shared {
+        buffer: Mutex::new(
+            ReplayBuffer::new(MEMORY_SIZE, TrainerRng::from_rng(&mut rng))
+                .with_config(config.replay),
+        ),
+        policy: Mutex::new(vec![]),
+        policy_version: AtomicUsize::new(0),
+        stats: Mutex::new(None),
+        metrics: Mutex::new(MetricsLogger::for_run(metrics_dir, config.metrics, 0)),
+        env_steps: AtomicUsize::new(0),
+        episodes: AtomicUsize::new(0),
+        stop: AtomicBool::new(false),
+    };
+    shared.publish(learner.policy_net.valid());
+
+    let start = Instant::now();
+    let (learner, updates) = thread::scope(|scope| {
+        for index in 0..actor_config.num_actors.max(1) {
+            // Modules can be sent between threads but not shared
+            let opponents = opponents.to_vec();
+            let rng = TrainerRng::from_rng(&mut rng);
+            let shared = &shared;
+            scope.spawn(move || run_actor(index, shared, opponents, config, rng, actor_device));
+        }
+
+        let result = run_learner(learner, &shared, config, device, metrics_dir, start);
+        // Stop the actors even if the learner gave up early
+        shared.stop.store(true, Ordering::Relaxed);
+        result
+    });
+
+    let throughput = Throughput {
+        env_steps: shared.env_steps.load(Ordering::Relaxed),
+        updates,
+        episodes: shared.episodes.load(Ordering::Relaxed),
+        seconds: start.elapsed().as_secs_f64(),
+    };
+    (learner, throughput)
+}
+
+fn run_actor<A: Backend>(
+    index: usize,
+    shared: &Shared,
+    opponents: Vec<Entrant<A>>,
+    config: &TrainingConfig,
+    mut rng: TrainerRng,
+    device: &A::Device,
+) {
+    let mut opponents: Vec<Box<dyn Agent>> = opponents
+        .iter()
+        .map(|opponent| opponent.agent(OPPONENT_EPSILON, device))
+        .collect();
+    let mirror = opponents.is_empty();
+    let mut version = shared.policy_version.load(Ordering::Acquire);
+    let mut policy: DQN<A> = shared.fetch(device);
+
+    let mut env = TimeLimit::new(GameEnv::new(), MAX_EPISODE_ITERS);
+    let mut staged: Vec<Experience> = vec![];
+
+    while !shared.is_finished(config) {
+        let opponent_index = (!mirror).then(|| rng.random_range(0..opponents.len()));
+        let opponent_name = match opponent_index {
+            Some(opponent_index) => {
+                opponents[opponent_index].reset();
+                opponents[opponent_index].name()
+            }
+            None => "self".to_string(),
+        };
+        let [mut obs0, mut obs1] = env.reset(None);
+        let mut accumulators = [(); 2].map(|_| NStepAccumulator::new(config.n_step, config.gamma));
+        let mut tracker = EpisodeTracker::default();
+
+        loop {
+            let steps_done = shared.env_steps.fetch_add(1, Ordering::Relaxed) + 1;
+            let epsilon = get_epsilon(steps_done, config.epsilon_start, config.epsilon_decay);
+
+            let action0 = select_action(obs0, &policy, epsilon, NUM_ACTIONS, &mut rng, device);
+            let action1 = match opponent_index {
+                Some(opponent_index) => opponents[opponent_index].act(&obs1, &mut rng).to_int(),
+                None => select_action(obs1, &policy, epsilon, NUM_ACTIONS, &mut rng, device),
+            };
+
+            let step = env.step([action0, action1]);
+            let [obs0_next, obs1_next] = step.observations;
+            let stopping = shared.is_finished(config);
+            let episode_over = step.is_done() || stopping;
+
+            let sides = if mirror { 2 } else { 1 };
+            for (player, accumulator) in accumulators.iter_mut().enumerate().take(sides) {
+                let (state, action, next_state) = match player {
+                    0 => (obs0, action0, obs0_next),
+                    _ => (obs1, action1, obs1_next),
+                };
+                accumulator.push(
+                    Experience {
+                        state,
+                        action,
+                        reward: step.rewards[player],
+                        next_state,
+                        is_done: step.terminated,
+                        steps: 1,
+                    },
+                    episode_over,
+                    &mut staged,
+                );
+            }
+            tracker.step(step.rewards[0]);
+            obs0 = obs0_next;
+            obs1 = obs1_next;
+
+            if staged.len() >= config.actor_learner.push_batch || episode_over {
+                shared.buffer.lock().unwrap().extend(staged.drain(..));
+            }
+
+            let latest = shared.policy_version.load(Ordering::Acquire);
+            if latest != version {
+                version = latest;
+                policy = shared.fetch(device);
+            }
+
+            if step.is_done() {
+                let episode = shared.episodes.fetch_add(1, Ordering::Relaxed);
+                println!(
+                    "->> Actor {index} finished episode {episode} against {opponent_name} with final health 0:{} 1:{} Reward 0:{:.2} epsilon {epsilon}",
+                    step.info.health[0],
+                    step.info.health[1],
+                    tracker.episode_return(),
+                );
+                if let Some(stats) = *shared.stats.lock().unwrap() {
+                    tracker.train(stats);
+                }
+                let episode_metrics =
+                    tracker.finish(episode, 0, opponent_name, &step.info, epsilon);
+                shared
+                    .metrics
+                    .lock()
+                    .unwrap()
+                    .log(&episode_metrics)
+                    .expect("Should log metrics");
+                break;
+            }
+            if stopping {
+                return;
+            }
+        }
+    }
+}
+
+/// Trains until the actors have played enough, returns the learner and its update count
+fn run_learner<B: AutodiffBackend>(
+    mut learner: DqnLearner<B>,
+    shared: &Shared,
+    config: &TrainingConfig,
+    device: &B::Device,
+    metrics_dir: Option<&Path>,
+    start: Instant,
+) -> (DqnLearner<B>, usize) {
+    let actor_config = config.actor_learner;
+    let benchmark = Benchmark::<B::InnerBackend>::load(&config.benchmark, device)
+        .expect("Should load benchmark models");
+    let mut benchmark_state = BenchmarkState::default();
+    let report_interval = Duration::from_secs_f64(actor_config.report_interval);
+
+    let mut updates = 0;
+    // Env steps at the last hard target update and benchmark
+    let mut target_steps = 0;
+    let mut benchmark_steps = 0;
+    let mut last_report = (start, 0, 0);
+
+    while !shared.is_finished(config) {
+        let env_steps = shared.env_steps.load(Ordering::Relaxed);
+
+        let now = Instant::now();
+        let (report_time, report_steps, report_updates) = last_report;
+        if now - report_time >= report_interval {
+            let seconds = (now - report_time).as_secs_f64();
+            println!(
+                "   Throughput: {:.0} env steps/s {:.1} updates/s, buffer {}, episode {}",
+                (env_steps - report_steps) as f64 / seconds,
+                (updates - report_updates) as f64 / seconds,
+                shared.buffer.lock().unwrap().len(),
+                shared.episodes.load(Ordering::Relaxed),
+            );
+            last_report = (now, env_steps, updates);
+        }
+
+        if let Some(benchmark) = &benchmark
+            && benchmark.is_due(benchmark_steps, env_steps)
+        {
+            benchmark_steps = env_steps;
+            let result = benchmark.evaluate(
+                &Entrant::Model {
+                    name: "student".to_string(),
+                    model: learner.policy_net.valid(),
+                },
+                &mut benchmark_state,
+                env_steps,
+                shared.episodes.load(Ordering::Relaxed),
+                0,
+                config.seed,
+                device,
+            );
+            report(&result, &mut shared.metrics.lock().unwrap(), metrics_dir)
+                .expect("Should log benchmark");
+            if result.best
+                && let Some(dir) = metrics_dir
+            {
+                learner
+                    .save(&dir.join("best"))
+                    .expect("Should save best learner");
+            }
+        }
+
+        // Same replay ratio as the single-threaded trainers, at most
+        let ready = env_steps > TRAIN_START
+            && (updates + 1) * config.iters_per_training_step <= env_steps
+            && shared.buffer.lock().unwrap().len() >= config.batch_size;
+        if !ready {
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        // The buffer is only locked to sample and to update priorities, not for the step
+        let batch = shared
+            .buffer
+            .lock()
+            .unwrap()
+            .sample_batch_tensors::<B>(config.batch_size, device);
+        let (indices, pushes) = (batch.indices.clone(), batch.pushes);
+        let (policy_net, stats, td_errors) = train_on_batch(
+            learner.policy_net,
+            &learner.target_net,
+            batch,
+            &mut learner.optimizer,
+            config,
+        );
+        learner.policy_net = policy_net;
+        if let Some(td_errors) = td_errors {
+            shared
+                .buffer
+                .lock()
+                .unwrap()
+                .update_sampled_priorities(&indices, pushes, &td_errors);
+        }
+        *shared.stats.lock().unwrap() = Some(stats);
+        updates += 1;
+
+        match config.target_update {
+            TargetUpdate::Hard { interval } => {
+                let interval = interval.max(1);
+                if env_steps / interval > target_steps / interval {
+                    learner.update_target();
+                    target_steps = env_steps;
+                }
+            }
+            TargetUpdate::Polyak { tau } => learner.soft_update_target(tau),
+        }
+
+        if updates.is_multiple_of(actor_config.sync_interval.max(1)) {
+            shared.publish(learner.policy_net.valid());
+        }
+    }
+
+    (learner, updates)
+}
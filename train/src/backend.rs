@@ -0,0 +1,75 @@
+#[cfg(feature = "candle")]
+use burn::backend::Candle;
+#[cfg(feature = "ndarray")]
+use burn::backend::NdArray;
+#[cfg(feature = "wgpu")]
+use burn::backend::Wgpu;
+use burn::prelude::Backend;
+use clap::ValueEnum;
+
+#[cfg(not(any(feature = "ndarray", feature = "wgpu", feature = "candle")))]
+compile_error!("Enable at least one of the ndarray, wgpu or candle features");
+
+/// The burn backends compiled into this build. Wgpu needs a GPU adapter, ndarray and
+/// candle run on any CPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BackendKind {
+    #[cfg(feature = "ndarray")]
+    Ndarray,
+    #[cfg(feature = "wgpu")]
+    Wgpu,
+    #[cfg(feature = "candle")]
+    Candle,
+}
+
+impl Default for BackendKind {
+    /// The GPU when it's compiled in
+    #[allow(unreachable_code)]
+    fn default() -> Self {
+        #[cfg(feature = "wgpu")]
+        return Self::Wgpu;
+        #[cfg(feature = "ndarray")]
+        return Self::Ndarray;
+        #[cfg(feature = "candle")]
+        return Self::Candle;
+    }
+}
+
+impl std::fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self
+            .to_possible_value()
+            .expect("No backend is skipped on the command line");
+        f.write_str(value.get_name())
+    }
+}
+
+/// Work to run on whichever backend was picked. A closure can't be generic over the
+/// backend, so every entry point implements this instead.
+pub trait BackendTask {
+    type Output;
+
+    fn run<B: Backend>(self, device: B::Device) -> Self::Output;
+}
+
+impl BackendKind {
+    /// Examples don't take flags, they read `BOXING_BACKEND` instead
+    pub fn from_env() -> Self {
+        match std::env::var("BOXING_BACKEND") {
+            Ok(name) => Self::from_str(&name, true)
+                .unwrap_or_else(|_| panic!("Unknown or disabled backend {name}")),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn run<T: BackendTask>(self, task: T) -> T::Output {
+        match self {
+            #[cfg(feature = "ndarray")]
+            Self::Ndarray => task.run::<NdArray<f32>>(Default::default()),
+            #[cfg(feature = "wgpu")]
+            Self::Wgpu => task.run::<Wgpu<f32, i32>>(Default::default()),
+            #[cfg(feature = "candle")]
+            Self::Candle => task.run::<Candle<f32, i64>>(Default::default()),
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use core::{bots::ScriptedBot, model::load_saved_model};
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use burn::{
+    prelude::Backend,
+    record::{FullPrecisionSettings, NamedMpkFileRecorder},
+};
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    checkpoint::TrainerRng,
+    evaluation::{EVAL_EPSILON, Entrant, Standing, play_fight},
+    metrics::MetricsLogger,
+};
+
+/// Rating of a fresh student and of opponents missing from `BenchmarkConfig::ratings`
+pub const DEFAULT_ELO: f32 = 1000.0;
+
+/// Evaluation matches played every so often while training, against opponents that don't
+/// change so the results are comparable over the run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BenchmarkConfig {
+    /// Environment steps between evaluations, 0 turns them off
+    pub interval: usize,
+    /// Fights against each opponent per evaluation, sides alternating
+    pub games: usize,
+    pub models: Vec<PathBuf>,
+    pub bots: Vec<ScriptedBot>,
+    /// Fixed Elo of opponents by name, `bot_pressure` or a model's file stem
+    pub ratings: BTreeMap<String, f32>,
+    /// How far one fight's result moves the student's Elo
+    pub k_factor: f32,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        Self {
+            interval: 0,
+            games: 4,
+            models: vec![],
+            bots: ScriptedBot::ALL.to_vec(),
+            ratings: BTreeMap::new(),
+            k_factor: 16.0,
+        }
+    }
+}
+
+/// The student's rating and best score so far, saved with checkpoints so a resumed run
+/// carries on from them
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct BenchmarkState {
+    pub elo: f32,
+    pub best_score: Option<f32>,
+}
+
+impl Default for BenchmarkState {
+    fn default() -> Self {
+        Self {
+            elo: DEFAULT_ELO,
+            best_score: None,
+        }
+    }
+}
+
+/// One evaluation, a line of `benchmark.jsonl`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BenchmarkResult {
+    pub step: usize,
+    pub episode: usize,
+    /// Which learner, 0 unless two train side by side
+    pub player: usize,
+    /// Share of points won over every fight, draws count half
+    pub score: f32,
+    pub elo: f32,
+    /// Whether the score beat every earlier evaluation
+    pub best: bool,
+    /// The student's results against each opponent, under the opponent's name
+    pub opponents: Vec<Standing>,
+}
+
+/// Expected score of a player rated `rating` against one rated `opponent`
+pub fn expected_score(rating: f32, opponent: f32) -> f32 {
+    1.0 / (1.0 + 10f32.powf((opponent - rating) / 400.0))
+}
+
+/// The loaded opponents of a `BenchmarkConfig`
+pub struct Benchmark<B: Backend> {
+    config: BenchmarkConfig,
+    opponents: Vec<Entrant<B>>,
+}
+
+impl<B: Backend> Benchmark<B> {
+    /// `None` when evaluation is off or there's nobody to play
+    pub fn load(
+        config: &BenchmarkConfig,
+        device: &B::Device,
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if config.interval == 0 || (config.models.is_empty() && config.bots.is_empty()) {
+            return Ok(None);
+        }
+
+        let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+        let mut opponents = vec![];
+        for path in &config.models {
+            let name = path.file_stem().map_or_else(
+                || path.display().to_string(),
+                |stem| stem.to_string_lossy().to_string(),
+            );
+            let model = load_saved_model(&recorder, path.clone(), device)?;
+            opponents.push(Entrant::from_saved(name, model));
+        }
+        opponents.extend(config.bots.iter().copied().map(Entrant::Bot));
+
+        Ok(Some(Self {
+            config: config.clone(),
+            opponents,
+        }))
+    }
+
+    /// Whether a multiple of `interval` steps was passed going from `previous_steps` to
+    /// `steps_done`, so trainers that only check between episodes don't skip evaluations
+    pub fn is_due(&self, previous_steps: usize, steps_done: usize) -> bool {
+        steps_done / self.config.interval > previous_steps / self.config.interval
+    }
+
+    fn rating(&self, name: &str) -> f32 {
+        self.config
+            .ratings
+            .get(name)
+            .copied()
+            .unwrap_or(DEFAULT_ELO)
+    }
+
+    /// Plays `games` fights against every opponent, one thread each, and moves `state` on.
+    /// The fights are seeded from `seed` and `step` alone, so evaluating doesn't change the
+    /// course of training.
+    #[allow(clippy::too_many_arguments)]
+    pub fn evaluate(
+        &self,
+        student: &Entrant<B>,
+        state: &mut BenchmarkState,
+        step: usize,
+        episode: usize,
+        player: usize,
+        seed: u64,
+        device: &B::Device,
+    ) -> BenchmarkResult {
+        let mut rng = TrainerRng::seed_from_u64(seed ^ step as u64);
+        let games = self.config.games;
+
+        let opponents: Vec<Standing> = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .opponents
+                .iter()
+                .map(|opponent| {
+                    let mut rng = TrainerRng::from_rng(&mut rng);
+                    // Modules can be sent between threads but not shared
+                    let opponent = opponent.clone();
+                    let student = student.clone();
+                    scope.spawn(move || {
+                        let mut student = student.agent(EVAL_EPSILON, device);
+                        let mut agent = opponent.agent(EVAL_EPSILON, device);
+                        let mut standing = Standing {
+                            name: opponent.name(),
+                            ..Default::default()
+                        };
+                        for game in 0..games {
+                            let winner = if game.is_multiple_of(2) {
+                                play_fight(student.as_mut(), agent.as_mut(), &mut rng)
+                            } else {
+                                play_fight(agent.as_mut(), student.as_mut(), &mut rng)
+                                    .map(|winner| 1 - winner)
+                            };
+                            match winner {
+                                Some(0) => standing.wins += 1,
+                                Some(_) => standing.losses += 1,
+                                None => standing.draws += 1,
+                            }
+                        }
+                        standing
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Evaluation should finish"))
+                .collect()
+        });
+
+        // One update for the whole evaluation, so the order of the fights doesn't matter
+        let points: f32 = opponents
+            .iter()
+            .map(|standing| standing.wins as f32 + 0.5 * standing.draws as f32)
+            .sum();
+        let expected: f32 = opponents
+            .iter()
+            .map(|standing| {
+                standing.games() as f32 * expected_score(state.elo, self.rating(&standing.name))
+            })
+            .sum();
+        state.elo += self.config.k_factor * (points - expected);
+
+        let total_games: usize = opponents.iter().map(Standing::games).sum();
+        let score = points / total_games.max(1) as f32;
+        let best = state.best_score.is_none_or(|best_score| score > best_score);
+        if best {
+            state.best_score = Some(score);
+        }
+
+        BenchmarkResult {
+            step,
+            episode,
+            player,
+            score,
+            elo: state.elo,
+            best,
+            opponents,
+        }
+    }
+}
+
+/// Prints the result and logs it to `benchmark.jsonl` in `dir` and to TensorBoard
+pub fn report(
+    result: &BenchmarkResult,
+    metrics: &mut MetricsLogger,
+    dir: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "Benchmark of player {} at step {}: score {:.3} Elo {:.0}{}",
+        result.player,
+        result.step,
+        result.score,
+        result.elo,
+        if result.best { " (best so far)" } else { "" }
+    );
+    for standing in &result.opponents {
+        println!(
+            "    vs {:<20} {:>3} W {:>3} L {:>3} D",
+            standing.name, standing.wins, standing.losses, standing.draws
+        );
+    }
+
+    if let Some(dir) = dir {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join("benchmark.jsonl"))?;
+        writeln!(file, "{}", serde_json::to_string(result)?)?;
+    }
+    let player = result.player;
+    metrics.log_scalars(
+        result.episode,
+        &[
+            (&format!("player{player}/benchmark_score"), result.score),
+            (&format!("player{player}/benchmark_elo"), result.elo),
+        ],
+    )?;
+    Ok(())
+}
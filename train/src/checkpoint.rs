@@ -0,0 +1,151 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use burn::tensor::backend::AutodiffBackend;
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    benchmark::BenchmarkState,
+    replay_buffer::ReplayBuffer,
+    train::{DqnLearner, MEMORY_SIZE, TrainingConfig},
+};
+
+/// Same generator as `StdRng`, but its state can be written to disk
+pub type TrainerRng = ChaCha12Rng;
+
+/// Everything besides the networks that a run needs to continue where it stopped
+#[derive(Serialize, Deserialize)]
+pub struct TrainerState {
+    pub replay_buffer: ReplayBuffer,
+    pub rng: TrainerRng,
+    pub steps_done: usize,
+    pub iters: usize,
+    pub episode: usize,
+    /// Missing from checkpoints written before benchmarks existed
+    #[serde(default)]
+    pub benchmark: BenchmarkState,
+}
+
+impl TrainerState {
+    pub fn new(config: &TrainingConfig) -> Self {
+        let mut rng = TrainerRng::seed_from_u64(config.seed);
+        Self {
+            replay_buffer: ReplayBuffer::new(MEMORY_SIZE, TrainerRng::from_rng(&mut rng))
+                .with_config(config.replay),
+            rng,
+            steps_done: 0,
+            iters: 0,
+            episode: 0,
+            benchmark: BenchmarkState::default(),
+        }
+    }
+}
+
+/// A training run as laid out on disk:
+///
+/// ```text
+/// <dir>/policy.mpk     policy network
+/// <dir>/target.mpk     target network
+/// <dir>/optimizer.mpk  optimizer state
+/// <dir>/state.mpk      replay buffer, RNG and step counters
+/// <dir>/config.json    the run's TrainingConfig
+/// ```
+pub struct Checkpoint<B: AutodiffBackend> {
+    pub learner: DqnLearner<B>,
+    pub state: TrainerState,
+    pub config: TrainingConfig,
+}
+
+impl<B: AutodiffBackend> Checkpoint<B> {
+    /// A run that hasn't taken any steps yet
+    pub fn new(learner: DqnLearner<B>, config: TrainingConfig) -> Self {
+        Self {
+            learner,
+            state: TrainerState::new(&config),
+            config,
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        save_checkpoint(dir, &self.learner, &self.state, &self.config)
+    }
+
+    /// Finishes a save a crash interrupted first, see `save_checkpoint`
+    pub fn load(dir: &Path, device: &B::Device) -> Result<Self, Box<dyn std::error::Error>> {
+        finish_save(dir)?;
+        let config: TrainingConfig =
+            serde_json::from_str(&fs::read_to_string(dir.join("config.json"))?)?;
+        let learner = DqnLearner::load(dir, device, &config)?;
+        let state: TrainerState =
+            rmp_serde::decode::from_read(BufReader::new(File::open(dir.join("state.mpk"))?))?;
+        Ok(Self {
+            learner,
+            state,
+            config,
+        })
+    }
+}
+
+/// Files `save_checkpoint` writes, the run's directory also holds metrics and snapshots
+const CHECKPOINT_FILES: [&str; 5] = [
+    "policy.mpk",
+    "target.mpk",
+    "optimizer.mpk",
+    "state.mpk",
+    "config.json",
+];
+
+/// Where `save_checkpoint` writes the next checkpoint before moving it into place
+const STAGING_DIR: &str = ".saving";
+/// Written into the staging dir once every file is, the save counts from then on
+const COMMIT_MARKER: &str = "complete";
+
+/// Same as `Checkpoint::save` for a run that has been taken apart while training. Every file
+/// is written to `<dir>/.saving` and committed with a marker before any is moved into place.
+/// A crash before the marker leaves the previous checkpoint whole, one after it leaves the
+/// new one for `Checkpoint::load` to finish moving.
+pub fn save_checkpoint<B: AutodiffBackend>(
+    dir: &Path,
+    learner: &DqnLearner<B>,
+    state: &TrainerState,
+    config: &TrainingConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let staging = dir.join(STAGING_DIR);
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    learner.save(&staging)?;
+    let mut writer = BufWriter::new(File::create(staging.join("state.mpk"))?);
+    rmp_serde::encode::write(&mut writer, state)?;
+    writer.into_inner()?.sync_all()?;
+    fs::write(
+        staging.join("config.json"),
+        serde_json::to_string_pretty(config)?,
+    )?;
+    File::create(staging.join(COMMIT_MARKER))?.sync_all()?;
+
+    finish_save(dir)
+}
+
+/// Moves a committed save into place, or drops a save that never got its marker
+fn finish_save(dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let staging = dir.join(STAGING_DIR);
+    if !staging.exists() {
+        return Ok(());
+    }
+    if staging.join(COMMIT_MARKER).exists() {
+        // Files moved before a crash are already gone from the staging dir
+        for file in CHECKPOINT_FILES {
+            if staging.join(file).exists() {
+                fs::rename(staging.join(file), dir.join(file))?;
+            }
+        }
+    }
+    fs::remove_dir_all(staging)?;
+    Ok(())
+}
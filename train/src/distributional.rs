@@ -0,0 +1,141 @@
+use core::model::{DQN, DistributionalHead};
+
+use burn::{prelude::*, tensor::backend::AutodiffBackend};
+
+use crate::train::LossFunction;
+
+/// What a distributional network's batch came to, one row per transition
+pub(crate) struct DistributionalLosses<B: Backend> {
+    /// Mean of the taken action's return distribution, `[batch, 1]`
+    pub q_values: Tensor<B, 2>,
+    /// Mean of its target distribution, `[batch, 1]`
+    pub expected_q: Tensor<B, 2>,
+    /// Distance between the two distributions, `[batch, 1]`
+    pub losses: Tensor<B, 2>,
+}
+
+/// Losses between the return distributions of the actions taken and their targets, the
+/// distributions of `next_actions` from `target_net` discounted by `discounts` (0 past the
+/// end of a fight) and shifted by the rewards. The target network is the inference copy, so
+/// dropout only touches the online pass. Categorical heads ignore `loss`, quantile
+/// heads take `Mse` as the plain quantile loss and `Huber` as the quantile Huber loss.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn distributional_losses<B: AutodiffBackend>(
+    head: DistributionalHead,
+    policy_net: &DQN<B>,
+    target_net: &DQN<B::InnerBackend>,
+    states: Tensor<B, 2>,
+    actions: Tensor<B, 1, Int>,
+    rewards: Tensor<B, 1>,
+    next_states: Tensor<B::InnerBackend, 2>,
+    next_actions: Tensor<B, 2, Int>,
+    discounts: Tensor<B, 1>,
+    loss: LossFunction,
+) -> DistributionalLosses<B> {
+    let atoms = head.atoms();
+    let distribution = taken(policy_net.distribution(states), actions.unsqueeze_dim(1));
+    // Targets are constants as far as the optimizer is concerned
+    let next_distribution = taken(
+        Tensor::from_inner(target_net.distribution(next_states)),
+        next_actions,
+    );
+    let rewards: Tensor<B, 2> = rewards.unsqueeze_dim(1);
+    let discounts: Tensor<B, 2> = discounts.unsqueeze_dim(1);
+
+    match head {
+        DistributionalHead::Categorical {
+            atoms: _,
+            v_min,
+            v_max,
+        } => {
+            let device = distribution.device();
+            let support: Tensor<B, 2> = head
+                .support::<B>(&device)
+                .expect("Categorical heads should have a support")
+                .unsqueeze();
+            let target = project(
+                next_distribution,
+                rewards + discounts * support.clone(),
+                v_min,
+                v_max,
+            );
+
+            let q_values = (distribution.clone() * support.clone()).sum_dim(1);
+            let expected_q = (target.clone() * support).sum_dim(1);
+            let log_probabilities = distribution.clamp_min(1e-6).log();
+            let losses = (target * log_probabilities).sum_dim(1).neg();
+            DistributionalLosses {
+                q_values,
+                expected_q,
+                losses,
+            }
+        }
+        DistributionalHead::Quantile { quantiles: _ } => {
+            let target = rewards + discounts * next_distribution;
+            let [batch_size, _] = target.dims();
+            let device = target.device();
+
+            // Every target quantile against every predicted one, `[batch, predicted, target]`
+            let errors = target.clone().reshape([batch_size, 1, atoms])
+                - distribution.clone().reshape([batch_size, atoms, 1]);
+            let fractions = (Tensor::<B, 1, Int>::arange(0..atoms as i64, &device).float() * 2.0
+                + 1.0)
+                / (2 * atoms) as f32;
+            let fractions: Tensor<B, 3> = fractions.reshape([1, atoms, 1]);
+            // Quantiles below their target are pushed up by their fraction, those above
+            // it down by the rest
+            let weights = (fractions - errors.clone().lower_elem(0.0).float()).abs();
+            // Squared errors would pull every quantile towards the mean, so `Mse` falls back
+            // to plain quantile regression
+            let penalties = match loss {
+                LossFunction::Mse => errors.abs(),
+                LossFunction::Huber { delta } => {
+                    let errors = errors.abs();
+                    let quadratic = errors.clone().clamp_max(delta);
+                    let linear = errors - quadratic.clone();
+                    (quadratic.powf_scalar(2.0) * 0.5 + linear * delta) / delta
+                }
+            };
+            let losses = (weights * penalties)
+                .mean_dim(2)
+                .sum_dim(1)
+                .reshape([batch_size, 1]);
+
+            DistributionalLosses {
+                q_values: distribution.mean_dim(1),
+                expected_q: target.mean_dim(1),
+                losses,
+            }
+        }
+    }
+}
+
+/// The rows of `[batch, actions, atoms]` for `actions` `[batch, 1]`, as `[batch, atoms]`
+fn taken<B: Backend>(distribution: Tensor<B, 3>, actions: Tensor<B, 2, Int>) -> Tensor<B, 2> {
+    let [batch_size, _, atoms] = distribution.dims();
+    let indices = actions.reshape([batch_size, 1, 1]).repeat_dim(2, atoms);
+    distribution.gather(1, indices).reshape([batch_size, atoms])
+}
+
+/// Spreads each of `probabilities` over the two support atoms either side of where it moved
+/// to, `returns`, so the target lands back on the support
+fn project<B: Backend>(
+    probabilities: Tensor<B, 2>,
+    returns: Tensor<B, 2>,
+    v_min: f32,
+    v_max: f32,
+) -> Tensor<B, 2> {
+    let [batch_size, atoms] = probabilities.dims();
+    let spacing = (v_max - v_min) / (atoms - 1).max(1) as f32;
+    let position = ((returns.clamp(v_min, v_max) - v_min) / spacing).clamp(0.0, (atoms - 1) as f32);
+    let lower = position.clone().floor();
+    let upper = position.clone().ceil();
+    // A return right on an atom would otherwise give it to neither side
+    let on_atom = lower.clone().equal(upper.clone()).float();
+
+    let to_lower = probabilities.clone() * (upper.clone() - position.clone() + on_atom);
+    let to_upper = probabilities * (position - lower.clone());
+    Tensor::zeros([batch_size, atoms], &to_lower.device())
+        .scatter(1, lower.int(), to_lower)
+        .scatter(1, upper.int(), to_upper)
+}
@@ -0,0 +1,189 @@
+use core::{
+    agent::{Agent, DqnAgent, PolicyAgent, RecurrentAgent},
+    bots::ScriptedBot,
+    env::{BoxingEnv, GameEnv, TimeLimit},
+    model::{DQN, SavedModel},
+    policy::ActorCritic,
+    recurrent::RecurrentDQN,
+};
+use std::thread;
+
+use burn::prelude::Backend;
+use rand::{RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{checkpoint::TrainerRng, train::MAX_EPISODE_ITERS};
+
+/// Exploration of models while they're being evaluated
+pub const EVAL_EPSILON: f32 = 0.005;
+
+/// Plays one fight without training anyone. Returns the winner, `None` for a draw.
+pub fn play_fight(
+    player0: &mut dyn Agent,
+    player1: &mut dyn Agent,
+    rng: &mut dyn RngCore,
+) -> Option<usize> {
+    let mut env = TimeLimit::new(GameEnv::new(), MAX_EPISODE_ITERS);
+    player0.reset();
+    player1.reset();
+    let [mut obs0, mut obs1] = env.reset(None);
+    loop {
+        let action0 = player0.act(&obs0, rng).to_int();
+        let action1 = player1.act(&obs1, rng).to_int();
+        let step = env.step([action0, action1]);
+        if step.is_done() {
+            return step.info.winner;
+        }
+        [obs0, obs1] = step.observations;
+    }
+}
+
+/// A fighter that can be sent to another thread, unlike an `Agent`
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+pub enum Entrant<B: Backend> {
+    Model {
+        name: String,
+        model: DQN<B>,
+    },
+    /// An actor-critic trained by PPO
+    Policy {
+        name: String,
+        model: ActorCritic<B>,
+    },
+    Recurrent {
+        name: String,
+        model: RecurrentDQN<B>,
+    },
+    Bot(ScriptedBot),
+}
+
+impl<B: Backend> Entrant<B> {
+    /// Whichever kind of model was loaded
+    pub fn from_saved(name: impl Into<String>, model: SavedModel<B>) -> Self {
+        let name = name.into();
+        match model {
+            SavedModel::Dqn(model) => Self::Model { name, model },
+            SavedModel::ActorCritic(model) => Self::Policy { name, model },
+            SavedModel::Recurrent(model) => Self::Recurrent { name, model },
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::Model { name, .. } | Self::Policy { name, .. } | Self::Recurrent { name, .. } => {
+                name.clone()
+            }
+            Self::Bot(bot) => Agent::name(bot),
+        }
+    }
+
+    /// DQNs, recurrent or not, explore with `epsilon`, policies sample their actions and bots
+    /// play as scripted
+    pub fn agent(&self, epsilon: f32, device: &B::Device) -> Box<dyn Agent> {
+        match self {
+            Self::Model { name, model } => {
+                Box::new(DqnAgent::new(name.clone(), model.clone(), epsilon, device))
+            }
+            Self::Policy { name, model } => {
+                Box::new(PolicyAgent::new(name.clone(), model.clone(), false, device))
+            }
+            Self::Recurrent { name, model } => Box::new(RecurrentAgent::new(
+                name.clone(),
+                model.clone(),
+                epsilon,
+                device,
+            )),
+            Self::Bot(bot) => Box::new(*bot),
+        }
+    }
+}
+
+/// One entrant's results in a round robin
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Standing {
+    pub name: String,
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+}
+
+impl Standing {
+    pub fn games(&self) -> usize {
+        self.wins + self.losses + self.draws
+    }
+
+    /// Share of points won, draws count half
+    pub fn score(&self) -> f32 {
+        (self.wins as f32 + 0.5 * self.draws as f32) / self.games().max(1) as f32
+    }
+}
+
+/// Every pair of entrants plays `games` fights with sides alternating. Each entrant's
+/// thread plays the pairs where it comes first. Standings come back in entrant order.
+pub fn round_robin<B: Backend>(
+    entrants: &[Entrant<B>],
+    games: usize,
+    rng: &mut TrainerRng,
+    device: &B::Device,
+) -> Vec<Standing> {
+    let results: Vec<Vec<(usize, usize, Option<usize>)>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..entrants.len())
+            .map(|first| {
+                let mut rng = TrainerRng::from_rng(&mut *rng);
+                // Modules can be sent between threads but not shared
+                let entrants = entrants[first..].to_vec();
+                scope.spawn(move || {
+                    let mut agents: Vec<Box<dyn Agent>> = entrants
+                        .iter()
+                        .map(|entrant| entrant.agent(EVAL_EPSILON, device))
+                        .collect();
+                    let (head, rest) = agents.split_at_mut(1);
+                    let agent = head[0].as_mut();
+                    let mut results = vec![];
+                    for (offset, other) in rest.iter_mut().enumerate() {
+                        let second = first + 1 + offset;
+                        for game in 0..games {
+                            let winner = if game.is_multiple_of(2) {
+                                play_fight(agent, other.as_mut(), &mut rng)
+                            } else {
+                                play_fight(other.as_mut(), agent, &mut rng).map(|winner| 1 - winner)
+                            };
+                            results.push((first, second, winner));
+                        }
+                    }
+                    results
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Evaluation should finish"))
+            .collect()
+    });
+
+    let mut standings: Vec<Standing> = entrants
+        .iter()
+        .map(|entrant| Standing {
+            name: entrant.name(),
+            ..Default::default()
+        })
+        .collect();
+    for (first, second, winner) in results.into_iter().flatten() {
+        match winner {
+            Some(0) => {
+                standings[first].wins += 1;
+                standings[second].losses += 1;
+            }
+            Some(_) => {
+                standings[first].losses += 1;
+                standings[second].wins += 1;
+            }
+            None => {
+                standings[first].draws += 1;
+                standings[second].draws += 1;
+            }
+        }
+    }
+    standings
+}
@@ -0,0 +1,304 @@
+use core::{
+    agent::{Agent, DqnAgent},
+    env::{GameEnv, TimeLimit},
+    model::save_dqn,
+};
+use std::{fs, path::Path};
+
+use burn::{
+    module::AutodiffModule,
+    record::{FullPrecisionSettings, NamedMpkFileRecorder},
+    tensor::backend::AutodiffBackend,
+};
+use rand::distr::{Distribution, weighted::WeightedIndex};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    benchmark::Benchmark,
+    checkpoint::{Checkpoint, save_checkpoint},
+    metrics::MetricsLogger,
+    train::{MAX_EPISODE_ITERS, play_episode, run_benchmark},
+};
+
+/// How prioritized fictitious self-play weighs an opponent the learner beats `x` of the time
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PfspWeighting {
+    /// `(1 - x)^exponent`, focuses on the opponents the learner loses to
+    Hard { exponent: f32 },
+    /// `x (1 - x)`, focuses on even matchups
+    Variance,
+}
+
+impl PfspWeighting {
+    fn weight(&self, win_rate: f32) -> f32 {
+        match self {
+            Self::Hard { exponent } => (1.0 - win_rate).powf(*exponent),
+            Self::Variance => win_rate * (1.0 - win_rate),
+        }
+    }
+}
+
+/// Missing fields fall back to `Default`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LeagueConfig {
+    /// Episodes between frozen copies of the learner joining the pool
+    pub snapshot_interval: usize,
+    /// The oldest snapshot leaves once there are more, fixed opponents always stay
+    pub max_snapshots: usize,
+    pub weighting: PfspWeighting,
+    /// Exploration of frozen snapshots, like a teacher's
+    pub snapshot_epsilon: f32,
+}
+
+impl Default for LeagueConfig {
+    fn default() -> Self {
+        Self {
+            snapshot_interval: 200,
+            max_snapshots: 20,
+            weighting: PfspWeighting::Hard { exponent: 2.0 },
+            snapshot_epsilon: 0.05,
+        }
+    }
+}
+
+/// The learner's results against one opponent
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OpponentRecord {
+    pub name: String,
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+    /// Snapshots are frozen copies of the learner, the rest were passed in
+    pub snapshot: bool,
+}
+
+impl OpponentRecord {
+    pub fn games(&self) -> usize {
+        self.wins + self.losses + self.draws
+    }
+
+    /// Draws count as half a win. Opponents start at 0.5 with the estimate sharpening over
+    /// the first few games, so a new snapshot isn't written off after one lucky fight.
+    pub fn win_rate(&self) -> f32 {
+        (self.wins as f32 + 0.5 * self.draws as f32 + 1.0) / (self.games() as f32 + 2.0)
+    }
+}
+
+struct Opponent {
+    agent: Box<dyn Agent>,
+    record: OpponentRecord,
+}
+
+/// An opponent pool that grows with snapshots of the learner
+pub struct League {
+    opponents: Vec<Opponent>,
+    config: LeagueConfig,
+}
+
+impl League {
+    pub fn new(fixed: Vec<Box<dyn Agent>>, config: LeagueConfig) -> Self {
+        let opponents = fixed
+            .into_iter()
+            .map(|agent| Opponent {
+                record: OpponentRecord {
+                    name: agent.name(),
+                    ..Default::default()
+                },
+                agent,
+            })
+            .collect();
+        Self { opponents, config }
+    }
+
+    pub fn len(&self) -> usize {
+        self.opponents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.opponents.is_empty()
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &OpponentRecord> {
+        self.opponents.iter().map(|opponent| &opponent.record)
+    }
+
+    /// Adds a frozen snapshot, dropping the oldest one when the pool is full
+    pub fn add_snapshot(&mut self, agent: Box<dyn Agent>) {
+        let snapshots: Vec<usize> = (0..self.opponents.len())
+            .filter(|&index| self.opponents[index].record.snapshot)
+            .collect();
+        if snapshots.len() >= self.config.max_snapshots
+            && let Some(&oldest) = snapshots.first()
+        {
+            self.opponents.remove(oldest);
+        }
+
+        self.opponents.push(Opponent {
+            record: OpponentRecord {
+                name: agent.name(),
+                snapshot: true,
+                ..Default::default()
+            },
+            agent,
+        });
+    }
+
+    /// Picks the next opponent with prioritized fictitious self-play
+    pub fn sample(&self, rng: &mut impl rand::Rng) -> usize {
+        let weights: Vec<f32> = self
+            .records()
+            .map(|record| self.config.weighting.weight(record.win_rate()))
+            .collect();
+        match WeightedIndex::new(&weights) {
+            Ok(distribution) => distribution.sample(rng),
+            // Every weight is zero, e.g. the learner beats everyone under `Hard`
+            Err(_) => rng.random_range(0..self.opponents.len()),
+        }
+    }
+
+    /// Records a fight where the learner was player 0
+    pub fn record(&mut self, index: usize, winner: Option<usize>) {
+        let record = &mut self.opponents[index].record;
+        match winner {
+            Some(0) => record.wins += 1,
+            Some(_) => record.losses += 1,
+            None => record.draws += 1,
+        }
+    }
+
+    pub fn print_standings(&self) {
+        println!(
+            "{:<24} {:>6} {:>6} {:>6} {:>8}",
+            "opponent", "wins", "losses", "draws", "win rate"
+        );
+        for record in self.records() {
+            println!(
+                "{:<24} {:>6} {:>6} {:>6} {:>8.3}",
+                record.name,
+                record.wins,
+                record.losses,
+                record.draws,
+                record.win_rate()
+            );
+        }
+    }
+
+    /// Writes the per-opponent records as JSON
+    pub fn save_standings(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let records: Vec<&OpponentRecord> = self.records().collect();
+        fs::write(path, serde_json::to_string_pretty(&records)?)?;
+        Ok(())
+    }
+}
+
+/// Trains a student against a league of `fixed` opponents plus snapshots of itself, taken
+/// every `snapshot_interval` episodes starting with the untrained student.
+///
+/// With a `checkpoint_dir` the run is checkpointed like `train_against`, snapshots are saved
+/// as models under `snapshots/` so they can be used as teachers later, the standings go
+/// to `league.json` and episodes to the metrics files.
+pub fn train_league<B: AutodiffBackend>(
+    checkpoint: Checkpoint<B>,
+    fixed: Vec<Box<dyn Agent>>,
+    device: &B::Device,
+    checkpoint_dir: Option<&Path>,
+) -> (Checkpoint<B>, League) {
+    let Checkpoint {
+        learner: mut student,
+        mut state,
+        config,
+    } = checkpoint;
+    let league_config = config.league;
+    let mut league = League::new(fixed, league_config);
+
+    let mut env = TimeLimit::new(GameEnv::new(), MAX_EPISODE_ITERS);
+    let mut metrics = MetricsLogger::for_run(checkpoint_dir, config.metrics, state.episode);
+    let benchmark = Benchmark::<B::InnerBackend>::load(&config.benchmark, device)
+        .expect("Should load benchmark models");
+
+    while state.episode < config.num_episodes && state.iters <= config.max_iters {
+        let episode = state.episode;
+        let previous_steps = state.steps_done;
+
+        if episode % league_config.snapshot_interval.max(1) == 0 {
+            let name = format!("snapshot{episode:06}");
+            // Snapshots only play, so they drop the autodiff graph
+            let policy_net = student.policy_net.valid();
+            if let Some(dir) = checkpoint_dir {
+                let snapshots_dir = dir.join("snapshots");
+                fs::create_dir_all(&snapshots_dir).expect("Should create snapshots dir");
+                let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+                save_dqn(policy_net.clone(), &recorder, snapshots_dir.join(&name))
+                    .expect("Should save snapshot");
+            }
+            league.add_snapshot(Box::new(DqnAgent::new(
+                name,
+                policy_net,
+                league_config.snapshot_epsilon,
+                device,
+            )));
+        }
+
+        let index = league.sample(&mut state.rng);
+        let opponent = &mut league.opponents[index];
+        println!(
+            "Beginning episode {episode} against {} (win rate {:.3})",
+            opponent.record.name,
+            opponent.record.win_rate()
+        );
+        let result = play_episode(
+            &mut student,
+            opponent.agent.as_mut(),
+            &mut state,
+            &config,
+            &mut env,
+            &mut metrics,
+            device,
+        );
+        // A fight cut short by `max_iters` says nothing about the matchup
+        if let Some(winner) = result {
+            league.record(index, winner);
+        }
+
+        state.episode += 1;
+
+        run_benchmark(
+            benchmark.as_ref(),
+            previous_steps,
+            &student,
+            &mut state,
+            &config,
+            &mut metrics,
+            checkpoint_dir,
+            device,
+        );
+
+        if let Some(dir) = checkpoint_dir
+            && config.checkpoint_interval > 0
+            && state.episode % config.checkpoint_interval == 0
+        {
+            save_checkpoint(dir, &student, &state, &config).expect("Should save checkpoint");
+            league
+                .save_standings(&dir.join("league.json"))
+                .expect("Should save standings");
+            league.print_standings();
+        }
+    }
+
+    if let Some(dir) = checkpoint_dir {
+        save_checkpoint(dir, &student, &state, &config).expect("Should save checkpoint");
+        league
+            .save_standings(&dir.join("league.json"))
+            .expect("Should save standings");
+    }
+
+    (
+        Checkpoint {
+            learner: student,
+            state,
+            config,
+        },
+        league,
+    )
+}
@@ -1,2 +1,18 @@
+pub mod actor_learner;
+pub mod backend;
+pub mod benchmark;
+pub mod checkpoint;
+pub mod distributional;
+pub mod evaluation;
+pub mod league;
+pub mod metrics;
+pub mod pbt;
+pub mod plot;
+pub mod ppo;
+pub mod recurrent;
 pub mod replay_buffer;
+pub mod sequence_replay;
+pub mod sum_tree;
+pub mod sweep;
+pub mod tensorboard;
 pub mod train;
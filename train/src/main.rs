@@ -1,160 +1,703 @@
 #![recursion_limit = "256"]
 
 use core::{
-    OBSERVATION_LENGTH,
-    model::{DQN, DQNConfig},
+    agent::Agent,
+    bots::ScriptedBot,
+    model::{DQN, Risk, SavedModel, load_dqn, load_saved_model, save_dqn},
+    policy::{ActorCritic, load_actor_critic},
+    recurrent::{RecurrentDQN, load_recurrent_dqn},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
 };
-use std::path::PathBuf;
-
-pub mod replay_buffer;
-pub mod train;
-use crate::train::{train, train_against};
 
+#[cfg(feature = "ndarray")]
+use burn::backend::NdArray;
 use burn::{
-    backend::{Autodiff, Wgpu},
-    module::Module,
-    optim::AdamConfig,
+    backend::Autodiff,
     prelude::Backend,
-    record::{FullPrecisionSettings, NamedMpkFileRecorder, Recorder},
-    tensor::Device,
+    record::{BinFileRecorder, FullPrecisionSettings, NamedMpkFileRecorder},
+};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use train::{
+    actor_learner::train_actor_learner,
+    backend::{BackendKind, BackendTask},
+    checkpoint::{Checkpoint, TrainerRng},
+    evaluation::{Entrant, Standing, round_robin},
+    league::train_league,
+    metrics::read_metrics,
+    pbt::train_population,
+    plot::{Metric, render_svg},
+    ppo::train_ppo,
+    recurrent::train_recurrent,
+    sweep::{SweepRun, SweepSpec, Trainer, TrainerSpec, run_sweep},
+    train::{DqnLearner, TrainingConfig, train, train_against},
 };
 
-use crate::train::TrainingConfig;
+const MODELS_DIR: &str = "./assets/models";
+const BINARY_MODELS_DIR: &str = "./assets/binary_models";
+const TRAINING_CONFIGS_DIR: &str = "./assets/training_configs";
+const CHECKPOINTS_DIR: &str = "./assets/checkpoints";
+const TEACHER_EPSILON: f32 = 0.05;
+const MODEL_PREFIXES: [&str; 3] = ["dqn", "ppo", "rdqn"];
 
-fn train_2(model_name0: &str, model_name1: &str, config: &TrainingConfig) {
-    let start = std::time::Instant::now();
+#[derive(Parser)]
+#[command(about = "Train, resume and export boxing models")]
+struct Cli {
+    /// Defaults to wgpu when it's compiled in, use ndarray on machines without a GPU
+    #[arg(long, global = true, value_enum, default_value_t)]
+    backend: BackendKind,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Train a fresh pair of models against each other
+    Selfplay {
+        /// TrainingConfig JSON, see assets/training_configs
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Where to save both models, defaults to the next free numbers in assets/models
+        #[arg(long, num_args = 2, value_names = ["MODEL0", "MODEL1"])]
+        output: Option<Vec<PathBuf>>,
+    },
+    /// Train a model against a fixed pool of models and scripted bots
+    Against(StudentArgs),
+    /// Train a model against a league of models, bots and its own past snapshots, picking
+    /// opponents it struggles with more often. Can't be resumed.
+    League(StudentArgs),
+    /// Train a model on experience from several actor threads playing on the CPU while one
+    /// learner updates it, plays itself without teachers or bots. Can't be resumed.
+    ActorLearner(StudentArgs),
+    /// Train an actor-critic with PPO against teachers and bots, or against itself without
+    /// any. Its student is an earlier PPO model. Can't be resumed.
+    Ppo(StudentArgs),
+    /// Train a recurrent DQN on replayed sequences of whole fights against teachers and bots,
+    /// or against itself without any. Its student is an earlier recurrent model. Can't be
+    /// resumed.
+    Recurrent(StudentArgs),
+    /// Population-based training of several models at once, the weakest copy the strongest
+    /// and perturb their hyperparameters. Can't be resumed.
+    Pbt {
+        /// TrainingConfig JSON, its `pbt` section sizes the population
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Model every member starts from, fresh networks when omitted
+        #[arg(long)]
+        student: Option<PathBuf>,
+        /// Opponent model besides the population, can be repeated
+        #[arg(long = "teacher")]
+        teachers: Vec<PathBuf>,
+        /// Scripted opponent by name, can be repeated
+        #[arg(long = "bot")]
+        bots: Vec<String>,
+        /// Where the lineage log goes, defaults to assets/checkpoints/pbt_<first model name>
+        #[arg(long)]
+        run_dir: Option<PathBuf>,
+    },
+    /// Train every config of a grid or random search spec, then rank the models in a round
+    /// robin with the scripted bots as yardsticks
+    Sweep { spec: PathBuf },
+    /// Render learning curves from a run's metrics.csv or metrics.jsonl to SVG
+    Plot {
+        metrics: PathBuf,
+        /// Defaults to the metrics file with an .svg extension
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Panel to draw, can be repeated
+        #[arg(long = "metric", value_enum, default_values_t = [Metric::Return, Metric::Win, Metric::Loss, Metric::MeanQ])]
+        metrics_to_plot: Vec<Metric>,
+        /// Episodes in the moving average
+        #[arg(long, default_value_t = 50)]
+        smoothing: usize,
+    },
+    /// Continue an interrupted `against` run from its checkpoint directory
+    Resume { checkpoint_dir: PathBuf },
+    /// Copy a distributional DQN with another attitude to risk, making a cautious or
+    /// reckless variant of the same fighter
+    Risk {
+        model: PathBuf,
+        #[arg(value_enum)]
+        attitude: Attitude,
+        /// Share of the worst (cautious) or best (reckless) returns actions are picked by
+        #[arg(long, default_value_t = 0.25)]
+        alpha: f32,
+        /// Defaults to <model name>_<attitude>.mpk next to the model
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Convert a model to the binary format embedded by the web build
+    Export {
+        model: PathBuf,
+        /// Defaults to assets/binary_models/<model name>.bin
+        output: Option<PathBuf>,
+    },
+}
 
-    type MyBackend = Wgpu<f32, i32>;
-    type MyAutodiffBackend = Autodiff<MyBackend>;
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Attitude {
+    Neutral,
+    Cautious,
+    Reckless,
+}
 
-    let device = Default::default();
+#[derive(Args)]
+struct StudentArgs {
+    /// TrainingConfig JSON, see assets/training_configs
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Model to keep training, a fresh network when omitted
+    #[arg(long)]
+    student: Option<PathBuf>,
+    /// Opponent model of either kind, can be repeated
+    #[arg(long = "teacher")]
+    teachers: Vec<PathBuf>,
+    /// Scripted opponent by name, can be repeated
+    #[arg(long = "bot")]
+    bots: Vec<String>,
+    /// Defaults to the next free number in assets/models
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Defaults to assets/checkpoints/<output name>
+    #[arg(long)]
+    checkpoint_dir: Option<PathBuf>,
+}
 
-    let (dqn0, dqn1) = train::<MyAutodiffBackend>(&device, config);
+/// What `resume` needs besides the checkpoint itself, stored next to it
+#[derive(Serialize, Deserialize)]
+struct AgainstRun {
+    teachers: Vec<PathBuf>,
+    bots: Vec<ScriptedBot>,
+    output: PathBuf,
+}
 
+fn load_model<B: Backend>(
+    model_path: &Path,
+    device: &B::Device,
+) -> Result<DQN<B>, Box<dyn std::error::Error>> {
     let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
-    dqn0.save_file(
-        PathBuf::from(format!("./assets/models/{model_name0}")),
-        &recorder,
-    )
-    .expect("Should save");
-    dqn1.save_file(
-        PathBuf::from(format!("./assets/models/{model_name1}")),
+    Ok(load_dqn(&recorder, model_path.to_path_buf(), device)?)
+}
+
+/// A DQN or an actor-critic, whichever the file holds
+fn load_any_model<B: Backend>(
+    model_path: &Path,
+    device: &B::Device,
+) -> Result<SavedModel<B>, Box<dyn std::error::Error>> {
+    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    Ok(load_saved_model(
         &recorder,
-    )
-    .expect("Should save");
+        model_path.to_path_buf(),
+        device,
+    )?)
+}
 
-    // Save training configs
-    let config_json = serde_json::to_string_pretty(&config).expect("Should serialize config");
-    std::fs::write(
-        PathBuf::from(format!("./assets/training_configs/{model_name0}.json")),
-        &config_json,
-    )
-    .expect("Should save config");
-    std::fs::write(
-        PathBuf::from(format!("./assets/training_configs/{model_name1}.json")),
+fn load_config(path: Option<&Path>) -> TrainingConfig {
+    match path {
+        Some(path) => {
+            let json = fs::read_to_string(path).expect("Should read config");
+            serde_json::from_str(&json).expect("Should parse config")
+        }
+        None => TrainingConfig::default(),
+    }
+}
+
+/// Models are numbered `dqnNNN.mpk`, `ppoNNN.mpk` and `rdqnNNN.mpk` alike, this picks the
+/// numbers after the highest one taken
+fn next_free_model_paths(prefix: &str, count: usize) -> Vec<PathBuf> {
+    let next_index = fs::read_dir(MODELS_DIR)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| {
+                    let file_name = entry.file_name().to_string_lossy().to_string();
+                    let stem = file_name.strip_suffix(".mpk")?;
+                    MODEL_PREFIXES
+                        .iter()
+                        .find_map(|prefix| stem.strip_prefix(prefix))?
+                        .parse::<usize>()
+                        .ok()
+                })
+                .max()
+                .map_or(0, |index| index + 1)
+        })
+        .unwrap_or(0);
+
+    (next_index..next_index + count)
+        .map(|index| PathBuf::from(format!("{MODELS_DIR}/{prefix}{index:03}.mpk")))
+        .collect()
+}
+
+fn model_name(model_path: &Path) -> String {
+    model_path
+        .file_stem()
+        .expect("Model path should have a file name")
+        .to_string_lossy()
+        .to_string()
+}
+
+fn save_model<B: Backend>(model: DQN<B>, model_path: &Path, config: &TrainingConfig) {
+    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    save_dqn(model, &recorder, model_path.to_path_buf()).expect("Should save model");
+    save_config(model_path, config);
+}
+
+fn save_any_model<B: Backend>(model: SavedModel<B>, model_path: &Path, config: &TrainingConfig) {
+    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    model
+        .save(&recorder, model_path.to_path_buf())
+        .expect("Should save model");
+    save_config(model_path, config);
+}
+
+fn save_config(model_path: &Path, config: &TrainingConfig) {
+    // Keep the hyperparameters next to every model
+    let config_json = serde_json::to_string_pretty(config).expect("Should serialize config");
+    fs::write(
+        PathBuf::from(format!(
+            "{TRAINING_CONFIGS_DIR}/{}.json",
+            model_name(model_path)
+        )),
         &config_json,
     )
     .expect("Should save config");
 
+    println!("Saved {}", model_path.display());
+}
+
+fn selfplay<B: Backend>(config: TrainingConfig, output: Option<Vec<PathBuf>>, device: &B::Device) {
+    let start = std::time::Instant::now();
+    let output = output.unwrap_or_else(|| next_free_model_paths("dqn", 2));
+    // Weight init and dropout draw from the backend's own RNG
+    B::seed(config.seed);
+
+    let learners = [(); 2].map(|_| {
+        let policy_net = config.model.init(device);
+        DqnLearner::<Autodiff<B>>::new(policy_net, device, &config)
+    });
+    // Both players' metrics go next to the first model's checkpoint
+    let metrics_dir = PathBuf::from(format!("{CHECKPOINTS_DIR}/{}", model_name(&output[0])));
+    let learners = train(learners, device, &config, Some(&metrics_dir));
+
+    for (learner, model_path) in learners.into_iter().zip(&output) {
+        learner
+            .save(&PathBuf::from(format!(
+                "{CHECKPOINTS_DIR}/{}",
+                model_name(model_path)
+            )))
+            .expect("Should save checkpoint");
+        save_model(learner.policy_net, model_path, &config);
+    }
+
     println!("Total time: {:?}", start.elapsed());
 }
 
-//fn main() {
-//    let mut config = TrainingConfig {
-//        optimizer: AdamConfig::new(),
-//        gamma: 0.999,
-//        batch_size: 128,
-//        learning_rate: 0.0005,
-//        num_episodes: 20000,
-//        max_iters: 20000,
-//        epsilon_decay: 0.0007,
-//        seed: 456,
-//        iters_per_training_step: 8,
-//    };
-//
-//    let starting_index: usize = 64;
-//
-//    train_2(
-//        &format!("dqn{:03}", starting_index),
-//        &format!("dqn{:03}", starting_index + 1),
-//        &config,
-//    );
-//    config.seed += 1;
-//    train_2(
-//        &format!("dqn{:03}", starting_index + 2),
-//        &format!("dqn{:03}", starting_index + 3),
-//        &config,
-//    );
-//    config.seed += 1;
-//    train_2(
-//        &format!("dqn{:03}", starting_index + 4),
-//        &format!("dqn{:03}", starting_index + 5),
-//        &config,
-//    );
-//    config.seed += 1;
-//    train_2(
-//        &format!("dqn{:03}", starting_index + 6),
-//        &format!("dqn{:03}", starting_index + 7),
-//        &config,
-//    );
-//}
+fn parse_bots(names: &[String]) -> Vec<ScriptedBot> {
+    names
+        .iter()
+        .map(|name| ScriptedBot::from_name(name).unwrap_or_else(|| panic!("Unknown bot {name}")))
+        .collect()
+}
 
-fn load_model<B: Backend>(
-    model_path: &PathBuf,
+/// Teachers only play, so they skip autodiff
+fn build_entrants<B: Backend>(
+    teachers: &[PathBuf],
+    bots: &[ScriptedBot],
     device: &B::Device,
-) -> Result<DQN<B>, Box<dyn std::error::Error>> {
-    let record =
-        NamedMpkFileRecorder::<FullPrecisionSettings>::new().load(model_path.clone(), device)?;
-    let model: DQN<B> = DQNConfig::new(23, 24).init(device).load_record(record);
-    Ok(model)
+) -> Vec<Entrant<B>> {
+    let models = teachers.iter().map(|path| {
+        let model = load_any_model(path, device).expect("Should load teacher model");
+        Entrant::from_saved(model_name(path), model)
+    });
+    models
+        .chain(bots.iter().copied().map(Entrant::Bot))
+        .collect()
 }
 
-fn main() {
-    let config = TrainingConfig {
-        optimizer: AdamConfig::new(),
-        gamma: 0.999,
-        batch_size: 128,
-        learning_rate: 0.0001,
-        num_episodes: 20000,
-        max_iters: 200_000,
-        epsilon_decay: 0.0001,
-        epsilon_start: 1.0,
-        seed: 26,
-        iters_per_training_step: 8,
+/// Teachers only play, so they skip autodiff
+fn build_teachers<B: Backend>(run: &AgainstRun, device: &B::Device) -> Vec<Box<dyn Agent>> {
+    let mut teachers: Vec<Box<dyn Agent>> = vec![];
+    for model_path in &run.teachers {
+        let teacher = load_any_model::<B>(model_path, device).expect("Should load teacher model");
+        teachers.push(teacher.into_agent(model_name(model_path), TEACHER_EPSILON, device));
+    }
+    for bot in &run.bots {
+        teachers.push(Box::new(*bot));
+    }
+    teachers
+}
+
+/// Sets up a fresh run from the command line, creating its checkpoint dir
+fn start_run<B: Backend>(
+    args: StudentArgs,
+    device: &B::Device,
+) -> (Checkpoint<Autodiff<B>>, AgainstRun, PathBuf) {
+    let config = load_config(args.config.as_deref());
+    // Weight init and dropout draw from the backend's own RNG
+    B::seed(config.seed);
+
+    let bots = parse_bots(&args.bots);
+    let output = args
+        .output
+        .unwrap_or_else(|| next_free_model_paths("dqn", 1).remove(0));
+    let checkpoint_dir = args
+        .checkpoint_dir
+        .unwrap_or_else(|| PathBuf::from(format!("{CHECKPOINTS_DIR}/{}", model_name(&output))));
+    let run = AgainstRun {
+        teachers: args.teachers,
+        bots,
+        output,
     };
 
-    type MyBackend = Wgpu<f32, i32>;
-    type MyAutodiffBackend = Autodiff<MyBackend>;
+    let student_net = match args.student {
+        Some(student) => load_model(&student, device).expect("Should load student"),
+        None => config.model.init(device),
+    };
+    let student = DqnLearner::<Autodiff<B>>::new(student_net, device, &config);
+    fs::create_dir_all(&checkpoint_dir).expect("Should create checkpoint dir");
 
-    let device: Device<MyAutodiffBackend> = Default::default();
+    (Checkpoint::new(student, config), run, checkpoint_dir)
+}
 
-    //let student_net: DQN<MyAutodiffBackend> = DQNConfig::new(OBSERVATION_LENGTH, 24).init(&device);
-    let student_index = 74;
-    let student_net: DQN<MyAutodiffBackend> = load_model(
-        &PathBuf::from(format!("./assets/models/dqn{student_index:03}.mpk")),
-        &device,
-    )
-    .expect("Should load student element");
-
-    let mut teacher_nets = vec![];
-    let teacher_net_indices = vec![4, 0, 27, 38, 25, 42];
-
-    for i in teacher_net_indices {
-        let teacher_net = load_model(
-            &PathBuf::from(format!("./assets/models/dqn{i:03}.mpk")),
-            &device,
-        )
-        .expect("Should load teacher model");
-        teacher_nets.push(teacher_net);
+fn run_against<B: Backend>(
+    checkpoint: Checkpoint<Autodiff<B>>,
+    run: &AgainstRun,
+    checkpoint_dir: &Path,
+    device: &B::Device,
+) {
+    let teachers = build_teachers::<B>(run, device);
+    assert!(!teachers.is_empty(), "Pass at least one --teacher or --bot");
+    let checkpoint = train_against(checkpoint, teachers, device, Some(checkpoint_dir));
+    save_model(
+        checkpoint.learner.policy_net,
+        &run.output,
+        &checkpoint.config,
+    );
+}
+
+/// Actors run on ndarray when it's compiled in, otherwise on the training backend
+fn actor_learner<B: Backend>(args: StudentArgs, device: &B::Device) {
+    #[cfg(feature = "ndarray")]
+    run_actor_learner::<B, NdArray<f32>>(args, device, &Default::default());
+    #[cfg(not(feature = "ndarray"))]
+    run_actor_learner::<B, B>(args, device, device);
+}
+
+fn run_actor_learner<B: Backend, A: Backend>(
+    args: StudentArgs,
+    device: &B::Device,
+    actor_device: &A::Device,
+) {
+    let (checkpoint, run, checkpoint_dir) = start_run::<B>(args, device);
+    let opponents = build_entrants::<A>(&run.teachers, &run.bots, actor_device);
+
+    let (learner, throughput) = train_actor_learner(
+        checkpoint.learner,
+        &opponents,
+        &checkpoint.config,
+        device,
+        actor_device,
+        Some(&checkpoint_dir),
+    );
+    println!("Throughput: {throughput}");
+    learner
+        .save(&checkpoint_dir)
+        .expect("Should save checkpoint");
+    save_model(learner.policy_net, &run.output, &checkpoint.config);
+}
+
+/// Sets up a run of a trainer that doesn't checkpoint, the student is left to the caller
+/// since each trainer has its own kind of model
+fn start_plain_run<B: Backend>(
+    args: StudentArgs,
+    prefix: &str,
+) -> (TrainingConfig, AgainstRun, PathBuf, Option<PathBuf>) {
+    let config = load_config(args.config.as_deref());
+    // Weight init draws from the backend's own RNG
+    B::seed(config.seed);
+
+    let bots = parse_bots(&args.bots);
+    let output = args
+        .output
+        .unwrap_or_else(|| next_free_model_paths(prefix, 1).remove(0));
+    let checkpoint_dir = args
+        .checkpoint_dir
+        .unwrap_or_else(|| PathBuf::from(format!("{CHECKPOINTS_DIR}/{}", model_name(&output))));
+    fs::create_dir_all(&checkpoint_dir).expect("Should create checkpoint dir");
+    let run = AgainstRun {
+        teachers: args.teachers,
+        bots,
+        output,
+    };
+    (config, run, checkpoint_dir, args.student)
+}
+
+fn ppo<B: Backend>(args: StudentArgs, device: &B::Device) {
+    let start = std::time::Instant::now();
+    let (config, run, checkpoint_dir, student) = start_plain_run::<B>(args, "ppo");
+
+    let student: ActorCritic<Autodiff<B>> = match student {
+        Some(student) => {
+            let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+            load_actor_critic(&recorder, student, device).expect("Should load student")
+        }
+        None => config.ppo.model.init(device),
+    };
+    let opponents = build_teachers::<B>(&run, device);
+    let model = train_ppo(student, opponents, device, &config, Some(&checkpoint_dir));
+    save_any_model(SavedModel::ActorCritic(model), &run.output, &config);
+
+    println!("Total time: {:?}", start.elapsed());
+}
+
+fn recurrent<B: Backend>(args: StudentArgs, device: &B::Device) {
+    let start = std::time::Instant::now();
+    let (config, run, checkpoint_dir, student) = start_plain_run::<B>(args, "rdqn");
+
+    let student: RecurrentDQN<Autodiff<B>> = match student {
+        Some(student) => {
+            let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+            load_recurrent_dqn(&recorder, student, device).expect("Should load student")
+        }
+        None => config.recurrent.model.init(device),
+    };
+    let opponents = build_teachers::<B>(&run, device);
+    let model = train_recurrent(student, opponents, device, &config, Some(&checkpoint_dir));
+    save_any_model(SavedModel::Recurrent(model), &run.output, &config);
+
+    println!("Total time: {:?}", start.elapsed());
+}
+
+fn sweep<B: Backend>(spec_path: &Path, device: &B::Device) {
+    let spec_json = fs::read_to_string(spec_path).expect("Should read sweep spec");
+    let spec: SweepSpec = serde_json::from_str(&spec_json).expect("Should parse sweep spec");
+    let runs = spec.runs().expect("Should expand sweep spec");
+
+    // Numbers are handed out up front so each run's models sit next to each other
+    let outputs: Vec<Vec<PathBuf>> =
+        next_free_model_paths("dqn", runs.len() * spec.models_per_run())
+            .chunks(spec.models_per_run())
+            .map(<[PathBuf]>::to_vec)
+            .collect();
+    for (run, models) in runs.iter().zip(&outputs) {
+        let names: Vec<String> = models.iter().map(|path| model_name(path)).collect();
+        println!("{}: {}", names.join(", "), describe_overrides(run));
     }
 
-    let trained_model = train_against(student_net, teacher_nets, &device, &config);
-    let new_model_index = 77;
-    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
-    trained_model
-        .save_file(
-            PathBuf::from(format!("./assets/models/dqn{new_model_index:03}.mpk")),
-            &recorder,
-        )
-        .expect("Should save");
+    let trainer = match &spec.trainer {
+        TrainerSpec::Selfplay => Trainer::Selfplay,
+        TrainerSpec::Against {
+            student,
+            teachers,
+            bots,
+        } => Trainer::Against {
+            student: student
+                .as_ref()
+                .map(|path| load_model(path, device).expect("Should load student")),
+            opponents: build_entrants::<B>(teachers, bots, device),
+        },
+    };
+    run_sweep::<Autodiff<B>>(&runs, &trainer, spec.threads, device, &|index, models| {
+        for (model, output) in models.into_iter().zip(&outputs[index]) {
+            save_model(model, output, &runs[index].config);
+        }
+    });
+
+    let mut entrants: Vec<Entrant<B>> = outputs
+        .iter()
+        .flatten()
+        .map(|path| Entrant::Model {
+            name: model_name(path),
+            model: load_model(path, device).expect("Should load swept model"),
+        })
+        .collect();
+    entrants.extend(ScriptedBot::ALL.map(Entrant::Bot));
+    let mut rng = TrainerRng::seed_from_u64(spec.seed);
+    let standings = round_robin(&entrants, spec.eval_games, &mut rng, device);
+
+    // Bots have no run, their overrides column stays empty
+    let mut ranking: Vec<(Standing, String)> = standings
+        .into_iter()
+        .enumerate()
+        .map(|(index, standing)| {
+            let run = runs.get(index / spec.models_per_run());
+            (standing, run.map(describe_overrides).unwrap_or_default())
+        })
+        .collect();
+    ranking.sort_by(|(a, _), (b, _)| b.score().total_cmp(&a.score()));
+
+    println!(
+        "\n{:>4} {:<20} {:>6} {:>5} {:>6} {:>5}  overrides",
+        "rank", "model", "score", "wins", "losses", "draws"
+    );
+    for (rank, (standing, overrides)) in ranking.iter().enumerate() {
+        println!(
+            "{:>4} {:<20} {:>6.3} {:>5} {:>6} {:>5}  {overrides}",
+            rank + 1,
+            standing.name,
+            standing.score(),
+            standing.wins,
+            standing.losses,
+            standing.draws,
+        );
+    }
+}
+
+fn describe_overrides(run: &SweepRun) -> String {
+    run.overrides
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl BackendTask for Command {
+    type Output = ();
+
+    fn run<B: Backend>(self, device: B::Device) {
+        match self {
+            Command::Selfplay { config, output } => {
+                selfplay::<B>(load_config(config.as_deref()), output, &device);
+            }
+            Command::Against(args) => {
+                let (checkpoint, run, checkpoint_dir) = start_run::<B>(args, &device);
+                let run_json = serde_json::to_string_pretty(&run).expect("Should serialize run");
+                fs::write(checkpoint_dir.join("run.json"), run_json).expect("Should save run");
+
+                run_against::<B>(checkpoint, &run, &checkpoint_dir, &device);
+            }
+            Command::League(args) => {
+                let (checkpoint, run, checkpoint_dir) = start_run::<B>(args, &device);
+                let fixed = build_teachers::<B>(&run, &device);
+                let (checkpoint, league) =
+                    train_league(checkpoint, fixed, &device, Some(&checkpoint_dir));
+                league.print_standings();
+                save_model(
+                    checkpoint.learner.policy_net,
+                    &run.output,
+                    &checkpoint.config,
+                );
+            }
+            Command::ActorLearner(args) => actor_learner::<B>(args, &device),
+            Command::Ppo(args) => ppo::<B>(args, &device),
+            Command::Recurrent(args) => recurrent::<B>(args, &device),
+            Command::Pbt {
+                config,
+                student,
+                teachers,
+                bots,
+                run_dir,
+            } => {
+                let config = load_config(config.as_deref());
+                B::seed(config.seed);
+
+                let outputs = next_free_model_paths("dqn", config.pbt.population_size);
+                let run_dir = run_dir.unwrap_or_else(|| {
+                    PathBuf::from(format!("{CHECKPOINTS_DIR}/pbt_{}", model_name(&outputs[0])))
+                });
+                let student =
+                    student.map(|path| load_model(&path, &device).expect("Should load student"));
+                let fixed = build_entrants::<B>(&teachers, &parse_bots(&bots), &device);
+
+                let names = outputs.iter().map(|path| model_name(path)).collect();
+                let members = train_population::<Autodiff<B>>(
+                    &config, names, student, &fixed, &device, &run_dir,
+                );
+                for (member, output) in members.into_iter().zip(&outputs) {
+                    save_model(
+                        member.checkpoint.learner.policy_net,
+                        output,
+                        &member.checkpoint.config,
+                    );
+                }
+                println!("Lineage in {}", run_dir.join("lineage.jsonl").display());
+            }
+            Command::Sweep { spec } => sweep::<B>(&spec, &device),
+            Command::Plot {
+                metrics,
+                output,
+                metrics_to_plot,
+                smoothing,
+            } => {
+                let records = read_metrics(&metrics).expect("Should read metrics");
+                let output = output.unwrap_or_else(|| metrics.with_extension("svg"));
+                let svg = render_svg(&records, &metrics_to_plot, smoothing);
+                fs::write(&output, svg).expect("Should save plot");
+                println!("Plotted {} episodes to {}", records.len(), output.display());
+            }
+            Command::Resume { checkpoint_dir } => {
+                let run_json =
+                    fs::read_to_string(checkpoint_dir.join("run.json")).expect("Should read run");
+                let run: AgainstRun = serde_json::from_str(&run_json).expect("Should parse run");
+                let checkpoint =
+                    Checkpoint::load(&checkpoint_dir, &device).expect("Should load checkpoint");
+                // The backend's RNG can't be saved, so dropout continues from a seed of its
+                // own rather than replaying the draws the run started with
+                B::seed(checkpoint.config.seed ^ checkpoint.state.steps_done as u64);
+
+                println!(
+                    "Resuming from {} at episode {}",
+                    checkpoint_dir.display(),
+                    checkpoint.state.episode
+                );
+                run_against::<B>(checkpoint, &run, &checkpoint_dir, &device);
+            }
+            Command::Risk {
+                model: model_path,
+                attitude,
+                alpha,
+                output,
+            } => {
+                let SavedModel::Dqn(model) =
+                    load_any_model::<B>(&model_path, &device).expect("Should load model")
+                else {
+                    panic!("Only DQNs have a risk setting");
+                };
+                assert!(
+                    model.config().distributional.is_some(),
+                    "Only distributional DQNs can pick actions by anything but the mean"
+                );
+                let risk = match attitude {
+                    Attitude::Neutral => Risk::Neutral,
+                    Attitude::Cautious => Risk::Cautious { alpha },
+                    Attitude::Reckless => Risk::Reckless { alpha },
+                };
+                let output = output.unwrap_or_else(|| {
+                    let attitude = format!("{attitude:?}").to_lowercase();
+                    model_path.with_file_name(format!("{}_{attitude}.mpk", model_name(&model_path)))
+                });
+
+                // The variant was trained like the original, only the network's risk differs
+                let original_config = PathBuf::from(format!(
+                    "{TRAINING_CONFIGS_DIR}/{}.json",
+                    model_name(&model_path)
+                ));
+                let mut config = load_config(original_config.exists().then_some(&original_config));
+                let model = model.with_risk(risk);
+                config.model = model.config().clone();
+                save_model(model, &output, &config);
+            }
+            Command::Export { model, output } => {
+                let output = output.unwrap_or_else(|| {
+                    PathBuf::from(format!("{BINARY_MODELS_DIR}/{}.bin", model_name(&model)))
+                });
+                let model: SavedModel<B> =
+                    load_any_model(&model, &device).expect("Should load model");
+                let recorder = BinFileRecorder::<FullPrecisionSettings>::new();
+                model
+                    .save(&recorder, output.clone())
+                    .expect("Should save binary model");
+                println!("Exported {}", output.display());
+            }
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    cli.backend.run(cli.command);
 }
@@ -0,0 +1,348 @@
+use core::env::StepInfo;
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{tensorboard::EventWriter, train::TrainStats};
+
+/// How a fight ended for the player being logged
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Win,
+    Loss,
+    Draw,
+}
+
+impl Outcome {
+    pub fn new(winner: Option<usize>, player: usize) -> Self {
+        match winner {
+            Some(winner) if winner == player => Self::Win,
+            Some(_) => Self::Loss,
+            None => Self::Draw,
+        }
+    }
+
+    /// 1 for a win, 0 for a loss, draws in between
+    pub fn score(self) -> f32 {
+        match self {
+            Self::Win => 1.0,
+            Self::Loss => 0.0,
+            Self::Draw => 0.5,
+        }
+    }
+}
+
+/// One player's episode, a row of `metrics.csv` or a line of `metrics.jsonl`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EpisodeMetrics {
+    pub episode: usize,
+    /// Which learner, 0 unless two train side by side
+    pub player: usize,
+    pub opponent: String,
+    #[serde(rename = "return")]
+    pub episode_return: f32,
+    pub length: usize,
+    pub outcome: Outcome,
+    pub punches_thrown: usize,
+    pub punches_landed: usize,
+    /// Exploration at the end of the episode
+    pub epsilon: f32,
+    /// Means over the training steps taken during the episode, 0 when there were none
+    pub loss: f32,
+    pub mean_q: f32,
+    pub td_error: f32,
+}
+
+/// Adds up one player's steps until its episode ends
+#[derive(Clone, Debug, Default)]
+pub struct EpisodeTracker {
+    episode_return: f32,
+    length: usize,
+    train_steps: usize,
+    loss: f32,
+    mean_q: f32,
+    td_error: f32,
+}
+
+impl EpisodeTracker {
+    pub fn episode_return(&self) -> f32 {
+        self.episode_return
+    }
+
+    pub fn step(&mut self, reward: f32) {
+        self.episode_return += reward;
+        self.length += 1;
+    }
+
+    pub fn train(&mut self, stats: TrainStats) {
+        self.train_steps += 1;
+        self.loss += stats.loss;
+        self.mean_q += stats.mean_q;
+        self.td_error += stats.td_error;
+    }
+
+    /// Wraps up the episode and starts counting the next one
+    pub fn finish(
+        &mut self,
+        episode: usize,
+        player: usize,
+        opponent: String,
+        info: &StepInfo,
+        epsilon: f32,
+    ) -> EpisodeMetrics {
+        let tracker = std::mem::take(self);
+        let train_steps = tracker.train_steps.max(1) as f32;
+        EpisodeMetrics {
+            episode,
+            player,
+            opponent,
+            episode_return: tracker.episode_return,
+            length: tracker.length,
+            outcome: Outcome::new(info.winner, player),
+            punches_thrown: info.num_punches[player],
+            punches_landed: info.num_landed_punches[player],
+            epsilon,
+            loss: tracker.loss / train_steps,
+            mean_q: tracker.mean_q / train_steps,
+            td_error: tracker.td_error / train_steps,
+        }
+    }
+}
+
+/// Which files a run with a checkpoint directory writes its metrics to
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub csv: bool,
+    pub jsonl: bool,
+    pub tensorboard: bool,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            csv: true,
+            jsonl: false,
+            tensorboard: false,
+        }
+    }
+}
+
+/// Writes every finished episode to the files picked in `MetricsConfig`
+#[derive(Default)]
+pub struct MetricsLogger {
+    csv: Option<csv::Writer<File>>,
+    jsonl: Option<BufWriter<File>>,
+    tensorboard: Option<EventWriter>,
+}
+
+impl MetricsLogger {
+    /// Logs nothing
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Appends to `metrics.csv` and `metrics.jsonl` in `dir`, so a resumed run carries on
+    /// where it stopped. Rows from `first_episode` on are dropped first from these and from
+    /// `benchmark.jsonl`, a run resumed from its last checkpoint logs those episodes again.
+    pub fn create(
+        dir: &Path,
+        config: MetricsConfig,
+        first_episode: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(dir)?;
+        drop_episodes_from(&dir.join("metrics.csv"), first_episode)?;
+        retain_jsonl_rows(&dir.join("metrics.jsonl"), |episode| {
+            episode < first_episode
+        })?;
+        // Benchmarks are stamped with the episodes finished so far, so one at `first_episode`
+        // ran before the checkpoint was saved
+        retain_jsonl_rows(&dir.join("benchmark.jsonl"), |episode| {
+            episode <= first_episode
+        })?;
+        let append = |name: &str| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join(name))
+        };
+
+        let csv = if config.csv {
+            let file = append("metrics.csv")?;
+            // The header is only written once
+            let is_new = file.metadata()?.len() == 0;
+            Some(
+                csv::WriterBuilder::new()
+                    .has_headers(is_new)
+                    .from_writer(file),
+            )
+        } else {
+            None
+        };
+        let jsonl = if config.jsonl {
+            Some(BufWriter::new(append("metrics.jsonl")?))
+        } else {
+            None
+        };
+        let tensorboard = if config.tensorboard {
+            Some(EventWriter::create(dir, first_episode as i64)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            csv,
+            jsonl,
+            tensorboard,
+        })
+    }
+
+    /// Logs to `dir` when there is one, like checkpoints
+    pub fn for_run(dir: Option<&Path>, config: MetricsConfig, first_episode: usize) -> Self {
+        match dir {
+            Some(dir) => {
+                Self::create(dir, config, first_episode).expect("Should open metrics files")
+            }
+            None => Self::disabled(),
+        }
+    }
+
+    pub fn log(&mut self, metrics: &EpisodeMetrics) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(csv) = &mut self.csv {
+            csv.serialize(metrics)?;
+            csv.flush()?;
+        }
+        if let Some(jsonl) = &mut self.jsonl {
+            serde_json::to_writer(&mut *jsonl, metrics)?;
+            writeln!(jsonl)?;
+            jsonl.flush()?;
+        }
+        if let Some(tensorboard) = &mut self.tensorboard {
+            let player = metrics.player;
+            let tags = [
+                "return",
+                "length",
+                "win",
+                "punches_thrown",
+                "punches_landed",
+                "epsilon",
+                "loss",
+                "mean_q",
+                "td_error",
+            ]
+            .map(|name| format!("player{player}/{name}"));
+            let values = [
+                metrics.episode_return,
+                metrics.length as f32,
+                metrics.outcome.score(),
+                metrics.punches_thrown as f32,
+                metrics.punches_landed as f32,
+                metrics.epsilon,
+                metrics.loss,
+                metrics.mean_q,
+                metrics.td_error,
+            ];
+            let scalars: Vec<(&str, f32)> = tags.iter().map(String::as_str).zip(values).collect();
+            tensorboard.add_scalars(metrics.episode as i64, &scalars)?;
+            tensorboard.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Extra scalars that only go to TensorBoard, on the same episode axis
+    pub fn log_scalars(
+        &mut self,
+        episode: usize,
+        scalars: &[(&str, f32)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(tensorboard) = &mut self.tensorboard {
+            tensorboard.add_scalars(episode as i64, scalars)?;
+            tensorboard.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Rewrites a `metrics.csv` without the rows from `first_episode` on, if it has any
+fn drop_episodes_from(path: &Path, first_episode: usize) -> Result<(), Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let records = read_metrics(path)?;
+    if records.iter().all(|record| record.episode < first_episode) {
+        return Ok(());
+    }
+
+    let mut writer = csv::Writer::from_path(path)?;
+    for record in records
+        .iter()
+        .filter(|record| record.episode < first_episode)
+    {
+        writer.serialize(record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Rewrites a JSONL log with only the rows whose `episode` field passes `keep`, if any fail
+fn retain_jsonl_rows(
+    path: &Path,
+    keep: impl Fn(usize) -> bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(());
+    }
+    #[derive(Deserialize)]
+    struct Row {
+        episode: usize,
+    }
+
+    let mut lines = vec![];
+    let mut dropped = false;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row: Row = serde_json::from_str(&line)?;
+        if keep(row.episode) {
+            lines.push(line);
+        } else {
+            dropped = true;
+        }
+    }
+    if !dropped {
+        return Ok(());
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    for line in lines {
+        writeln!(writer, "{line}")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads back a `metrics.csv` or `metrics.jsonl`, going by the extension
+pub fn read_metrics(path: &Path) -> Result<Vec<EpisodeMetrics>, Box<dyn std::error::Error>> {
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "jsonl")
+    {
+        BufReader::new(File::open(path)?)
+            .lines()
+            .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    } else {
+        csv::Reader::from_path(path)?
+            .deserialize()
+            .map(|record| Ok(record?))
+            .collect()
+    }
+}
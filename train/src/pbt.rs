@@ -0,0 +1,303 @@
+use core::model::DQN;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+    thread,
+};
+
+use burn::{module::AutodiffModule, tensor::backend::AutodiffBackend};
+use rand::{Rng, SeedableRng, seq::IndexedRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    checkpoint::{Checkpoint, TrainerRng},
+    evaluation::{Entrant, Standing, round_robin},
+    train::{DqnLearner, TrainingConfig, train_against},
+};
+
+/// Missing fields fall back to `Default`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PbtConfig {
+    /// Learners trained side by side, one thread each
+    pub population_size: usize,
+    /// Episodes every member trains between evaluations, replaces `num_episodes`
+    pub round_episodes: usize,
+    /// Steps a member may take in one round, replaces `max_iters`
+    pub round_iters: usize,
+    pub num_rounds: usize,
+    /// Fights per pair of members in the round robin, sides alternate
+    pub eval_games: usize,
+    /// Share of the population at the bottom that copies a member from the same share at
+    /// the top after every round
+    pub truncation: f32,
+    /// Every explored hyperparameter is scaled by one of these, picked at random
+    pub perturb_factors: [f64; 2],
+    /// Exploration of the other members while they serve as opponents
+    pub opponent_epsilon: f32,
+}
+
+impl Default for PbtConfig {
+    fn default() -> Self {
+        Self {
+            population_size: 8,
+            round_episodes: 50,
+            round_iters: 150_000,
+            num_rounds: 20,
+            eval_games: 2,
+            truncation: 0.25,
+            perturb_factors: [0.8, 1.25],
+            opponent_epsilon: 0.05,
+        }
+    }
+}
+
+/// The part of a `TrainingConfig` that population-based training searches over
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Hyperparameters {
+    pub learning_rate: f64,
+    pub gamma: f32,
+    pub epsilon_decay: f32,
+    pub iters_per_training_step: usize,
+}
+
+impl Hyperparameters {
+    pub fn from_config(config: &TrainingConfig) -> Self {
+        Self {
+            learning_rate: config.learning_rate,
+            gamma: config.gamma,
+            epsilon_decay: config.epsilon_decay,
+            iters_per_training_step: config.iters_per_training_step,
+        }
+    }
+
+    pub fn apply(&self, config: &mut TrainingConfig) {
+        config.learning_rate = self.learning_rate;
+        config.gamma = self.gamma;
+        config.epsilon_decay = self.epsilon_decay;
+        config.iters_per_training_step = self.iters_per_training_step;
+    }
+
+    /// Scales each hyperparameter by one of `factors`. Gamma is scaled through its horizon
+    /// `1 / (1 - gamma)` so it stays below 1, and the training interval always moves by at
+    /// least one step so it can't get stuck at 1.
+    pub fn perturb(&self, factors: [f64; 2], rng: &mut impl Rng) -> Self {
+        let mut factor = || *factors.choose(rng).expect("Two factors to choose from");
+
+        let horizon = 1.0 / (1.0 - self.gamma as f64).max(1e-6);
+        let gamma = (1.0 - 1.0 / (horizon * factor())).clamp(0.0, 0.9999) as f32;
+
+        let interval_factor = factor();
+        let interval = (self.iters_per_training_step as f64 * interval_factor).round() as usize;
+        let iters_per_training_step = if interval_factor > 1.0 {
+            interval.max(self.iters_per_training_step + 1)
+        } else {
+            interval
+                .min(self.iters_per_training_step.saturating_sub(1))
+                .max(1)
+        };
+
+        Self {
+            learning_rate: self.learning_rate * factor(),
+            gamma,
+            epsilon_decay: self.epsilon_decay * factor() as f32,
+            iters_per_training_step,
+        }
+    }
+}
+
+/// One line of `lineage.jsonl`, written for every member after every round
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LineageEntry {
+    pub round: usize,
+    pub member: String,
+    /// Share of round-robin points won, draws count half
+    pub score: f32,
+    /// The member whose weights and hyperparameters were copied before exploring, `None`
+    /// when this member kept its own
+    pub copied_from: Option<String>,
+    /// What the member trains with next round
+    pub hyperparameters: Hyperparameters,
+}
+
+/// A slot in the population. Weights move between slots, the name stays.
+pub struct Member<B: AutodiffBackend> {
+    pub name: String,
+    pub checkpoint: Checkpoint<B>,
+}
+
+impl<B: AutodiffBackend> Member<B> {
+    /// A frozen copy of the current policy
+    pub fn entrant(&self) -> Entrant<B::InnerBackend> {
+        Entrant::Model {
+            name: self.name.clone(),
+            model: self.checkpoint.learner.policy_net.valid(),
+        }
+    }
+}
+
+/// Population-based training: every member trains against the others and the `fixed`
+/// opponents for `round_episodes`, then a round robin ranks them, the bottom `truncation`
+/// copy a member from the top and perturb its hyperparameters.
+///
+/// Every member starts from `student`, or a fresh network, with the config's
+/// hyperparameters perturbed once, except the first which keeps them as they are. The
+/// lineage is appended to `run_dir/lineage.jsonl` as it happens.
+pub fn train_population<B: AutodiffBackend>(
+    config: &TrainingConfig,
+    names: Vec<String>,
+    student: Option<DQN<B>>,
+    fixed: &[Entrant<B::InnerBackend>],
+    device: &B::Device,
+    run_dir: &Path,
+) -> Vec<Member<B>> {
+    let pbt = config.pbt;
+    let mut rng = TrainerRng::seed_from_u64(config.seed);
+    let base = Hyperparameters::from_config(config);
+
+    let mut members: Vec<Member<B>> = names
+        .into_iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let mut member_config = config.clone();
+            member_config.seed = config.seed + index as u64;
+            if index > 0 {
+                base.perturb(pbt.perturb_factors, &mut rng)
+                    .apply(&mut member_config);
+            }
+            let policy_net = student.clone().unwrap_or_else(|| config.model.init(device));
+            let learner = DqnLearner::new(policy_net, device, &member_config);
+            Member {
+                name,
+                checkpoint: Checkpoint::new(learner, member_config),
+            }
+        })
+        .collect();
+
+    fs::create_dir_all(run_dir).expect("Should create run dir");
+    let mut lineage_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(run_dir.join("lineage.jsonl"))
+        .expect("Should open lineage log");
+
+    for round in 0..pbt.num_rounds {
+        println!("PBT round {round}");
+        members = train_round(members, fixed, pbt, device);
+
+        let entrants: Vec<Entrant<B::InnerBackend>> = members.iter().map(Member::entrant).collect();
+        let scores: Vec<f32> = round_robin(&entrants, pbt.eval_games, &mut rng, device)
+            .iter()
+            .map(Standing::score)
+            .collect();
+        let copied_from = if round + 1 < pbt.num_rounds {
+            exploit_and_explore(&mut members, &scores, pbt, &mut rng)
+        } else {
+            // The last round's weights are the ones saved, so nothing gets overwritten
+            vec![None; members.len()]
+        };
+
+        println!(
+            "{:<12} {:>6} {:<12} {:>10} {:>7} {:>10} {:>6}",
+            "member", "score", "copied from", "lr", "gamma", "eps decay", "iters"
+        );
+        for ((member, score), parent) in members.iter().zip(&scores).zip(copied_from) {
+            let hyperparameters = Hyperparameters::from_config(&member.checkpoint.config);
+            println!(
+                "{:<12} {:>6.3} {:<12} {:>10.2e} {:>7.4} {:>10.2e} {:>6}",
+                member.name,
+                score,
+                parent.as_deref().unwrap_or("-"),
+                hyperparameters.learning_rate,
+                hyperparameters.gamma,
+                hyperparameters.epsilon_decay,
+                hyperparameters.iters_per_training_step,
+            );
+            let entry = LineageEntry {
+                round,
+                member: member.name.clone(),
+                score: *score,
+                copied_from: parent,
+                hyperparameters,
+            };
+            let line = serde_json::to_string(&entry).expect("Should serialize lineage");
+            writeln!(lineage_file, "{line}").expect("Should write lineage");
+        }
+    }
+
+    members
+}
+
+/// Trains every member on its own thread against frozen copies of the others. Modules can
+/// be sent between threads but not shared, so every thread gets its own copies.
+fn train_round<B: AutodiffBackend>(
+    members: Vec<Member<B>>,
+    fixed: &[Entrant<B::InnerBackend>],
+    pbt: PbtConfig,
+    device: &B::Device,
+) -> Vec<Member<B>> {
+    let snapshots: Vec<Entrant<B::InnerBackend>> = members.iter().map(Member::entrant).collect();
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = members
+            .into_iter()
+            .map(|mut member| {
+                let opponents: Vec<Entrant<B::InnerBackend>> = snapshots
+                    .iter()
+                    .filter(|snapshot| snapshot.name() != member.name)
+                    .chain(fixed)
+                    .cloned()
+                    .collect();
+                scope.spawn(move || {
+                    let opponents = opponents
+                        .iter()
+                        .map(|opponent| opponent.agent(pbt.opponent_epsilon, device))
+                        .collect();
+
+                    let config = &mut member.checkpoint.config;
+                    let state = &member.checkpoint.state;
+                    config.num_episodes = state.episode + pbt.round_episodes;
+                    config.max_iters = state.iters + pbt.round_iters;
+                    member.checkpoint = train_against(member.checkpoint, opponents, device, None);
+                    member
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Member should finish its round"))
+            .collect()
+    })
+}
+
+/// The bottom members take the weights and hyperparameters of a random top member and
+/// perturb the hyperparameters. Returns who copied whom.
+fn exploit_and_explore<B: AutodiffBackend>(
+    members: &mut [Member<B>],
+    scores: &[f32],
+    pbt: PbtConfig,
+    rng: &mut TrainerRng,
+) -> Vec<Option<String>> {
+    let mut ranking: Vec<usize> = (0..members.len()).collect();
+    ranking.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+    let cutoff = ((members.len() as f32 * pbt.truncation).ceil() as usize).min(members.len() / 2);
+
+    let mut copied_from = vec![None; members.len()];
+    for &loser in &ranking[members.len() - cutoff..] {
+        let winner = *ranking[..cutoff]
+            .choose(rng)
+            .expect("Cutoff should leave a top member");
+        let learner = members[winner].checkpoint.learner.clone();
+        let hyperparameters = Hyperparameters::from_config(&members[winner].checkpoint.config)
+            .perturb(pbt.perturb_factors, rng);
+
+        copied_from[loser] = Some(members[winner].name.clone());
+
+        // The replay buffer and step counters stay, only what was learned moves over
+        let member = &mut members[loser];
+        member.checkpoint.learner = learner;
+        hyperparameters.apply(&mut member.checkpoint.config);
+    }
+    copied_from
+}
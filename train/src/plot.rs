@@ -0,0 +1,223 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use clap::ValueEnum;
+
+use crate::metrics::EpisodeMetrics;
+
+const WIDTH: f32 = 800.0;
+const PANEL_HEIGHT: f32 = 200.0;
+const MARGIN_LEFT: f32 = 70.0;
+const MARGIN_RIGHT: f32 = 20.0;
+const MARGIN_TOP: f32 = 30.0;
+const MARGIN_BOTTOM: f32 = 30.0;
+/// One color per player
+const COLORS: [&str; 4] = ["#1f77b4", "#d62728", "#2ca02c", "#9467bd"];
+
+/// A column of the metrics files that can be plotted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Metric {
+    Return,
+    Length,
+    /// 1 for a win, 0 for a loss, so the smoothed curve is the win rate
+    Win,
+    PunchesThrown,
+    PunchesLanded,
+    Epsilon,
+    Loss,
+    MeanQ,
+    TdError,
+}
+
+impl Metric {
+    fn value(self, metrics: &EpisodeMetrics) -> f32 {
+        match self {
+            Self::Return => metrics.episode_return,
+            Self::Length => metrics.length as f32,
+            Self::Win => metrics.outcome.score(),
+            Self::PunchesThrown => metrics.punches_thrown as f32,
+            Self::PunchesLanded => metrics.punches_landed as f32,
+            Self::Epsilon => metrics.epsilon,
+            Self::Loss => metrics.loss,
+            Self::MeanQ => metrics.mean_q,
+            Self::TdError => metrics.td_error,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Self::Return => "return",
+            Self::Length => "episode length",
+            Self::Win => "win rate",
+            Self::PunchesThrown => "punches thrown",
+            Self::PunchesLanded => "punches landed",
+            Self::Epsilon => "epsilon",
+            Self::Loss => "loss",
+            Self::MeanQ => "mean Q",
+            Self::TdError => "TD error",
+        }
+    }
+}
+
+/// One player's values of a metric, by episode
+struct Series {
+    player: usize,
+    episodes: Vec<usize>,
+    raw: Vec<f32>,
+    smoothed: Vec<f32>,
+}
+
+/// Mean of the last `window` values at every point
+fn moving_average(values: &[f32], window: usize) -> Vec<f32> {
+    let window = window.max(1);
+    let mut sum = 0.0;
+    values
+        .iter()
+        .enumerate()
+        .map(|(index, value)| {
+            sum += value;
+            if index >= window {
+                sum -= values[index - window];
+            }
+            sum / (index + 1).min(window) as f32
+        })
+        .collect()
+}
+
+/// Learning curves as an SVG, one panel per metric stacked top to bottom and one line per
+/// player. The raw values are drawn faintly behind a `smoothing` episode moving average.
+pub fn render_svg(records: &[EpisodeMetrics], metrics: &[Metric], smoothing: usize) -> String {
+    let mut players: BTreeMap<usize, Vec<&EpisodeMetrics>> = BTreeMap::new();
+    for record in records {
+        players.entry(record.player).or_default().push(record);
+    }
+    let first_episode = records
+        .iter()
+        .map(|record| record.episode)
+        .min()
+        .unwrap_or(0);
+    let last_episode = records
+        .iter()
+        .map(|record| record.episode)
+        .max()
+        .unwrap_or(0);
+    let episode_span = (last_episode - first_episode).max(1) as f32;
+
+    let height = PANEL_HEIGHT * metrics.len() as f32;
+    let plot_width = WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_height = PANEL_HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{height}" viewBox="0 0 {WIDTH} {height}" font-family="sans-serif" font-size="11">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="{WIDTH}" height="{height}" fill="white"/>"#
+    );
+
+    for (panel, metric) in metrics.iter().enumerate() {
+        let top = panel as f32 * PANEL_HEIGHT + MARGIN_TOP;
+        let bottom = top + plot_height;
+
+        let series: Vec<Series> = players
+            .iter()
+            .map(|(player, records)| {
+                let episodes: Vec<usize> = records.iter().map(|record| record.episode).collect();
+                let raw: Vec<f32> = records.iter().map(|record| metric.value(record)).collect();
+                let smoothed = moving_average(&raw, smoothing);
+                Series {
+                    player: *player,
+                    episodes,
+                    raw,
+                    smoothed,
+                }
+            })
+            .collect();
+        let (mut low, mut high) = series
+            .iter()
+            .flat_map(|series| series.raw.iter().copied())
+            .filter(|value| value.is_finite())
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), value| {
+                (low.min(value), high.max(value))
+            });
+        if low > high {
+            (low, high) = (0.0, 1.0);
+        } else if low == high {
+            (low, high) = (low - 0.5, high + 0.5);
+        }
+
+        let x = |episode: usize| {
+            MARGIN_LEFT + (episode - first_episode) as f32 / episode_span * plot_width
+        };
+        let y = |value: f32| bottom - (value - low) / (high - low) * plot_height;
+
+        let _ = writeln!(
+            svg,
+            r#"<text x="{MARGIN_LEFT}" y="{}" font-size="13" font-weight="bold">{}</text>"#,
+            top - 10.0,
+            metric.title()
+        );
+        let _ = writeln!(
+            svg,
+            r##"<rect x="{MARGIN_LEFT}" y="{top}" width="{plot_width}" height="{plot_height}" fill="none" stroke="#888"/>"##
+        );
+        for (value, anchor_y) in [(high, top + 4.0), (low, bottom)] {
+            let _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{anchor_y}" text-anchor="end">{}</text>"#,
+                MARGIN_LEFT - 6.0,
+                format_value(value)
+            );
+        }
+        for (episode, anchor) in [(first_episode, "start"), (last_episode, "end")] {
+            let _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{}" text-anchor="{anchor}">episode {episode}</text>"#,
+                x(episode),
+                bottom + 16.0
+            );
+        }
+
+        for (index, line) in series.iter().enumerate() {
+            let color = COLORS[line.player % COLORS.len()];
+            for (values, style) in [
+                (&line.raw, r#"stroke-opacity="0.25" stroke-width="1""#),
+                (&line.smoothed, r#"stroke-width="2""#),
+            ] {
+                let points: Vec<String> = line
+                    .episodes
+                    .iter()
+                    .zip(values)
+                    .filter(|(_, value)| value.is_finite())
+                    .map(|(episode, value)| format!("{:.1},{:.1}", x(*episode), y(*value)))
+                    .collect();
+                let _ = writeln!(
+                    svg,
+                    r#"<polyline fill="none" stroke="{color}" {style} points="{}"/>"#,
+                    points.join(" ")
+                );
+            }
+            if series.len() > 1 {
+                let _ = writeln!(
+                    svg,
+                    r#"<text x="{}" y="{}" text-anchor="end" fill="{color}">player {}</text>"#,
+                    WIDTH - MARGIN_RIGHT - 70.0 * (series.len() - 1 - index) as f32,
+                    top - 10.0,
+                    line.player
+                );
+            }
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn format_value(value: f32) -> String {
+    if value != 0.0 && (value.abs() < 0.01 || value.abs() >= 10_000.0) {
+        format!("{value:.2e}")
+    } else {
+        format!("{value:.2}")
+    }
+}
@@ -0,0 +1,389 @@
+use core::{
+    OBSERVATION_LENGTH, OUTPUT_SIZE, Observation,
+    agent::Agent,
+    env::{BoxingEnv, GameEnv, TimeLimit},
+    observations_to_tensor,
+    policy::{ActorCritic, ActorCriticConfig, sample_from, save_actor_critic},
+};
+use std::path::Path;
+
+use burn::{
+    module::AutodiffModule,
+    optim::{Adam, GradientsParams, Optimizer, adaptor::OptimizerAdaptor},
+    prelude::*,
+    record::{FullPrecisionSettings, NamedMpkFileRecorder},
+    tensor::{activation, backend::AutodiffBackend, cast::ToElement},
+};
+use rand::{Rng, SeedableRng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    benchmark::{Benchmark, BenchmarkState, report},
+    checkpoint::TrainerRng,
+    evaluation::Entrant,
+    metrics::{EpisodeTracker, MetricsLogger},
+    train::{MAX_EPISODE_ITERS, TrainStats, TrainingConfig, clip_grad_norm},
+};
+
+/// Rollout size and loss weights of `train_ppo`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PpoConfig {
+    /// Architecture of freshly initialized actor-critics
+    pub model: ActorCriticConfig,
+    /// Env steps collected with the same policy before every update
+    pub rollout_length: usize,
+    /// Passes over each rollout
+    pub epochs: usize,
+    pub minibatch_size: usize,
+    pub learning_rate: f64,
+    /// How far the probability ratio of an action may move before its gradient is cut
+    pub clip_epsilon: f32,
+    /// Trades the bias of short advantage estimates against the variance of long ones
+    pub gae_lambda: f32,
+    pub value_coef: f32,
+    /// Rewards keeping the policy spread out, so it keeps exploring
+    pub entropy_coef: f32,
+    pub max_grad_norm: Option<f32>,
+}
+
+impl Default for PpoConfig {
+    fn default() -> Self {
+        Self {
+            model: ActorCriticConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE),
+            rollout_length: 2048,
+            epochs: 4,
+            minibatch_size: 256,
+            learning_rate: 0.0003,
+            clip_epsilon: 0.2,
+            gae_lambda: 0.95,
+            value_coef: 0.5,
+            entropy_coef: 0.01,
+            max_grad_norm: Some(0.5),
+        }
+    }
+}
+
+pub type PpoOptimizer<B> = OptimizerAdaptor<Adam, ActorCritic<B>, B>;
+
+/// One player's steps of a rollout, in order
+#[derive(Default)]
+struct Trajectory {
+    observations: Vec<Observation>,
+    actions: Vec<usize>,
+    log_probs: Vec<f32>,
+    values: Vec<f32>,
+    rewards: Vec<f32>,
+    /// The episode ended after this step, so nothing is bootstrapped past it
+    dones: Vec<bool>,
+}
+
+impl Trajectory {
+    /// Generalized advantage estimates and the value targets they imply. `last_value` is
+    /// the value of the state after the last step, used unless that step ended an episode.
+    fn advantages(&self, last_value: f32, gamma: f32, lambda: f32) -> (Vec<f32>, Vec<f32>) {
+        let mut advantages = vec![0.0; self.rewards.len()];
+        let mut advantage = 0.0;
+        for step in (0..self.rewards.len()).rev() {
+            let next_value = self.values.get(step + 1).copied().unwrap_or(last_value);
+            let not_done = if self.dones[step] { 0.0 } else { 1.0 };
+            let delta = self.rewards[step] + gamma * next_value * not_done - self.values[step];
+            advantage = delta + gamma * lambda * not_done * advantage;
+            advantages[step] = advantage;
+        }
+        let returns = advantages
+            .iter()
+            .zip(&self.values)
+            .map(|(advantage, value)| advantage + value)
+            .collect();
+        (advantages, returns)
+    }
+}
+
+/// Every trajectory of a rollout flattened, ready to train on
+#[derive(Default)]
+struct Batch {
+    observations: Vec<Observation>,
+    actions: Vec<i32>,
+    log_probs: Vec<f32>,
+    advantages: Vec<f32>,
+    returns: Vec<f32>,
+}
+
+/// Trains an actor-critic with proximal policy optimization against `opponents`, or against
+/// itself when there are none, in which case both sides' steps are trained on. Stops after
+/// `num_episodes` fights or `max_iters` env steps.
+///
+/// Episodes are logged to `metrics_dir`, where `loss` is the PPO loss, `mean_q` the value
+/// head's mean estimate and `td_error` the mean absolute advantage of the latest update.
+/// Benchmark bests are saved to `best.mpk` there. Runs can't be resumed.
+pub fn train_ppo<B: AutodiffBackend>(
+    model: ActorCritic<B>,
+    mut opponents: Vec<Box<dyn Agent>>,
+    device: &B::Device,
+    config: &TrainingConfig,
+    metrics_dir: Option<&Path>,
+) -> ActorCritic<B> {
+    let ppo = &config.ppo;
+    let mut model = model;
+    let mut optimizer: PpoOptimizer<B> = config.optimizer.init();
+    let mut rng = TrainerRng::seed_from_u64(config.seed);
+    let mut metrics = MetricsLogger::for_run(metrics_dir, config.metrics, 0);
+    let benchmark = Benchmark::<B::InnerBackend>::load(&config.benchmark, device)
+        .expect("Should load benchmark models");
+    let mut benchmark_state = BenchmarkState::default();
+
+    let mirror = opponents.is_empty();
+    let sides = if mirror { 2 } else { 1 };
+    let mut env = TimeLimit::new(GameEnv::new(), MAX_EPISODE_ITERS);
+    let mut opponent_index = 0;
+    // Replaced on the first step, once an opponent is picked
+    let mut observations = env.reset(None);
+    let mut trackers = [EpisodeTracker::default(), EpisodeTracker::default()];
+    let mut episode_started = false;
+
+    let mut episode = 0;
+    let mut steps_done = 0;
+    let mut updates = 0;
+    let mut latest_stats = None;
+
+    while episode < config.num_episodes && steps_done <= config.max_iters {
+        let policy = model.valid();
+        let mut trajectories = [Trajectory::default(), Trajectory::default()];
+
+        for _ in 0..ppo.rollout_length {
+            if !episode_started {
+                if !mirror {
+                    opponent_index = rng.random_range(0..opponents.len());
+                    opponents[opponent_index].reset();
+                }
+                observations = env.reset(None);
+                episode_started = true;
+            }
+
+            let (probabilities, values) = policy.evaluate(&observations[..sides], device);
+            let mut actions = [0; 2];
+            for side in 0..sides {
+                actions[side] = sample_from(&probabilities[side], &mut rng);
+            }
+            if !mirror {
+                actions[1] = opponents[opponent_index]
+                    .act(&observations[1], &mut rng)
+                    .to_int();
+            }
+
+            let step = env.step(actions);
+            // A fight cut short by the time limit isn't worth nothing from there on
+            let truncated_values = (step.truncated && !step.terminated)
+                .then(|| policy.evaluate(&step.observations[..sides], device).1);
+            for side in 0..sides {
+                let trajectory = &mut trajectories[side];
+                let mut reward = step.rewards[side];
+                if let Some(values) = &truncated_values {
+                    reward += config.gamma * values[side];
+                }
+                trajectory.observations.push(observations[side]);
+                trajectory.actions.push(actions[side]);
+                trajectory.log_probs.push(
+                    probabilities[side][actions[side]]
+                        .max(f32::MIN_POSITIVE)
+                        .ln(),
+                );
+                trajectory.values.push(values[side]);
+                trajectory.rewards.push(reward);
+                trajectory.dones.push(step.is_done());
+                trackers[side].step(step.rewards[side]);
+            }
+            observations = step.observations;
+            steps_done += 1;
+
+            if let Some(benchmark) = &benchmark
+                && benchmark.is_due(steps_done - 1, steps_done)
+            {
+                let student = Entrant::Policy {
+                    name: "student".to_string(),
+                    model: policy.clone(),
+                };
+                let result = benchmark.evaluate(
+                    &student,
+                    &mut benchmark_state,
+                    steps_done,
+                    episode,
+                    0,
+                    config.seed,
+                    device,
+                );
+                report(&result, &mut metrics, metrics_dir).expect("Should log benchmark");
+                if result.best
+                    && let Some(dir) = metrics_dir
+                {
+                    save_actor_critic(
+                        policy.clone(),
+                        &NamedMpkFileRecorder::<FullPrecisionSettings>::new(),
+                        dir.join("best"),
+                    )
+                    .expect("Should save best model");
+                }
+            }
+
+            if step.is_done() {
+                println!(
+                    "->> Episode {episode} finished with final health 0:{} 1:{} Reward 0:{:.2} steps {steps_done}",
+                    step.info.health[0],
+                    step.info.health[1],
+                    trackers[0].episode_return(),
+                );
+                for (side, tracker) in trackers.iter_mut().enumerate().take(sides) {
+                    if let Some(stats) = latest_stats {
+                        tracker.train(stats);
+                    }
+                    let opponent = if mirror {
+                        "self".to_string()
+                    } else {
+                        opponents[opponent_index].name()
+                    };
+                    let episode_metrics = tracker.finish(episode, side, opponent, &step.info, 0.0);
+                    metrics.log(&episode_metrics).expect("Should log metrics");
+                }
+                episode += 1;
+                episode_started = false;
+                if episode >= config.num_episodes {
+                    break;
+                }
+            }
+        }
+
+        // Trajectories that stop mid-fight bootstrap from where they stopped
+        let last_values = if episode_started {
+            policy.evaluate(&observations[..sides], device).1
+        } else {
+            vec![0.0; sides]
+        };
+        let mut batch = Batch::default();
+        for (trajectory, last_value) in trajectories.iter().zip(last_values) {
+            let (advantages, returns) =
+                trajectory.advantages(last_value, config.gamma, ppo.gae_lambda);
+            batch.observations.extend(&trajectory.observations);
+            batch
+                .actions
+                .extend(trajectory.actions.iter().map(|action| *action as i32));
+            batch.log_probs.extend(&trajectory.log_probs);
+            batch.advantages.extend(advantages);
+            batch.returns.extend(returns);
+        }
+        if batch.actions.is_empty() {
+            break;
+        }
+
+        let stats;
+        (model, stats) = update(model, &mut optimizer, &batch, config, &mut rng, device);
+        updates += 1;
+        latest_stats = Some(stats);
+        println!(
+            "   Update {updates} at step {steps_done}: loss {:.4} mean value {:.3} mean |advantage| {:.3}",
+            stats.loss, stats.mean_q, stats.td_error,
+        );
+    }
+
+    model
+}
+
+/// `epochs` passes of clipped policy gradient, value regression and entropy bonus over
+/// shuffled minibatches of the rollout
+fn update<B: AutodiffBackend>(
+    mut model: ActorCritic<B>,
+    optimizer: &mut PpoOptimizer<B>,
+    batch: &Batch,
+    config: &TrainingConfig,
+    rng: &mut TrainerRng,
+    device: &B::Device,
+) -> (ActorCritic<B>, TrainStats) {
+    let ppo = &config.ppo;
+    let size = batch.actions.len();
+
+    // Normalized per rollout, so the step size doesn't depend on the scale of the rewards
+    let mean = batch.advantages.iter().sum::<f32>() / size as f32;
+    let variance = batch
+        .advantages
+        .iter()
+        .map(|advantage| (advantage - mean).powi(2))
+        .sum::<f32>()
+        / size as f32;
+    let normalized: Vec<f32> = batch
+        .advantages
+        .iter()
+        .map(|advantage| (advantage - mean) / (variance.sqrt() + 1e-8))
+        .collect();
+
+    let observations = observations_to_tensor::<B>(&batch.observations, device);
+    let actions = Tensor::<B, 1, Int>::from_ints(
+        TensorData::new(batch.actions.clone(), Shape::new([size])),
+        device,
+    );
+    let floats = |values: Vec<f32>| {
+        Tensor::<B, 1>::from_floats(TensorData::new(values, Shape::new([size])), device)
+    };
+    let old_log_probs = floats(batch.log_probs.clone());
+    let advantages = floats(normalized);
+    let returns = floats(batch.returns.clone());
+
+    let mut indices: Vec<i32> = (0..size as i32).collect();
+    let mut total_loss = 0.0;
+    let mut minibatches = 0;
+    for _ in 0..ppo.epochs {
+        indices.shuffle(rng);
+        for chunk in indices.chunks(ppo.minibatch_size.max(1)) {
+            let chunk = Tensor::<B, 1, Int>::from_ints(
+                TensorData::new(chunk.to_vec(), Shape::new([chunk.len()])),
+                device,
+            );
+            let (logits, values) = model.forward(observations.clone().select(0, chunk.clone()));
+            let log_probs = activation::log_softmax(logits, 1);
+            let action_log_probs: Tensor<B, 1> = log_probs
+                .clone()
+                .gather(1, actions.clone().select(0, chunk.clone()).unsqueeze_dim(1))
+                .squeeze(1);
+
+            let advantage = advantages.clone().select(0, chunk.clone());
+            let ratio = (action_log_probs - old_log_probs.clone().select(0, chunk.clone())).exp();
+            let clipped = ratio
+                .clone()
+                .clamp(1.0 - ppo.clip_epsilon, 1.0 + ppo.clip_epsilon);
+            let policy_loss = -(ratio * advantage.clone())
+                .min_pair(clipped * advantage)
+                .mean();
+            let value_loss = (values - returns.clone().select(0, chunk))
+                .powi_scalar(2)
+                .mean();
+            let entropy = -(log_probs.clone().exp() * log_probs).sum_dim(1).mean();
+
+            let loss = policy_loss + value_loss * ppo.value_coef - entropy * ppo.entropy_coef;
+            total_loss += loss.clone().into_scalar().to_f32();
+            minibatches += 1;
+
+            let mut grads = GradientsParams::from_grads(loss.backward(), &model);
+            if let Some(max_norm) = ppo.max_grad_norm {
+                grads = clip_grad_norm(grads, &model, max_norm);
+            }
+            model = optimizer.step(ppo.learning_rate, model, grads);
+        }
+    }
+
+    let stats = TrainStats {
+        loss: total_loss / minibatches.max(1) as f32,
+        mean_q: batch
+            .returns
+            .iter()
+            .zip(&batch.advantages)
+            .map(|(value_target, advantage)| value_target - advantage)
+            .sum::<f32>()
+            / size as f32,
+        td_error: batch
+            .advantages
+            .iter()
+            .map(|advantage| advantage.abs())
+            .sum::<f32>()
+            / size as f32,
+    };
+    (model, stats)
+}
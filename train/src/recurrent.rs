@@ -0,0 +1,386 @@
+use core::{
+    OBSERVATION_LENGTH, OUTPUT_SIZE,
+    agent::Agent,
+    env::{BoxingEnv, GameEnv, TimeLimit},
+    recurrent::{
+        RecurrentDQN, RecurrentDQNConfig, RecurrentState, save_recurrent_dqn,
+        select_recurrent_actions,
+    },
+};
+use std::path::Path;
+
+use burn::{
+    module::AutodiffModule,
+    nn::loss::{HuberLossConfig, MseLoss},
+    optim::{Adam, GradientsParams, Optimizer, adaptor::OptimizerAdaptor},
+    prelude::*,
+    record::{FullPrecisionSettings, NamedMpkFileRecorder},
+    tensor::{backend::AutodiffBackend, cast::ToElement},
+};
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    benchmark::{Benchmark, BenchmarkState, report},
+    checkpoint::TrainerRng,
+    evaluation::Entrant,
+    metrics::{EpisodeTracker, MetricsLogger},
+    sequence_replay::{EpisodeRecord, SequenceBatch, SequenceReplay},
+    train::{
+        LossFunction, MAX_EPISODE_ITERS, TRAIN_START, TargetUpdate, TrainStats, TrainingConfig,
+        clip_grad_norm, get_epsilon, polyak_update,
+    },
+};
+
+/// Sequence replay of `train_recurrent`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecurrentConfig {
+    /// Architecture of freshly initialized networks
+    pub model: RecurrentDQNConfig,
+    /// Sequences per gradient step
+    pub batch_size: usize,
+    /// Steps trained on per sequence
+    pub sequence_length: usize,
+    /// Steps replayed before each sequence only to bring the stored state up to date with
+    /// the current weights
+    pub burn_in: usize,
+    /// Steps kept for replay, in whole episodes
+    pub capacity: usize,
+}
+
+impl Default for RecurrentConfig {
+    fn default() -> Self {
+        Self {
+            model: RecurrentDQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE),
+            batch_size: 32,
+            sequence_length: 32,
+            burn_in: 16,
+            capacity: 100_000,
+        }
+    }
+}
+
+pub type RecurrentOptimizer<B> = OptimizerAdaptor<Adam, RecurrentDQN<B>, B>;
+
+/// Trains a recurrent DQN against `opponents`, or against itself when there are none, in
+/// which case both sides' fights are replayed. Fights are stored whole and the network
+/// trains on sequences of them, each starting from the state it was played with.
+///
+/// Episodes are logged to `metrics_dir` like the other trainers and benchmark bests are
+/// saved to `best.mpk` there. Runs can't be resumed.
+pub fn train_recurrent<B: AutodiffBackend>(
+    model: RecurrentDQN<B>,
+    mut opponents: Vec<Box<dyn Agent>>,
+    device: &B::Device,
+    config: &TrainingConfig,
+    metrics_dir: Option<&Path>,
+) -> RecurrentDQN<B> {
+    let recurrent = &config.recurrent;
+    let mut policy_net = model;
+    let mut target_net = policy_net.clone();
+    let mut optimizer: RecurrentOptimizer<B> = config.optimizer.init();
+    let mut rng = TrainerRng::seed_from_u64(config.seed);
+    let mut replay = SequenceReplay::new(recurrent.capacity, TrainerRng::from_rng(&mut rng));
+    let mut metrics = MetricsLogger::for_run(metrics_dir, config.metrics, 0);
+    let benchmark = Benchmark::<B::InnerBackend>::load(&config.benchmark, device)
+        .expect("Should load benchmark models");
+    let mut benchmark_state = BenchmarkState::default();
+
+    let mirror = opponents.is_empty();
+    let sides = if mirror { 2 } else { 1 };
+    let state_size = policy_net.config().state_size();
+    let mut env = TimeLimit::new(GameEnv::new(), MAX_EPISODE_ITERS);
+    // Acting needs no gradients, the copy is refreshed after every gradient step
+    let mut actor = policy_net.valid();
+    let mut steps_done = 0;
+
+    for episode in 0..config.num_episodes {
+        if steps_done > config.max_iters {
+            break;
+        }
+        let opponent_index = (!mirror).then(|| rng.random_range(0..opponents.len()));
+        let opponent_name = match opponent_index {
+            Some(opponent_index) => {
+                opponents[opponent_index].reset();
+                opponents[opponent_index].name()
+            }
+            None => "self".to_string(),
+        };
+
+        let mut observations = env.reset(None);
+        let mut records: Vec<EpisodeRecord> = observations[..sides]
+            .iter()
+            .map(|observation| EpisodeRecord::new(*observation))
+            .collect();
+        let mut trackers = [EpisodeTracker::default(), EpisodeTracker::default()];
+        let mut state: Option<RecurrentState<B::InnerBackend>> = None;
+
+        loop {
+            let epsilon = get_epsilon(steps_done, config.epsilon_start, config.epsilon_decay);
+            let states_before = state
+                .as_ref()
+                .map_or_else(|| vec![0.0; sides * state_size], RecurrentState::to_vec);
+            let mut actions = [0; 2];
+            actions[..sides].copy_from_slice(&select_recurrent_actions(
+                &observations[..sides],
+                &actor,
+                &mut state,
+                epsilon,
+                &mut rng,
+                device,
+            ));
+            if let Some(opponent_index) = opponent_index {
+                actions[1] = opponents[opponent_index]
+                    .act(&observations[1], &mut rng)
+                    .to_int();
+            }
+
+            let step = env.step(actions);
+            for side in 0..sides {
+                records[side].push(
+                    states_before[side * state_size..(side + 1) * state_size].to_vec(),
+                    actions[side],
+                    step.rewards[side],
+                    step.observations[side],
+                );
+                trackers[side].step(step.rewards[side]);
+            }
+            observations = step.observations;
+            steps_done += 1;
+
+            // Only finished fights are replayed, so the first ones are played out untrained
+            let trained = steps_done > TRAIN_START
+                && steps_done.is_multiple_of(config.iters_per_training_step)
+                && !replay.is_empty();
+            if trained {
+                let batch = replay.sample(
+                    recurrent.batch_size,
+                    recurrent.burn_in,
+                    recurrent.sequence_length,
+                );
+                let stats;
+                (policy_net, stats) = train_on_sequences(
+                    policy_net,
+                    &target_net,
+                    batch,
+                    &mut optimizer,
+                    device,
+                    config,
+                );
+                for tracker in trackers.iter_mut().take(sides) {
+                    tracker.train(stats);
+                }
+                actor = policy_net.valid();
+            }
+            match config.target_update {
+                TargetUpdate::Hard { interval } => {
+                    if steps_done.is_multiple_of(interval) {
+                        target_net = policy_net.clone();
+                    }
+                }
+                TargetUpdate::Polyak { tau } => {
+                    if trained {
+                        target_net = polyak_update(target_net, &policy_net, tau);
+                    }
+                }
+            }
+
+            if let Some(benchmark) = &benchmark
+                && benchmark.is_due(steps_done - 1, steps_done)
+            {
+                let student = Entrant::Recurrent {
+                    name: "student".to_string(),
+                    model: actor.clone(),
+                };
+                let result = benchmark.evaluate(
+                    &student,
+                    &mut benchmark_state,
+                    steps_done,
+                    episode,
+                    0,
+                    config.seed,
+                    device,
+                );
+                report(&result, &mut metrics, metrics_dir).expect("Should log benchmark");
+                if result.best
+                    && let Some(dir) = metrics_dir
+                {
+                    save_recurrent_dqn(
+                        actor.clone(),
+                        &NamedMpkFileRecorder::<FullPrecisionSettings>::new(),
+                        dir.join("best"),
+                    )
+                    .expect("Should save best model");
+                }
+            }
+
+            if step.is_done() {
+                println!(
+                    "->> Episode {episode} against {opponent_name} finished with final health 0:{} 1:{} Reward 0:{:.2} epsilon {epsilon} steps {steps_done}",
+                    step.info.health[0],
+                    step.info.health[1],
+                    trackers[0].episode_return(),
+                );
+                for (side, mut record) in records.into_iter().enumerate() {
+                    record.finish(step.terminated);
+                    replay.push(record);
+                    let episode_metrics = trackers[side].finish(
+                        episode,
+                        side,
+                        opponent_name.clone(),
+                        &step.info,
+                        epsilon,
+                    );
+                    metrics.log(&episode_metrics).expect("Should log metrics");
+                }
+                break;
+            }
+        }
+    }
+
+    policy_net
+}
+
+/// Both networks replay the burn-in one step at a time without gradients, so rows whose
+/// burn-in starts before their episode keep their stored state until it begins
+fn burn_in<B: Backend>(
+    model: &RecurrentDQN<B>,
+    observations: Tensor<B, 3>,
+    state: RecurrentState<B>,
+    mask: Tensor<B, 2>,
+) -> RecurrentState<B> {
+    let [batch_size, steps, _] = observations.dims();
+    let mut state = state;
+    for step in 0..steps {
+        let (_, next) = model.forward(
+            observations.clone().slice([0..batch_size, step..step + 1]),
+            Some(state.clone()),
+        );
+        let keep = mask.clone().slice([0..batch_size, step..step + 1]);
+        let blend = |next: Tensor<B, 2>, previous: Tensor<B, 2>| {
+            next * keep.clone() + previous * (keep.clone().neg() + 1.0)
+        };
+        state = RecurrentState {
+            hidden: blend(next.hidden, state.hidden),
+            cell: match (next.cell, state.cell) {
+                (Some(next), Some(previous)) => Some(blend(next, previous)),
+                _ => None,
+            },
+        }
+        .detach();
+    }
+    state
+}
+
+/// One gradient step on a batch of sequences, padded steps don't count towards the loss
+/// or the stats
+pub fn train_on_sequences<B: AutodiffBackend, O: Optimizer<RecurrentDQN<B>, B>>(
+    policy_net: RecurrentDQN<B>,
+    target_net: &RecurrentDQN<B>,
+    batch: SequenceBatch,
+    optimizer: &mut O,
+    device: &B::Device,
+    config: &TrainingConfig,
+) -> (RecurrentDQN<B>, TrainStats) {
+    let mut policy_net = policy_net;
+    let SequenceBatch {
+        batch_size,
+        burn_in: burn_in_steps,
+        length,
+        observations,
+        states,
+        burn_in_mask,
+        actions,
+        rewards,
+        dones,
+        mask,
+    } = batch;
+    let window = burn_in_steps + length + 1;
+    let floats = |data: Vec<f32>, columns: usize| {
+        Tensor::<B, 2>::from_floats(
+            TensorData::new(data, Shape::new([batch_size, columns])),
+            device,
+        )
+    };
+
+    let observations = Tensor::<B, 3>::from_floats(
+        TensorData::new(
+            observations,
+            Shape::new([batch_size, window, OBSERVATION_LENGTH]),
+        ),
+        device,
+    );
+    let mut state = policy_net.state_from_vec(states.clone(), batch_size, device);
+    let mut target_state = target_net.state_from_vec(states, batch_size, device);
+    if burn_in_steps > 0 {
+        let burn_in_observations = observations
+            .clone()
+            .slice([0..batch_size, 0..burn_in_steps]);
+        let burn_in_mask = floats(burn_in_mask, burn_in_steps);
+        state = burn_in(
+            &policy_net,
+            burn_in_observations.clone(),
+            state,
+            burn_in_mask.clone(),
+        );
+        target_state = burn_in(target_net, burn_in_observations, target_state, burn_in_mask);
+    }
+
+    let observations = observations.slice([0..batch_size, burn_in_steps..window]);
+    let (q_values, _) = policy_net.forward(observations.clone(), Some(state));
+    let actions = Tensor::<B, 2, Int>::from_ints(
+        TensorData::new(actions, Shape::new([batch_size, length])),
+        device,
+    );
+    let taken_q: Tensor<B, 2> = q_values
+        .clone()
+        .slice([0..batch_size, 0..length])
+        .gather(2, actions.unsqueeze_dim(2))
+        .squeeze(2);
+
+    let (target_q, _) = target_net.forward(observations, Some(target_state));
+    let target_q = target_q.slice([0..batch_size, 1..length + 1]);
+    let next_q: Tensor<B, 2> = if config.double_dqn {
+        let next_actions = q_values
+            .slice([0..batch_size, 1..length + 1])
+            .detach()
+            .argmax(2);
+        target_q.gather(2, next_actions).squeeze(2)
+    } else {
+        target_q.max_dim(2).squeeze(2)
+    };
+    // Targets are constants as far as the optimizer is concerned
+    let next_q = next_q.detach();
+    let not_done = floats(dones, length).neg() + 1.0;
+    let expected_q = floats(rewards, length) + next_q * not_done * config.gamma;
+
+    let mask = floats(mask, length);
+    let count = mask.clone().sum();
+    let masked_mean = |values: Tensor<B, 2>| (values * mask.clone()).sum() / count.clone();
+    let mean_q = masked_mean(taken_q.clone().detach()).into_scalar().to_f32();
+    let td_error = masked_mean((expected_q.clone() - taken_q.clone()).detach().abs())
+        .into_scalar()
+        .to_f32();
+
+    let loss = match config.loss {
+        LossFunction::Mse => MseLoss::new().forward_no_reduction(taken_q, expected_q),
+        LossFunction::Huber { delta } => HuberLossConfig::new(delta)
+            .init()
+            .forward_no_reduction(taken_q, expected_q),
+    };
+    let loss = masked_mean(loss);
+    let loss_value = loss.clone().into_scalar().to_f32();
+    let mut grads = GradientsParams::from_grads(loss.backward(), &policy_net);
+    if let Some(max_norm) = config.max_grad_norm {
+        grads = clip_grad_norm(grads, &policy_net, max_norm);
+    }
+
+    policy_net = optimizer.step(config.learning_rate, policy_net, grads);
+    let stats = TrainStats {
+        loss: loss_value,
+        mean_q,
+        td_error,
+    };
+    (policy_net, stats)
+}
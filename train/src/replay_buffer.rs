@@ -1,18 +1,25 @@
 use core::{OBSERVATION_LENGTH, Observation};
+use std::collections::VecDeque;
 
 use burn::{
     prelude::Backend,
     tensor::{Bool, Float, Int, Shape, Tensor, TensorData},
 };
-use rand::{rngs::ThreadRng, seq::IndexedRandom};
+use rand::{Rng, seq::index};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug)]
+use crate::{checkpoint::TrainerRng, sum_tree::SumTree};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Experience {
     pub state: Observation,
     pub action: usize,
     pub reward: f32,
     pub next_state: Observation,
     pub is_done: bool,
+    /// How many discounted rewards `reward` adds up, `next_state` is this many steps
+    /// after `state`
+    pub steps: usize,
 }
 
 pub struct BatchTensors<B: Backend> {
@@ -21,26 +28,131 @@ pub struct BatchTensors<B: Backend> {
     pub rewards: Tensor<B, 1, Float>,
     pub next_states: Tensor<B, 2, Float>,
     pub is_dones: Tensor<B, 1, Bool>,
+    /// `Experience::steps`, as floats so they can be used as exponents
+    pub steps: Tensor<B, 1, Float>,
+    /// Importance sampling weights to scale each transition's loss by, all 1 when uniform
+    pub weights: Tensor<B, 1, Float>,
+    /// Where the transitions live in the buffer, for `update_priorities`
+    pub indices: Vec<usize>,
+    /// `ReplayBuffer::pushes` when the batch was sampled, for `update_sampled_priorities`
+    pub pushes: usize,
+}
+
+/// How transitions are picked for a batch
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Prioritization {
+    #[default]
+    Uniform,
+    /// In proportion to the TD error
+    Proportional,
+    /// By the rank of the TD error, which a few huge errors can't dominate
+    RankBased,
+}
+
+/// Missing fields fall back to `Default`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplayConfig {
+    pub prioritization: Prioritization,
+    /// How strongly priorities skew sampling, 0 samples uniformly
+    pub alpha: f64,
+    /// Importance sampling correction of the first batch, annealed linearly to full
+    /// correction over `beta_steps` batches
+    pub beta_start: f64,
+    pub beta_steps: usize,
+    /// Added to every priority so transitions with no TD error still get sampled
+    pub priority_epsilon: f32,
+    /// Batches between full re-sorts of the rank-based ordering, new transitions go to
+    /// the top in between
+    pub rank_resort_interval: usize,
 }
 
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            prioritization: Prioritization::Uniform,
+            alpha: 0.6,
+            beta_start: 0.4,
+            beta_steps: 100_000,
+            priority_epsilon: 1e-5,
+            rank_resort_interval: 100,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct ReplayBuffer {
     capacity: usize,
     position: usize,
     size: usize,
     buffer: Vec<Experience>,
-    rng: ThreadRng,
+    config: ReplayConfig,
+    /// `|TD error| + priority_epsilon` of every transition, empty when uniform
+    priorities: Vec<f32>,
+    /// New transitions get the highest priority seen so they're sampled at least once
+    max_priority: f32,
+    /// `priority^alpha` per transition, proportional only
+    tree: SumTree,
+    /// Running sum of `(1 / rank)^alpha` over ranks, rank-based only
+    rank_cdf: Vec<f64>,
+    batches_sampled: usize,
+    /// Saved with the buffer so a resumed run draws the same batches
+    rng: TrainerRng,
+    /// Indices from highest to lowest priority as of the last sort, rank-based only.
+    /// Missing from checkpoints written before it was cached, which sort on the next batch
+    #[serde(default)]
+    rank_order: Vec<usize>,
+    #[serde(default)]
+    batches_since_sort: usize,
+    /// Slots written since the last sort, oldest first, rank-based only
+    #[serde(default)]
+    pushed_since_sort: Vec<usize>,
+    /// Transitions ever pushed, to tell which slots were overwritten after a batch
+    #[serde(default)]
+    pushes: usize,
 }
 
 impl ReplayBuffer {
-    pub fn new(capacity: usize) -> Self {
+    /// Pass an RNG derived from the run's seed to make sampling reproducible
+    pub fn new(capacity: usize, rng: TrainerRng) -> Self {
         Self {
             capacity,
             buffer: Vec::with_capacity(capacity),
             position: 0,
             size: 0,
-            rng: rand::rng(),
+            config: ReplayConfig::default(),
+            priorities: vec![],
+            max_priority: 1.0,
+            tree: SumTree::new(0),
+            rank_cdf: vec![],
+            batches_sampled: 0,
+            rng,
+            rank_order: vec![],
+            batches_since_sort: 0,
+            pushed_since_sort: vec![],
+            pushes: 0,
         }
     }
+
+    /// Must be set while the buffer is still empty
+    pub fn with_config(mut self, config: ReplayConfig) -> Self {
+        assert_eq!(self.size, 0, "Replay config changed on a filled buffer");
+        if config.prioritization == Prioritization::Proportional {
+            self.tree = SumTree::new(self.capacity);
+        }
+        self.config = config;
+        self
+    }
+
+    /// Transitions stored, at most the capacity
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
     pub fn push(&mut self, experience: Experience) {
         if self.size < self.capacity {
             self.buffer.push(experience);
@@ -49,7 +161,159 @@ impl ReplayBuffer {
             self.buffer[self.position] = experience;
         }
 
+        match self.config.prioritization {
+            Prioritization::Uniform => {}
+            Prioritization::Proportional | Prioritization::RankBased => {
+                if self.priorities.len() < self.size {
+                    self.priorities.push(self.max_priority);
+                } else {
+                    self.priorities[self.position] = self.max_priority;
+                }
+                if self.config.prioritization == Prioritization::Proportional {
+                    self.tree.set(
+                        self.position,
+                        (self.max_priority as f64).powf(self.config.alpha),
+                    );
+                } else {
+                    if self.rank_cdf.len() < self.size {
+                        let rank = self.rank_cdf.len() + 1;
+                        let previous = self.rank_cdf.last().copied().unwrap_or(0.0);
+                        self.rank_cdf
+                            .push(previous + (rank as f64).powf(-self.config.alpha));
+                    }
+                    self.pushed_since_sort.push(self.position);
+                }
+            }
+        }
+
         self.position = (self.position + 1) % self.capacity;
+        self.pushes += 1;
+    }
+
+    /// Transitions pushed since the buffer was created
+    pub fn pushes(&self) -> usize {
+        self.pushes
+    }
+
+    /// `update_priorities` for a batch sampled when the buffer had taken `pushes` transitions.
+    /// Slots overwritten since then hold fresh transitions, which keep the highest priority
+    /// they were pushed with instead of inheriting the old transition's TD error.
+    pub fn update_sampled_priorities(
+        &mut self,
+        indices: &[usize],
+        pushes: usize,
+        td_errors: &[f32],
+    ) {
+        let written = self.pushes - pushes;
+        // Slots are written in ring order, the last `written` before `position` are new
+        let overwritten = |index: usize| {
+            written >= self.capacity
+                || (self.position + self.capacity - index - 1) % self.capacity < written
+        };
+        let (indices, td_errors): (Vec<usize>, Vec<f32>) = indices
+            .iter()
+            .zip(td_errors)
+            .filter(|&(&index, _)| !overwritten(index))
+            .map(|(&index, &td_error)| (index, td_error))
+            .unzip();
+        self.update_priorities(&indices, &td_errors);
+    }
+
+    /// Sets the priorities of a sampled batch from the TD errors it produced
+    pub fn update_priorities(&mut self, indices: &[usize], td_errors: &[f32]) {
+        if self.config.prioritization == Prioritization::Uniform {
+            return;
+        }
+        for (&index, td_error) in indices.iter().zip(td_errors) {
+            let priority = td_error.abs() + self.config.priority_epsilon;
+            self.priorities[index] = priority;
+            self.max_priority = self.max_priority.max(priority);
+            if self.config.prioritization == Prioritization::Proportional {
+                self.tree
+                    .set(index, (priority as f64).powf(self.config.alpha));
+            }
+        }
+    }
+
+    /// Picks transitions along with their sampling probabilities
+    fn sample_indices(&mut self, batch_size: usize) -> (Vec<usize>, Vec<f64>) {
+        match self.config.prioritization {
+            Prioritization::Uniform => {
+                let indices = index::sample(&mut self.rng, self.size, batch_size).into_vec();
+                let probabilities = vec![1.0 / self.size as f64; indices.len()];
+                (indices, probabilities)
+            }
+            Prioritization::Proportional => {
+                // One sample from each of `batch_size` equal slices keeps the batch spread out
+                let total = self.tree.total();
+                let segment = total / batch_size as f64;
+                (0..batch_size)
+                    .map(|i| {
+                        let mass = segment * (i as f64 + self.rng.random::<f64>());
+                        let index = self.tree.find(mass).min(self.size - 1);
+                        (index, self.tree.get(index) / total)
+                    })
+                    .unzip()
+            }
+            Prioritization::RankBased => {
+                // Sorting every batch would cost more than the training step, and a ranking
+                // a few batches stale samples much the same
+                if self.rank_order.is_empty()
+                    || self.batches_since_sort >= self.config.rank_resort_interval
+                {
+                    self.sort_ranks();
+                } else if !self.pushed_since_sort.is_empty() {
+                    self.promote_pushed();
+                }
+                self.batches_since_sort += 1;
+
+                let total = self.rank_cdf[self.size - 1];
+                let segment = total / batch_size as f64;
+                (0..batch_size)
+                    .map(|i| {
+                        let mass = segment * (i as f64 + self.rng.random::<f64>());
+                        let rank = self
+                            .rank_cdf
+                            .partition_point(|&cumulative| cumulative <= mass)
+                            .min(self.size - 1);
+                        let below = if rank == 0 {
+                            0.0
+                        } else {
+                            self.rank_cdf[rank - 1]
+                        };
+                        (self.rank_order[rank], (self.rank_cdf[rank] - below) / total)
+                    })
+                    .unzip()
+            }
+        }
+    }
+
+    fn sort_ranks(&mut self) {
+        let priorities = &self.priorities;
+        self.rank_order = (0..self.size).collect();
+        self.rank_order
+            .sort_unstable_by(|&a, &b| priorities[b].total_cmp(&priorities[a]));
+        self.batches_since_sort = 0;
+        self.pushed_since_sort.clear();
+    }
+
+    /// Moves the slots written since the last sort to the top of the ranking, newest first,
+    /// since they were pushed with the highest priority seen
+    fn promote_pushed(&mut self) {
+        let mut promoted = vec![false; self.size];
+        let mut order = Vec::with_capacity(self.size);
+        for index in self.pushed_since_sort.drain(..).rev() {
+            if !promoted[index] {
+                promoted[index] = true;
+                order.push(index);
+            }
+        }
+        order.extend(self.rank_order.iter().filter(|&&index| !promoted[index]));
+        self.rank_order = order;
+        // Only possible with a ranking from before pushes were tracked
+        if self.rank_order.len() != self.size {
+            self.sort_ranks();
+        }
     }
 
     pub fn sample_batch_tensors<B: Backend>(
@@ -62,15 +326,33 @@ impl ReplayBuffer {
         let mut rewards: Vec<f32> = Vec::with_capacity(batch_size);
         let mut next_states: Vec<f32> = Vec::with_capacity(batch_size * OBSERVATION_LENGTH); // Flattened
         let mut is_dones: Vec<bool> = Vec::with_capacity(batch_size);
+        let mut steps: Vec<f32> = Vec::with_capacity(batch_size);
 
-        for experience in self.buffer.choose_multiple(&mut self.rng, batch_size) {
+        let (indices, probabilities) = self.sample_indices(batch_size);
+        for &index in &indices {
+            let experience = &self.buffer[index];
             states.extend_from_slice(&experience.state.normalize());
             actions.push(experience.action as i32);
             rewards.push(experience.reward);
             next_states.extend_from_slice(&experience.next_state.normalize());
             is_dones.push(experience.is_done);
+            steps.push(experience.steps as f32);
         }
 
+        // Undo the bias of prioritized sampling, scaled so the largest weight is 1
+        let progress = self.batches_sampled as f64 / self.config.beta_steps.max(1) as f64;
+        let beta = self.config.beta_start + (1.0 - self.config.beta_start) * progress.min(1.0);
+        let weights: Vec<f64> = probabilities
+            .iter()
+            .map(|probability| (self.size as f64 * probability).powf(-beta))
+            .collect();
+        let max_weight = weights.iter().copied().fold(f64::MIN, f64::max);
+        let weights: Vec<f32> = weights
+            .iter()
+            .map(|weight| (weight / max_weight) as f32)
+            .collect();
+        self.batches_sampled += 1;
+
         let state_tensor_data =
             TensorData::new(states, Shape::new([batch_size, OBSERVATION_LENGTH]));
         let action_tensor_data = TensorData::new(actions, Shape::new([batch_size]));
@@ -78,6 +360,8 @@ impl ReplayBuffer {
         let next_state_tensor_data =
             TensorData::new(next_states, Shape::new([batch_size, OBSERVATION_LENGTH]));
         let is_dones_tensor_data = TensorData::new(is_dones, Shape::new([batch_size]));
+        let steps_tensor_data = TensorData::new(steps, Shape::new([batch_size]));
+        let weight_tensor_data = TensorData::new(weights, Shape::new([batch_size]));
 
         BatchTensors {
             states: Tensor::from_floats(state_tensor_data, device),
@@ -85,6 +369,70 @@ impl ReplayBuffer {
             rewards: Tensor::from_floats(reward_tensor_data, device),
             next_states: Tensor::from_floats(next_state_tensor_data, device),
             is_dones: Tensor::from_bool(is_dones_tensor_data, device),
+            steps: Tensor::from_floats(steps_tensor_data, device),
+            weights: Tensor::from_floats(weight_tensor_data, device),
+            indices,
+            pushes: self.pushes,
+        }
+    }
+}
+
+impl Extend<Experience> for ReplayBuffer {
+    fn extend<I: IntoIterator<Item = Experience>>(&mut self, experiences: I) {
+        for experience in experiences {
+            self.push(experience);
+        }
+    }
+}
+
+/// Turns the single-step transitions of one player in one fight into n-step transitions.
+/// Fights stepped side by side each need their own.
+pub struct NStepAccumulator {
+    n: usize,
+    gamma: f32,
+    /// Transitions still collecting rewards, oldest first
+    pending: VecDeque<Experience>,
+}
+
+impl NStepAccumulator {
+    pub fn new(n: usize, gamma: f32) -> Self {
+        assert!(n > 0, "n-step returns need at least one step");
+        Self {
+            n,
+            gamma,
+            pending: VecDeque::with_capacity(n),
+        }
+    }
+
+    /// Adds the latest single-step transition and moves every transition that has
+    /// collected `n` rewards into `buffer`. When `episode_over`, the rest go in as
+    /// shorter transitions, bootstrapping from the last state unless `is_done`.
+    ///
+    /// `buffer` is usually a `ReplayBuffer`, or a `Vec` to hand transitions over in batches.
+    pub fn push(
+        &mut self,
+        experience: Experience,
+        episode_over: bool,
+        buffer: &mut impl Extend<Experience>,
+    ) {
+        debug_assert_eq!(experience.steps, 1, "Expected a single-step transition");
+        for pending in &mut self.pending {
+            pending.reward += self.gamma.powi(pending.steps as i32) * experience.reward;
+            pending.steps += 1;
+            pending.next_state = experience.next_state;
+            pending.is_done = experience.is_done;
+        }
+        self.pending.push_back(experience);
+
+        while self
+            .pending
+            .front()
+            .is_some_and(|pending| pending.steps >= self.n)
+        {
+            buffer.extend(self.pending.pop_front());
+        }
+        if episode_over {
+            buffer.extend(self.pending.drain(..));
         }
     }
 }
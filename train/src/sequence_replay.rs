@@ -0,0 +1,199 @@
+use core::{OBSERVATION_LENGTH, Observation};
+use std::collections::VecDeque;
+
+use rand::Rng;
+
+use crate::checkpoint::TrainerRng;
+
+/// One player's fight as it was played, so it can be replayed in order
+#[derive(Clone, Debug)]
+pub struct EpisodeRecord {
+    /// One more than there are steps, the last one is where the last step led
+    observations: Vec<Observation>,
+    actions: Vec<usize>,
+    rewards: Vec<f32>,
+    /// The recurrent state before each step, as flattened by `RecurrentState::to_vec`
+    states: Vec<Vec<f32>>,
+    /// Whether the fight ended by knockout rather than the time limit
+    terminated: bool,
+}
+
+impl EpisodeRecord {
+    pub fn new(first_observation: Observation) -> Self {
+        Self {
+            observations: vec![first_observation],
+            actions: vec![],
+            rewards: vec![],
+            states: vec![],
+            terminated: false,
+        }
+    }
+
+    /// Records a step taken from `state`
+    pub fn push(
+        &mut self,
+        state: Vec<f32>,
+        action: usize,
+        reward: f32,
+        next_observation: Observation,
+    ) {
+        self.states.push(state);
+        self.actions.push(action);
+        self.rewards.push(reward);
+        self.observations.push(next_observation);
+    }
+
+    pub fn finish(&mut self, terminated: bool) {
+        self.terminated = terminated;
+    }
+
+    /// Steps taken
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+/// Windows of replayed fights, flattened row by row. Each row burns in on up to `burn_in`
+/// steps before its first trained step and then trains on up to `length` steps, positions
+/// outside the row's episode are zeros and masked out.
+#[derive(Clone, Debug)]
+pub struct SequenceBatch {
+    pub batch_size: usize,
+    pub burn_in: usize,
+    pub length: usize,
+    /// Normalized, `[batch_size, burn_in + length + 1, OBSERVATION_LENGTH]`. The extra
+    /// observation is where the last trained step led.
+    pub observations: Vec<f32>,
+    /// State before the first burn-in step that's in the episode, `[batch_size, state size]`
+    pub states: Vec<f32>,
+    /// 1 where a burn-in step is in the episode, `[batch_size, burn_in]`
+    pub burn_in_mask: Vec<f32>,
+    /// The rest are `[batch_size, length]`
+    pub actions: Vec<i32>,
+    pub rewards: Vec<f32>,
+    /// 1 where the step knocked someone out, so nothing is bootstrapped past it
+    pub dones: Vec<f32>,
+    /// 1 where the step is in the episode and trained on
+    pub mask: Vec<f32>,
+}
+
+/// Whole episodes for recurrent networks, which need to see steps in the order they were
+/// played
+pub struct SequenceReplay {
+    episodes: VecDeque<EpisodeRecord>,
+    /// Steps over all stored episodes
+    steps: usize,
+    capacity: usize,
+    rng: TrainerRng,
+}
+
+impl SequenceReplay {
+    /// Keeps at most `capacity` steps, in whole episodes
+    pub fn new(capacity: usize, rng: TrainerRng) -> Self {
+        Self {
+            episodes: VecDeque::new(),
+            steps: 0,
+            capacity,
+            rng,
+        }
+    }
+
+    /// Drops the oldest episodes once more than `capacity` steps are stored
+    pub fn push(&mut self, episode: EpisodeRecord) {
+        if episode.is_empty() {
+            return;
+        }
+        self.steps += episode.len();
+        self.episodes.push_back(episode);
+        while self.steps > self.capacity && self.episodes.len() > 1 {
+            let dropped = self.episodes.pop_front().expect("Should have an episode");
+            self.steps -= dropped.len();
+        }
+    }
+
+    /// Steps stored
+    pub fn len(&self) -> usize {
+        self.steps
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps == 0
+    }
+
+    /// Trained steps start anywhere in any stored episode with the same odds, burn-in
+    /// reaches back from there as far as the episode goes
+    pub fn sample(&mut self, batch_size: usize, burn_in: usize, length: usize) -> SequenceBatch {
+        assert!(!self.is_empty(), "Can't sample an empty sequence replay");
+        let window = burn_in + length + 1;
+        let mut batch = SequenceBatch {
+            batch_size,
+            burn_in,
+            length,
+            observations: Vec::with_capacity(batch_size * window * OBSERVATION_LENGTH),
+            states: vec![],
+            burn_in_mask: Vec::with_capacity(batch_size * burn_in),
+            actions: Vec::with_capacity(batch_size * length),
+            rewards: Vec::with_capacity(batch_size * length),
+            dones: Vec::with_capacity(batch_size * length),
+            mask: Vec::with_capacity(batch_size * length),
+        };
+
+        for _ in 0..batch_size {
+            let step = self.rng.random_range(0..self.steps);
+            let (episode, start) = self.locate(step);
+            let steps = episode.len();
+            let first = start.saturating_sub(burn_in);
+
+            for position in 0..window {
+                // Where this position of the window falls in the episode, if it does
+                let index = (position + start)
+                    .checked_sub(burn_in)
+                    .filter(|&index| index <= steps);
+                match index {
+                    Some(index) => batch
+                        .observations
+                        .extend(episode.observations[index].normalize()),
+                    None => batch.observations.extend([0.0; OBSERVATION_LENGTH]),
+                }
+                if position < burn_in {
+                    batch
+                        .burn_in_mask
+                        .push(if index.is_some() { 1.0 } else { 0.0 });
+                }
+            }
+            batch.states.extend(&episode.states[first]);
+
+            for offset in 0..length {
+                let step = start + offset;
+                if step < steps {
+                    batch.actions.push(episode.actions[step] as i32);
+                    batch.rewards.push(episode.rewards[step]);
+                    let done = episode.terminated && step + 1 == steps;
+                    batch.dones.push(if done { 1.0 } else { 0.0 });
+                    batch.mask.push(1.0);
+                } else {
+                    batch.actions.push(0);
+                    batch.rewards.push(0.0);
+                    batch.dones.push(0.0);
+                    batch.mask.push(0.0);
+                }
+            }
+        }
+        batch
+    }
+
+    /// The episode holding the `step`th stored step, and the step's index in it
+    fn locate(&self, mut step: usize) -> (&EpisodeRecord, usize) {
+        for episode in &self.episodes {
+            if step < episode.len() {
+                return (episode, step);
+            }
+            step -= episode.len();
+        }
+        unreachable!("Step should be within the stored episodes")
+    }
+}
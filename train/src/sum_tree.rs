@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+/// Binary tree where every node holds the sum of its two children, so a leaf can be
+/// picked in proportion to its value and updated in O(log n)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SumTree {
+    /// Node 1 is the root and node `i` has children `2i` and `2i + 1`, leaves start at
+    /// `leaf_offset`
+    nodes: Vec<f64>,
+    leaf_offset: usize,
+}
+
+impl SumTree {
+    pub fn new(capacity: usize) -> Self {
+        let leaf_offset = capacity.next_power_of_two();
+        Self {
+            nodes: vec![0.0; 2 * leaf_offset],
+            leaf_offset,
+        }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.nodes[1]
+    }
+
+    pub fn get(&self, index: usize) -> f64 {
+        self.nodes[self.leaf_offset + index]
+    }
+
+    pub fn set(&mut self, index: usize, value: f64) {
+        let mut node = self.leaf_offset + index;
+        self.nodes[node] = value;
+        // Parents are recomputed rather than adjusted by the difference, so rounding
+        // errors don't pile up over millions of updates
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = self.nodes[2 * node] + self.nodes[2 * node + 1];
+        }
+    }
+
+    /// Index of the leaf whose slice of `0..total()` contains `mass`
+    pub fn find(&self, mut mass: f64) -> usize {
+        let mut node = 1;
+        while node < self.leaf_offset {
+            let left = self.nodes[2 * node];
+            // Never walk into an empty subtree, `mass` can round up to the total
+            if mass < left || self.nodes[2 * node + 1] <= 0.0 {
+                node *= 2;
+            } else {
+                mass -= left;
+                node = 2 * node + 1;
+            }
+        }
+        node - self.leaf_offset
+    }
+}
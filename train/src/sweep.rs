@@ -0,0 +1,314 @@
+use core::{bots::ScriptedBot, model::DQN};
+use std::{collections::BTreeMap, path::PathBuf, thread};
+
+use burn::{module::AutodiffModule, tensor::backend::AutodiffBackend};
+use rand::{Rng, SeedableRng, seq::IndexedRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    checkpoint::{Checkpoint, TrainerRng},
+    evaluation::Entrant,
+    train::{DqnLearner, TrainingConfig, train, train_against},
+};
+
+/// Exploration of the fixed opponents in `Against` sweeps
+const TEACHER_EPSILON: f32 = 0.05;
+
+/// How the runs of a sweep are picked
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Search {
+    /// Every combination of every parameter's values
+    Grid,
+    /// Independent draws from every parameter
+    Random { samples: usize },
+}
+
+/// Where a swept parameter's values come from. Ranges that can't be sampled fail to parse.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedParameterSpace")]
+pub enum ParameterSpace {
+    /// Each in turn when searching a grid, one at random otherwise
+    Values(Vec<Value>),
+    /// Random search only
+    Uniform { low: f64, high: f64 },
+    /// Random search only, uniform in log space for rates and decays
+    LogUniform { low: f64, high: f64 },
+}
+
+/// `ParameterSpace` as written, before its ranges are checked
+#[derive(Deserialize)]
+enum UncheckedParameterSpace {
+    Values(Vec<Value>),
+    Uniform { low: f64, high: f64 },
+    LogUniform { low: f64, high: f64 },
+}
+
+impl TryFrom<UncheckedParameterSpace> for ParameterSpace {
+    type Error = String;
+
+    fn try_from(space: UncheckedParameterSpace) -> Result<Self, Self::Error> {
+        if let UncheckedParameterSpace::Uniform { low, high }
+        | UncheckedParameterSpace::LogUniform { low, high } = space
+            && !(low.is_finite() && high.is_finite())
+        {
+            return Err(format!("Range bounds need to be finite, got {low}..{high}"));
+        }
+        match space {
+            UncheckedParameterSpace::Values(values) => {
+                if values.is_empty() {
+                    return Err("Values needs at least one value".to_string());
+                }
+                Ok(Self::Values(values))
+            }
+            UncheckedParameterSpace::Uniform { low, high } => {
+                if low > high {
+                    return Err(format!(
+                        "Uniform range needs low <= high, got {low}..{high}"
+                    ));
+                }
+                Ok(Self::Uniform { low, high })
+            }
+            UncheckedParameterSpace::LogUniform { low, high } => {
+                if low <= 0.0 || low > high {
+                    return Err(format!(
+                        "LogUniform range needs 0 < low <= high, got {low}..{high}"
+                    ));
+                }
+                Ok(Self::LogUniform { low, high })
+            }
+        }
+    }
+}
+
+/// What every run of a sweep trains
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub enum TrainerSpec {
+    /// Two fresh models against each other, like `selfplay`
+    #[default]
+    Selfplay,
+    /// One model against fixed opponents, like `against`
+    Against {
+        #[serde(default)]
+        student: Option<PathBuf>,
+        #[serde(default)]
+        teachers: Vec<PathBuf>,
+        #[serde(default)]
+        bots: Vec<ScriptedBot>,
+    },
+}
+
+/// A sweep as written in its JSON file:
+///
+/// ```json
+/// {
+///   "base": {"num_episodes": 2000, "model": {...}},
+///   "search": "Grid",
+///   "parameters": {
+///     "learning_rate": {"Values": [0.0001, 0.0005]},
+///     "replay.alpha": {"Values": [0.4, 0.6]}
+///   }
+/// }
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SweepSpec {
+    /// Config every run starts from, missing fields are `TrainingConfig` defaults
+    #[serde(default)]
+    pub base: Value,
+    pub search: Search,
+    /// `TrainingConfig` fields by name, nested ones joined with dots
+    pub parameters: BTreeMap<String, ParameterSpace>,
+    #[serde(default)]
+    pub trainer: TrainerSpec,
+    /// Runs trained at the same time
+    #[serde(default = "default_threads")]
+    pub threads: usize,
+    /// Seeds random search and the evaluation
+    #[serde(default)]
+    pub seed: u64,
+    /// Fights per pair of models in the final round robin
+    #[serde(default = "default_eval_games")]
+    pub eval_games: usize,
+}
+
+fn default_threads() -> usize {
+    1
+}
+
+fn default_eval_games() -> usize {
+    4
+}
+
+/// One run of a sweep
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SweepRun {
+    /// The swept parameters' values, the rest is the base config
+    pub overrides: BTreeMap<String, Value>,
+    pub config: TrainingConfig,
+}
+
+impl SweepSpec {
+    /// Models every run produces
+    pub fn models_per_run(&self) -> usize {
+        match self.trainer {
+            TrainerSpec::Selfplay => 2,
+            TrainerSpec::Against { .. } => 1,
+        }
+    }
+
+    /// Turns the spec into concrete configs, in a fixed order so model numbers are stable
+    pub fn runs(&self) -> Result<Vec<SweepRun>, Box<dyn std::error::Error>> {
+        let base: TrainingConfig = if self.base.is_null() {
+            TrainingConfig::default()
+        } else {
+            serde_json::from_value(self.base.clone())?
+        };
+        let base = serde_json::to_value(&base)?;
+
+        let assignments: Vec<BTreeMap<String, Value>> = match self.search {
+            Search::Grid => {
+                let mut assignments = vec![BTreeMap::new()];
+                for (name, space) in &self.parameters {
+                    let ParameterSpace::Values(values) = space else {
+                        return Err(
+                            format!("{name} needs a list of values to search a grid").into()
+                        );
+                    };
+                    assignments = assignments
+                        .into_iter()
+                        .flat_map(|assignment| {
+                            values.iter().map(move |value| {
+                                let mut assignment = assignment.clone();
+                                assignment.insert(name.clone(), value.clone());
+                                assignment
+                            })
+                        })
+                        .collect();
+                }
+                assignments
+            }
+            Search::Random { samples } => {
+                let mut rng = TrainerRng::seed_from_u64(self.seed);
+                (0..samples)
+                    .map(|_| {
+                        self.parameters
+                            .iter()
+                            .map(|(name, space)| (name.clone(), space.sample(&mut rng)))
+                            .collect()
+                    })
+                    .collect()
+            }
+        };
+
+        assignments
+            .into_iter()
+            .map(|overrides| {
+                let mut config = base.clone();
+                for (name, value) in &overrides {
+                    set_field(&mut config, name, value.clone())?;
+                }
+                Ok(SweepRun {
+                    config: serde_json::from_value(config)?,
+                    overrides,
+                })
+            })
+            .collect()
+    }
+}
+
+impl ParameterSpace {
+    fn sample(&self, rng: &mut impl Rng) -> Value {
+        match self {
+            Self::Values(values) => values
+                .choose(rng)
+                .cloned()
+                .expect("Should have values, empty lists don't parse"),
+            Self::Uniform { low, high } => Value::from(rng.random_range(*low..=*high)),
+            Self::LogUniform { low, high } => {
+                Value::from(rng.random_range(low.ln()..=high.ln()).exp())
+            }
+        }
+    }
+}
+
+/// Sets a dotted path like `replay.alpha`, which has to exist already so typos don't pass
+fn set_field(config: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    let mut field = config;
+    for key in path.split('.') {
+        field = field
+            .get_mut(key)
+            .ok_or_else(|| format!("TrainingConfig has no field {path}"))?;
+    }
+    *field = value;
+    Ok(())
+}
+
+/// What a sweep trains against once its model files are loaded
+#[allow(clippy::large_enum_variant)]
+pub enum Trainer<B: AutodiffBackend> {
+    Selfplay,
+    Against {
+        student: Option<DQN<B>>,
+        opponents: Vec<Entrant<B::InnerBackend>>,
+    },
+}
+
+/// Trains every run, `threads` at a time, and hands each run's models to `finish` as soon
+/// as they're done. Runs are dealt to threads in turn, so with one thread they go in order.
+///
+/// With more than one thread the runs share the backend's RNG, so their weight init isn't
+/// reproducible.
+pub fn run_sweep<B: AutodiffBackend>(
+    runs: &[SweepRun],
+    trainer: &Trainer<B>,
+    threads: usize,
+    device: &B::Device,
+    finish: &(dyn Fn(usize, Vec<DQN<B::InnerBackend>>) + Sync),
+) {
+    let threads = threads.clamp(1, runs.len().max(1));
+    thread::scope(|scope| {
+        for thread_index in 0..threads {
+            let jobs: Vec<(usize, TrainingConfig)> = runs
+                .iter()
+                .enumerate()
+                .skip(thread_index)
+                .step_by(threads)
+                .map(|(index, run)| (index, run.config.clone()))
+                .collect();
+            // Modules can be sent between threads but not shared
+            let (student, opponents) = match trainer {
+                Trainer::Selfplay => (None, vec![]),
+                Trainer::Against { student, opponents } => (student.clone(), opponents.clone()),
+            };
+            let selfplay = matches!(trainer, Trainer::Selfplay);
+
+            scope.spawn(move || {
+                for (index, config) in jobs {
+                    println!("Starting sweep run {index}");
+                    B::seed(config.seed);
+                    let models = if selfplay {
+                        let learners = [(); 2].map(|_| {
+                            DqnLearner::<B>::new(config.model.init(device), device, &config)
+                        });
+                        train(learners, device, &config, None)
+                            .into_iter()
+                            .map(|learner| learner.policy_net.valid())
+                            .collect()
+                    } else {
+                        let policy_net =
+                            student.clone().unwrap_or_else(|| config.model.init(device));
+                        let learner = DqnLearner::new(policy_net, device, &config);
+                        let teachers = opponents
+                            .iter()
+                            .map(|opponent| opponent.agent(TEACHER_EPSILON, device))
+                            .collect();
+                        let checkpoint =
+                            train_against(Checkpoint::new(learner, config), teachers, device, None);
+                        vec![checkpoint.learner.policy_net.valid()]
+                    };
+                    finish(index, models);
+                }
+            });
+        }
+    });
+}
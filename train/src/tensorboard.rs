@@ -0,0 +1,127 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Writes scalar summaries in TensorBoard's event file format, a TFRecord stream of `Event`
+/// protos. Only the handful of proto fields scalars need are encoded, by hand.
+pub struct EventWriter {
+    writer: BufWriter<File>,
+}
+
+impl EventWriter {
+    /// Creates `events.out.tfevents.<seconds>.boxing` in `dir`. TensorBoard reads every
+    /// event file in a directory, so a resumed run just adds another, starting at
+    /// `first_step`. Anything earlier files logged from `first_step` on is discarded.
+    pub fn create(dir: &Path, first_step: i64) -> io::Result<Self> {
+        let path =
+            PathBuf::from(dir).join(format!("events.out.tfevents.{}.boxing", wall_time() as u64));
+        let mut writer = Self {
+            writer: BufWriter::new(File::create(path)?),
+        };
+
+        // Readers check the version before anything else
+        let mut event = vec![];
+        encode_double(&mut event, 1, wall_time());
+        encode_bytes(&mut event, 3, b"brain.Event:2");
+        writer.write_record(&event)?;
+
+        // A `SessionLog` with status START makes TensorBoard purge the steps it restarts from
+        let mut session_log = vec![];
+        encode_varint_field(&mut session_log, 1, 1);
+        let mut event = vec![];
+        encode_double(&mut event, 1, wall_time());
+        encode_varint_field(&mut event, 2, first_step as u64);
+        encode_bytes(&mut event, 7, &session_log);
+        writer.write_record(&event)?;
+        Ok(writer)
+    }
+
+    /// One event holding every `(tag, value)` pair at `step`
+    pub fn add_scalars(&mut self, step: i64, scalars: &[(&str, f32)]) -> io::Result<()> {
+        let mut summary = vec![];
+        for (tag, value) in scalars {
+            let mut summary_value = vec![];
+            encode_bytes(&mut summary_value, 1, tag.as_bytes());
+            encode_float(&mut summary_value, 2, *value);
+            encode_bytes(&mut summary, 1, &summary_value);
+        }
+
+        let mut event = vec![];
+        encode_double(&mut event, 1, wall_time());
+        encode_varint_field(&mut event, 2, step as u64);
+        encode_bytes(&mut event, 5, &summary);
+        self.write_record(&event)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write_record(&mut self, data: &[u8]) -> io::Result<()> {
+        let length = (data.len() as u64).to_le_bytes();
+        self.writer.write_all(&length)?;
+        self.writer
+            .write_all(&masked_crc32c(&length).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(&masked_crc32c(data).to_le_bytes())
+    }
+}
+
+fn wall_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |time| time.as_secs_f64())
+}
+
+fn encode_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn encode_varint_field(buffer: &mut Vec<u8>, field: u64, value: u64) {
+    encode_varint(buffer, field << 3);
+    encode_varint(buffer, value);
+}
+
+fn encode_double(buffer: &mut Vec<u8>, field: u64, value: f64) {
+    encode_varint(buffer, (field << 3) | 1);
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn encode_float(buffer: &mut Vec<u8>, field: u64, value: f32) {
+    encode_varint(buffer, (field << 3) | 5);
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn encode_bytes(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    encode_varint(buffer, (field << 3) | 2);
+    encode_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+/// CRC-32C (Castagnoli), bit by bit since records are small
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// TFRecord stores checksums rotated and offset so a CRC of a CRC doesn't look valid
+pub fn masked_crc32c(data: &[u8]) -> u32 {
+    crc32c(data).rotate_right(15).wrapping_add(0xA282_EAD8)
+}
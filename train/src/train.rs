@@ -1,18 +1,39 @@
 use core::{
-    Control, GameState, OBSERVATION_LENGTH, OUTPUT_SIZE, Observation, StepResult,
-    model::{DQN, DQNConfig},
-    select_action,
+    OBSERVATION_LENGTH, OUTPUT_SIZE,
+    agent::Agent,
+    env::{BoxingEnv, EnvStep, GameEnv, TimeLimit},
+    model::{DQN, DQNConfig, load_dqn, save_dqn},
+    select_action, select_actions,
+    vec_env::VecBoxingEnv,
 };
 
+use std::{fs, path::Path};
+
 use burn::{
-    nn::loss::{MseLoss, Reduction},
-    optim::{AdamConfig, GradientsParams, Optimizer},
+    module::{AutodiffModule, ModuleMapper, ModuleVisitor, ParamId},
+    nn::loss::{HuberLossConfig, MseLoss},
+    optim::{Adam, AdamConfig, GradientsParams, Optimizer, adaptor::OptimizerAdaptor},
     prelude::*,
+    record::{FullPrecisionSettings, NamedMpkFileRecorder, Recorder},
     tensor::{backend::AutodiffBackend, cast::ToElement},
 };
-use rand::{Rng, SeedableRng, rngs::StdRng};
-
-use crate::replay_buffer::{BatchTensors, Experience, ReplayBuffer};
+use rand::{Rng, SeedableRng};
+
+use crate::{
+    actor_learner::ActorLearnerConfig,
+    benchmark::{Benchmark, BenchmarkConfig, BenchmarkState, report},
+    checkpoint::{Checkpoint, TrainerRng, TrainerState, save_checkpoint},
+    distributional::{DistributionalLosses, distributional_losses},
+    evaluation::Entrant,
+    league::LeagueConfig,
+    metrics::{EpisodeTracker, MetricsConfig, MetricsLogger},
+    pbt::PbtConfig,
+    ppo::PpoConfig,
+    recurrent::RecurrentConfig,
+    replay_buffer::{
+        BatchTensors, Experience, NStepAccumulator, Prioritization, ReplayBuffer, ReplayConfig,
+    },
+};
 
 static EPS_MIN: f32 = 0.005;
 
@@ -21,9 +42,30 @@ pub fn get_epsilon(steps_done: usize, epsilon_start: f32, decay: f32) -> f32 {
     EPS_MIN + (epsilon_start - EPS_MIN) * (-decay * steps_done as f32).exp()
 }
 
-#[derive(serde::Serialize)]
+/// How the target network follows the policy network
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum TargetUpdate {
+    /// Copy the policy net every `interval` environment steps
+    Hard { interval: usize },
+    /// Move the target a `tau` fraction of the way to the policy net after every gradient step
+    Polyak { tau: f64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LossFunction {
+    /// Squared TD error. Quantile heads can't regress quantiles on squared errors, so there
+    /// it stands for the plain quantile loss on absolute errors
+    Mse,
+    /// Quadratic within `delta` of the target and linear beyond, so a surprise knockout
+    /// doesn't produce a huge gradient. Quantile heads use it as the quantile Huber loss
+    Huber { delta: f32 },
+}
+
+/// Missing fields fall back to `Default`, so configs written by older versions still load
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct TrainingConfig {
-    #[serde(skip)]
+    #[serde(skip, default = "AdamConfig::new")]
     pub optimizer: AdamConfig,
     pub gamma: f32,
     pub batch_size: usize,
@@ -34,15 +76,285 @@ pub struct TrainingConfig {
     pub epsilon_start: f32,
     pub seed: u64,
     pub iters_per_training_step: usize,
+    /// Number of fights stepped in lockstep by the self-play trainer
+    pub num_envs: usize,
+    pub num_env_threads: usize,
+    /// Episodes between checkpoints of `train_against`, 0 only checkpoints at the end
+    pub checkpoint_interval: usize,
+    /// Let the policy net pick the next action and the target net score it, instead of
+    /// taking the target net's max which overestimates Q values
+    pub double_dqn: bool,
+    /// Architecture of freshly initialized networks
+    pub model: DQNConfig,
+    /// Uniform or prioritized sampling from the replay buffer
+    pub replay: ReplayConfig,
+    /// Rewards summed into each stored transition before bootstrapping from the target net,
+    /// so the win bonus reaches earlier decisions faster
+    pub n_step: usize,
+    pub target_update: TargetUpdate,
+    pub loss: LossFunction,
+    /// Rescale the gradients of a step when their combined L2 norm exceeds this
+    pub max_grad_norm: Option<f32>,
+    /// Snapshot pool and opponent sampling of `train_league`
+    pub league: LeagueConfig,
+    /// Population size, rounds and perturbations of `train_population`
+    pub pbt: PbtConfig,
+    /// Per-episode metrics files written next to checkpoints
+    pub metrics: MetricsConfig,
+    /// Evaluation against fixed opponents during training
+    pub benchmark: BenchmarkConfig,
+    /// Actor threads and weight syncing of `train_actor_learner`
+    pub actor_learner: ActorLearnerConfig,
+    /// Rollouts and losses of `train_ppo`
+    pub ppo: PpoConfig,
+    /// Sequence replay of `train_recurrent`
+    pub recurrent: RecurrentConfig,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        Self {
+            optimizer: AdamConfig::new(),
+            gamma: 0.999,
+            batch_size: 128,
+            learning_rate: 0.0001,
+            num_episodes: 20000,
+            max_iters: 200_000,
+            epsilon_decay: 0.0001,
+            epsilon_start: 1.0,
+            seed: 26,
+            iters_per_training_step: 8,
+            num_envs: 1,
+            num_env_threads: 1,
+            checkpoint_interval: 100,
+            double_dqn: false,
+            model: DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE),
+            replay: ReplayConfig::default(),
+            n_step: 1,
+            target_update: TargetUpdate::Hard { interval: 250 },
+            loss: LossFunction::Mse,
+            max_grad_norm: None,
+            league: LeagueConfig::default(),
+            pbt: PbtConfig::default(),
+            metrics: MetricsConfig::default(),
+            benchmark: BenchmarkConfig::default(),
+            actor_learner: ActorLearnerConfig::default(),
+            ppo: PpoConfig::default(),
+            recurrent: RecurrentConfig::default(),
+        }
+    }
+}
+
+pub type DqnOptimizer<B> = OptimizerAdaptor<Adam, DQN<B>, B>;
+
+/// A network with its target network and optimizer. Adam's moment estimates only mean
+/// something if the optimizer outlives a single training step, so they travel together.
+#[derive(Clone)]
+pub struct DqnLearner<B: AutodiffBackend> {
+    pub policy_net: DQN<B>,
+    pub target_net: DQN<B>,
+    pub optimizer: DqnOptimizer<B>,
+}
+
+impl<B: AutodiffBackend> DqnLearner<B> {
+    pub fn new(policy_net: DQN<B>, device: &B::Device, config: &TrainingConfig) -> Self {
+        Self {
+            target_net: policy_net.config().init(device),
+            policy_net,
+            optimizer: config.optimizer.init(),
+        }
+    }
+
+    pub fn update_target(&mut self) {
+        self.target_net = self.policy_net.clone();
+    }
+
+    /// Blends `tau` of the policy net's weights into the target net
+    pub fn soft_update_target(&mut self, tau: f64) {
+        self.target_net = polyak_update(self.target_net.clone(), &self.policy_net, tau);
+    }
+
+    /// Call after every environment step, `trained` says whether it took a gradient step
+    pub fn after_step(&mut self, steps_done: usize, trained: bool, update: TargetUpdate) {
+        match update {
+            TargetUpdate::Hard { interval } => {
+                if steps_done.is_multiple_of(interval) {
+                    self.update_target();
+                }
+            }
+            TargetUpdate::Polyak { tau } => {
+                if trained {
+                    self.soft_update_target(tau);
+                }
+            }
+        }
+    }
+
+    /// Writes the policy, target and optimizer records into `dir`
+    pub fn save(&self, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+        save_dqn(self.policy_net.clone(), &recorder, dir.join("policy"))?;
+        save_dqn(self.target_net.clone(), &recorder, dir.join("target"))?;
+        recorder.record(self.optimizer.to_record(), dir.join("optimizer"))?;
+        Ok(())
+    }
+
+    /// Picks up a learner written by `save`, optimizer state included
+    pub fn load(
+        dir: &Path,
+        device: &B::Device,
+        config: &TrainingConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+        let policy_net = load_dqn(&recorder, dir.join("policy"), device)?;
+        let target_net = load_dqn(&recorder, dir.join("target"), device)?;
+        let optimizer_record = recorder.load(dir.join("optimizer"), device)?;
+        let optimizer = config.optimizer.init().load_record(optimizer_record);
+        Ok(Self {
+            policy_net,
+            target_net,
+            optimizer,
+        })
+    }
+}
+
+/// Every float parameter of a module, flattened, in visiting order
+struct FloatParams<B: Backend>(Vec<Tensor<B, 1>>);
+
+impl<B: Backend> ModuleVisitor<B> for FloatParams<B> {
+    fn visit_float<const D: usize>(&mut self, _id: ParamId, tensor: &Tensor<B, D>) {
+        self.0.push(tensor.clone().reshape([-1]));
+    }
+}
+
+/// Target and policy nets share an architecture, so their parameters come in the same
+/// order even though their ids differ
+struct PolyakMapper<B: Backend> {
+    policy_params: std::vec::IntoIter<Tensor<B, 1>>,
+    tau: f64,
 }
 
-pub fn train_step<B: AutodiffBackend>(
+impl<B: Backend> ModuleMapper<B> for PolyakMapper<B> {
+    fn map_float<const D: usize>(&mut self, _id: ParamId, tensor: Tensor<B, D>) -> Tensor<B, D> {
+        let policy = self
+            .policy_params
+            .next()
+            .expect("Target net should have the policy net's architecture")
+            .reshape(tensor.shape());
+        (tensor * (1.0 - self.tau) + policy * self.tau).detach()
+    }
+}
+
+/// Blends `tau` of `policy`'s weights into `target`, which must share its architecture
+pub(crate) fn polyak_update<B: Backend, M: Module<B>>(target: M, policy: &M, tau: f64) -> M {
+    let mut policy_params = FloatParams(vec![]);
+    policy.visit(&mut policy_params);
+    let mut mapper = PolyakMapper {
+        policy_params: policy_params.0.into_iter(),
+        tau,
+    };
+    target.map(&mut mapper)
+}
+
+/// Adds up the squared L2 norms of all gradients of a module
+struct GradNorm<'a, B: AutodiffBackend> {
+    grads: &'a GradientsParams,
+    sum_of_squares: Option<Tensor<B::InnerBackend, 1>>,
+}
+
+impl<B: AutodiffBackend> ModuleVisitor<B> for GradNorm<'_, B> {
+    fn visit_float<const D: usize>(&mut self, id: ParamId, _tensor: &Tensor<B, D>) {
+        if let Some(grad) = self.grads.get::<B::InnerBackend, D>(id) {
+            let squares = grad.powi_scalar(2).sum();
+            self.sum_of_squares = Some(match self.sum_of_squares.take() {
+                Some(sum) => sum + squares,
+                None => squares,
+            });
+        }
+    }
+}
+
+struct GradScale<'a, B: AutodiffBackend> {
+    grads: &'a mut GradientsParams,
+    scale: f32,
+    backend: std::marker::PhantomData<B>,
+}
+
+impl<B: AutodiffBackend> ModuleVisitor<B> for GradScale<'_, B> {
+    fn visit_float<const D: usize>(&mut self, id: ParamId, _tensor: &Tensor<B, D>) {
+        if let Some(grad) = self.grads.remove::<B::InnerBackend, D>(id) {
+            self.grads.register(id, grad * self.scale);
+        }
+    }
+}
+
+/// Scales all gradients together so their global norm is at most `max_norm`. Unlike
+/// burn's per-parameter clipping this keeps the direction of the step.
+pub(crate) fn clip_grad_norm<B: AutodiffBackend, M: Module<B>>(
+    mut grads: GradientsParams,
+    model: &M,
+    max_norm: f32,
+) -> GradientsParams {
+    let mut norm = GradNorm::<B> {
+        grads: &grads,
+        sum_of_squares: None,
+    };
+    model.visit(&mut norm);
+    let Some(sum_of_squares) = norm.sum_of_squares else {
+        return grads;
+    };
+
+    let norm = sum_of_squares.into_scalar().to_f32().sqrt();
+    if norm > max_norm {
+        model.visit(&mut GradScale::<B> {
+            grads: &mut grads,
+            scale: max_norm / norm,
+            backend: std::marker::PhantomData,
+        });
+    }
+    grads
+}
+
+/// What a gradient step saw, averaged over its batch
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrainStats {
+    pub loss: f32,
+    /// Q value of the actions taken
+    pub mean_q: f32,
+    /// Mean absolute difference between the Q values and their targets
+    pub td_error: f32,
+}
+
+/// One gradient step on a sampled batch, returns the updated network and what the step saw
+pub fn train_step<B: AutodiffBackend, O: Optimizer<DQN<B>, B>>(
     policy_net: DQN<B>,
     target_net: &DQN<B>,
     buffer: &mut ReplayBuffer,
+    optimizer: &mut O,
     device: &B::Device,
     config: &TrainingConfig,
-) -> DQN<B> {
+) -> (DQN<B>, TrainStats) {
+    let batch = buffer.sample_batch_tensors::<B>(config.batch_size, device);
+    let indices = batch.indices.clone();
+    let (policy_net, stats, td_errors) =
+        train_on_batch(policy_net, target_net, batch, optimizer, config);
+    if let Some(td_errors) = td_errors {
+        buffer.update_priorities(&indices, &td_errors);
+    }
+    (policy_net, stats)
+}
+
+/// `train_step` without the replay buffer, so the buffer can be shared with threads that
+/// keep filling it during the step. Also returns the batch's TD errors when the buffer
+/// needs them for its priorities.
+pub fn train_on_batch<B: AutodiffBackend, O: Optimizer<DQN<B>, B>>(
+    policy_net: DQN<B>,
+    target_net: &DQN<B>,
+    batch: BatchTensors<B>,
+    optimizer: &mut O,
+    config: &TrainingConfig,
+) -> (DQN<B>, TrainStats, Option<Vec<f32>>) {
     let mut policy_net = policy_net;
 
     let BatchTensors {
@@ -51,261 +363,535 @@ pub fn train_step<B: AutodiffBackend>(
         rewards,
         next_states,
         is_dones,
-    } = buffer.sample_batch_tensors::<B>(config.batch_size, device);
-
-    let mut optimizer = config.optimizer.init();
-
-    let q_values = policy_net
-        .forward(states)
-        .gather(1, actions.unsqueeze_dim(1));
-    let next_q_values: Tensor<B, 1, Float> = target_net.forward(next_states).max_dim(1).squeeze(1);
-    let future_q: Tensor<B, 1, Float> = (1.0 - is_dones.float()) * config.gamma;
-    let future_q: Tensor<B, 1, Float> = next_q_values.mul(future_q);
-    let expected_q: Tensor<B, 2, Float> = (rewards + future_q).unsqueeze_dim(1);
-
-    assert_eq!(
-        q_values.shape().dims,
-        expected_q.shape().dims,
-        "q_values and expected_q values differ in shape {:?} != {:?}",
-        q_values.shape().dims,
-        expected_q.shape().dims,
-    );
-
-    let loss = MseLoss::new().forward(q_values, expected_q, Reduction::Mean);
+        steps,
+        weights,
+        indices: _,
+        pushes: _,
+    } = batch;
+
+    // Targets come from the networks without dropout, only the online forward pass in the
+    // loss is trained with it
+    let target_net = target_net.valid();
+    let next_inner = next_states.clone().inner();
+    // Picked by expected return, even for distributional networks
+    let next_actions = if config.double_dqn {
+        policy_net.valid().forward(next_inner.clone()).argmax(1)
+    } else {
+        target_net.forward(next_inner.clone()).argmax(1)
+    };
+    let next_actions = Tensor::from_inner(next_actions);
+    // An n-step transition bootstraps gamma^n into the future
+    let discounts = (steps * config.gamma.ln()).exp();
+    let discounts: Tensor<B, 1, Float> = (1.0 - is_dones.float()) * discounts;
+
+    let (q_values, expected_q, loss) = match policy_net.config().distributional {
+        Some(head) => {
+            let DistributionalLosses {
+                q_values,
+                expected_q,
+                losses,
+            } = distributional_losses(
+                head,
+                &policy_net,
+                &target_net,
+                states,
+                actions,
+                rewards,
+                next_inner,
+                next_actions,
+                discounts,
+                config.loss,
+            );
+            (q_values, expected_q, losses)
+        }
+        None => {
+            let q_values = policy_net
+                .forward(states)
+                .gather(1, actions.unsqueeze_dim(1));
+            // Targets are constants as far as the optimizer is concerned
+            let next_q_values: Tensor<B, 1, Float> =
+                Tensor::from_inner(target_net.forward(next_inner))
+                    .gather(1, next_actions)
+                    .squeeze(1);
+            let future_q: Tensor<B, 1, Float> = next_q_values.mul(discounts);
+            let expected_q: Tensor<B, 2, Float> = (rewards + future_q).unsqueeze_dim(1);
+
+            assert_eq!(
+                q_values.shape().dims,
+                expected_q.shape().dims,
+                "q_values and expected_q values differ in shape {:?} != {:?}",
+                q_values.shape().dims,
+                expected_q.shape().dims,
+            );
+
+            let loss = match config.loss {
+                LossFunction::Mse => {
+                    MseLoss::new().forward_no_reduction(q_values.clone(), expected_q.clone())
+                }
+                LossFunction::Huber { delta } => HuberLossConfig::new(delta)
+                    .init()
+                    .forward_no_reduction(q_values.clone(), expected_q.clone()),
+            };
+            (q_values, expected_q, loss)
+        }
+    };
+
+    // Distributional networks are prioritized by how far apart the distributions are
+    let td_errors = (config.replay.prioritization != Prioritization::Uniform).then(|| {
+        let td_errors = match policy_net.config().distributional {
+            Some(_) => loss.clone(),
+            None => expected_q.clone() - q_values.clone(),
+        };
+        td_errors
+            .detach()
+            .into_data()
+            .to_vec::<f32>()
+            .expect("TD errors should be floats")
+    });
+
+    let mean_q = q_values.clone().detach().mean().into_scalar().to_f32();
+    let td_error = (expected_q - q_values)
+        .detach()
+        .abs()
+        .mean()
+        .into_scalar()
+        .to_f32();
+
+    let loss = (loss * weights.unsqueeze_dim(1)).mean();
+    let loss_value = loss.clone().into_scalar().to_f32();
     let grads = loss.backward();
-    let grads = GradientsParams::from_grads(grads, &policy_net);
+    let mut grads = GradientsParams::from_grads(grads, &policy_net);
+    if let Some(max_norm) = config.max_grad_norm {
+        grads = clip_grad_norm(grads, &policy_net, max_norm);
+    }
 
     policy_net = optimizer.step(config.learning_rate, policy_net, grads);
-    policy_net
+    let stats = TrainStats {
+        loss: loss_value,
+        mean_q,
+        td_error,
+    };
+    (policy_net, stats, td_errors)
 }
 
-static NUM_ACTIONS: usize = 24; // TODO: this probably shouldn't be written right here
-static TRAIN_START: usize = 200;
-static TARGET_UPDATE: usize = 250;
-static MEMORY_SIZE: usize = 100_000;
-
-pub fn train<B: AutodiffBackend>(device: &B::Device, config: &TrainingConfig) -> (DQN<B>, DQN<B>) {
-    let mut rng = StdRng::seed_from_u64(config.seed);
-
-    let mut policy_net0: DQN<B> = DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE).init(device);
-    let mut policy_net1: DQN<B> = DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE).init(device);
+pub(crate) static NUM_ACTIONS: usize = 24; // TODO: this probably shouldn't be written right here
+pub(crate) static TRAIN_START: usize = 200;
+pub(crate) static MEMORY_SIZE: usize = 100_000;
+pub(crate) static MAX_EPISODE_ITERS: usize = 3000;
 
-    let mut target_net0 = DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE).init(device);
-    let mut replay_buffer0 = ReplayBuffer::new(MEMORY_SIZE);
+/// Self-play between two learners, pass fresh `DqnLearner`s or ones loaded from a checkpoint.
+/// Both players' episodes are logged to `metrics_dir` when given, and with a benchmark
+/// configured each player's best-so-far learner is saved to `best_player<p>/` there.
+pub fn train<B: AutodiffBackend>(
+    learners: [DqnLearner<B>; 2],
+    device: &B::Device,
+    config: &TrainingConfig,
+    metrics_dir: Option<&Path>,
+) -> [DqnLearner<B>; 2] {
+    let mut metrics = MetricsLogger::for_run(metrics_dir, config.metrics, 0);
+    let benchmark = Benchmark::<B::InnerBackend>::load(&config.benchmark, device)
+        .expect("Should load benchmark models");
+    let mut benchmark_states = [BenchmarkState::default(); 2];
+    let mut rng = TrainerRng::seed_from_u64(config.seed);
+
+    let [mut learner0, mut learner1] = learners;
+
+    let mut replay_buffer0 =
+        ReplayBuffer::new(MEMORY_SIZE, TrainerRng::from_rng(&mut rng)).with_config(config.replay);
     let mut steps_done0 = 0;
 
-    let mut target_net1 = DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE).init(device);
-    let mut replay_buffer1 = ReplayBuffer::new(MEMORY_SIZE);
+    let mut replay_buffer1 =
+        ReplayBuffer::new(MEMORY_SIZE, TrainerRng::from_rng(&mut rng)).with_config(config.replay);
     let mut steps_done1 = 0;
 
     let mut iters = 0;
-
-    for episode in 0..config.num_episodes {
-        println!("Beginning episode {episode}");
-        let mut env = GameState::new();
-
-        let mut p0_obs = env.get_observation(0);
-        let mut p1_obs = env.get_observation(1);
-
-        let mut total_reward0 = 0.0;
-        let mut total_reward1 = 0.0;
-
-        let mut is_episode_done = false;
-
-        while !is_episode_done {
-            let epsilon = get_epsilon(steps_done0, config.epsilon_start, config.epsilon_decay);
-
-            let action0 =
-                select_action(p0_obs, &policy_net0, epsilon, NUM_ACTIONS, &mut rng, device);
-            let action1 =
-                select_action(p1_obs, &policy_net1, epsilon, NUM_ACTIONS, &mut rng, device);
-
-            let StepResult {
-                observations,
+    let mut episode = 0;
+
+    // Every env is one fight, all of them step in lockstep with a batched forward pass
+    let mut envs = VecBoxingEnv::new((0..config.num_envs).map(|_| GameEnv::new()).collect())
+        .with_num_threads(config.num_env_threads);
+    let mut trackers =
+        vec![[EpisodeTracker::default(), EpisodeTracker::default()]; config.num_envs];
+    let mut accumulators: Vec<[NStepAccumulator; 2]> = (0..config.num_envs)
+        .map(|_| [(); 2].map(|_| NStepAccumulator::new(config.n_step, config.gamma)))
+        .collect();
+    let mut stats = [TrainStats::default(); 2];
+
+    println!("Beginning episode {episode}");
+    while episode < config.num_episodes && iters <= config.max_iters {
+        let epsilon = get_epsilon(steps_done0, config.epsilon_start, config.epsilon_decay);
+
+        let p0_obs = envs.player_observations(0);
+        let p1_obs = envs.player_observations(1);
+
+        let actions0 = select_actions(
+            &p0_obs,
+            &learner0.policy_net.valid(),
+            epsilon,
+            NUM_ACTIONS,
+            &mut rng,
+            device,
+        );
+        let actions1 = select_actions(
+            &p1_obs,
+            &learner1.policy_net.valid(),
+            epsilon,
+            NUM_ACTIONS,
+            &mut rng,
+            device,
+        );
+        let actions: Vec<[usize; 2]> = actions0
+            .iter()
+            .zip(&actions1)
+            .map(|(a0, a1)| [*a0, *a1])
+            .collect();
+
+        let steps = envs.step(&actions);
+
+        for (env_i, step) in steps.into_iter().enumerate() {
+            let EnvStep {
+                observations: [p0_obs_next, p1_obs_next],
                 rewards,
-                is_done,
-            } = env.step([Control::from_int(action0), Control::from_int(action1)]);
-
-            let p0_obs_next = observations[0];
-            let p1_obs_next = observations[1];
-
-            replay_buffer0.push(Experience {
-                state: p0_obs,
-                action: action0,
-                reward: rewards[0],
-                next_state: p0_obs_next,
-                is_done,
-            });
-
-            replay_buffer1.push(Experience {
-                state: p1_obs,
-                action: action1,
-                reward: rewards[1],
-                next_state: p1_obs_next,
-                is_done,
-            });
-
-            total_reward0 += rewards[0];
-            total_reward1 += rewards[1];
-
-            p0_obs = p0_obs_next;
-            p1_obs = p1_obs_next;
+                terminated,
+                truncated,
+                info,
+            } = step;
+            let [accumulator0, accumulator1] = &mut accumulators[env_i];
+
+            accumulator0.push(
+                Experience {
+                    state: p0_obs[env_i],
+                    action: actions0[env_i],
+                    reward: rewards[0],
+                    next_state: p0_obs_next,
+                    is_done: terminated,
+                    steps: 1,
+                },
+                terminated || truncated,
+                &mut replay_buffer0,
+            );
+
+            accumulator1.push(
+                Experience {
+                    state: p1_obs[env_i],
+                    action: actions1[env_i],
+                    reward: rewards[1],
+                    next_state: p1_obs_next,
+                    is_done: terminated,
+                    steps: 1,
+                },
+                terminated || truncated,
+                &mut replay_buffer1,
+            );
+
+            trackers[env_i][0].step(rewards[0]);
+            trackers[env_i][1].step(rewards[1]);
 
             steps_done0 += 1;
             steps_done1 += 1;
 
-            if steps_done0 > TRAIN_START && steps_done0 % config.iters_per_training_step == 0 {
-                policy_net0 = train_step(
-                    policy_net0,
-                    &target_net0,
+            let train0 =
+                steps_done0 > TRAIN_START && steps_done0 % config.iters_per_training_step == 0;
+            if train0 {
+                (learner0.policy_net, stats[0]) = train_step(
+                    learner0.policy_net,
+                    &learner0.target_net,
                     &mut replay_buffer0,
+                    &mut learner0.optimizer,
                     device,
                     config,
                 );
+                trackers[env_i][0].train(stats[0]);
             }
-            if steps_done1 > TRAIN_START && steps_done1 % config.iters_per_training_step == 0 {
-                policy_net1 = train_step(
-                    policy_net1,
-                    &target_net1,
+            let train1 =
+                steps_done1 > TRAIN_START && steps_done1 % config.iters_per_training_step == 0;
+            if train1 {
+                (learner1.policy_net, stats[1]) = train_step(
+                    learner1.policy_net,
+                    &learner1.target_net,
                     &mut replay_buffer1,
+                    &mut learner1.optimizer,
                     device,
                     config,
                 );
+                trackers[env_i][1].train(stats[1]);
             }
 
-            if steps_done0 % TARGET_UPDATE == 0 {
-                target_net0 = policy_net0.clone();
-            }
-            if steps_done1 % TARGET_UPDATE == 0 {
-                target_net1 = policy_net1.clone();
+            learner0.after_step(steps_done0, train0, config.target_update);
+            learner1.after_step(steps_done1, train1, config.target_update);
+
+            if let Some(benchmark) = &benchmark
+                && benchmark.is_due(steps_done0 - 1, steps_done0)
+            {
+                for (player, learner) in [&learner0, &learner1].into_iter().enumerate() {
+                    let result = benchmark.evaluate(
+                        &Entrant::Model {
+                            name: "student".to_string(),
+                            model: learner.policy_net.valid(),
+                        },
+                        &mut benchmark_states[player],
+                        steps_done0,
+                        episode,
+                        player,
+                        config.seed,
+                        device,
+                    );
+                    report(&result, &mut metrics, metrics_dir).expect("Should log benchmark");
+                    if result.best
+                        && let Some(dir) = metrics_dir
+                    {
+                        learner
+                            .save(&dir.join(format!("best_player{player}")))
+                            .expect("Should save best learner");
+                    }
+                }
             }
 
-            is_episode_done = is_done;
-            if is_episode_done {
+            if terminated || truncated {
                 println!(
-                    "->> Episode finished with final health 0:{} 1:{}",
-                    env.players[0].health, env.players[1].health,
+                    "->> Episode {episode} finished with final health 0:{} 1:{} Reward 0:{:.2} Reward 1:{:.2}",
+                    info.health[0],
+                    info.health[1],
+                    trackers[env_i][0].episode_return(),
+                    trackers[env_i][1].episode_return(),
                 );
+                for (player, tracker) in trackers[env_i].iter_mut().enumerate() {
+                    let opponent = format!("player{}", 1 - player);
+                    let episode_metrics = tracker.finish(episode, player, opponent, &info, epsilon);
+                    metrics.log(&episode_metrics).expect("Should log metrics");
+                }
+                episode += 1;
+                println!("Beginning episode {episode}");
             }
 
             iters += 1;
             if iters % 1000 == 0 {
                 println!(
-                    "   Running iter {iters } Reward 0:{:.2} Reward 1:{:.2} num_punches: {:?} num_landed_punches: {:?} epsilon {}",
-                    total_reward0, total_reward1, env.num_punches, env.num_landed_punches, epsilon
+                    "   Running iter {iters } Reward 0:{:.2} Reward 1:{:.2} num_punches: {:?} num_landed_punches: {:?} epsilon {} Loss 0:{:.4} Loss 1:{:.4}",
+                    trackers[env_i][0].episode_return(),
+                    trackers[env_i][1].episode_return(),
+                    info.num_punches,
+                    info.num_landed_punches,
+                    epsilon,
+                    stats[0].loss,
+                    stats[1].loss,
                 )
             }
-
-            if iters > config.max_iters {
-                break;
-            }
-        }
-
-        println!("Finishing episode {episode}");
-
-        if iters > config.max_iters {
-            break;
         }
     }
 
-    (policy_net0, policy_net1)
+    [learner0, learner1]
 }
 
-pub fn train_against<B: AutodiffBackend>(
-    student_net: DQN<B>,
-    teacher_nets: Vec<DQN<B>>,
-    device: &B::Device,
+/// Plays one fight between the student, as player 0, and `opponent`, training the student
+/// as it goes. Returns the fight's winner, `None` for a draw, or `None` outright when
+/// `max_iters` cut it short. Only finished fights are logged to `metrics`.
+pub(crate) fn play_episode<B: AutodiffBackend>(
+    student: &mut DqnLearner<B>,
+    opponent: &mut dyn Agent,
+    state: &mut TrainerState,
     config: &TrainingConfig,
-) -> DQN<B> {
-    let mut rng = StdRng::seed_from_u64(config.seed);
-
-    let mut student_net = student_net.clone();
-
-    let max_episode_iters = 3000;
-
-    let mut target_net = DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE).init(device);
-    let mut replay_buffer = ReplayBuffer::new(MEMORY_SIZE);
-    let mut steps_done = 0;
-
-    let mut iters = 0;
-
-    for episode in 0..config.num_episodes {
-        let teacher_index = rng.random_range(0..teacher_nets.len());
-        let teacher_net = &teacher_nets[teacher_index];
-        println!("Beginning episode {episode} against teacher index {teacher_index}");
-        let mut env = GameState::new();
-
-        let mut p0_obs = env.get_observation(0);
-        let mut p1_obs = env.get_observation(1);
-
-        let mut total_reward0 = 0.0;
-        let mut total_reward1 = 0.0;
-
-        let mut episode_iters = 0;
-
-        loop {
-            let epsilon = get_epsilon(steps_done, config.epsilon_start, config.epsilon_decay);
-
-            let action0 =
-                select_action(p0_obs, &student_net, epsilon, NUM_ACTIONS, &mut rng, device);
-            let action1 = select_action(p1_obs, teacher_net, 0.05, NUM_ACTIONS, &mut rng, device);
-
-            let StepResult {
-                observations,
-                rewards,
-                is_done,
-            } = env.step([Control::from_int(action0), Control::from_int(action1)]);
-
-            let p0_obs_next = observations[0];
-            let p1_obs_next = observations[1];
-
-            replay_buffer.push(Experience {
+    env: &mut TimeLimit<GameEnv>,
+    metrics: &mut MetricsLogger,
+    device: &B::Device,
+) -> Option<Option<usize>> {
+    opponent.reset();
+    let [mut p0_obs, mut p1_obs] = env.reset(None);
+    let mut accumulator = NStepAccumulator::new(config.n_step, config.gamma);
+    let mut tracker = EpisodeTracker::default();
+    let mut stats = TrainStats::default();
+
+    loop {
+        let epsilon = get_epsilon(state.steps_done, config.epsilon_start, config.epsilon_decay);
+
+        let action0 = select_action(
+            p0_obs,
+            &student.policy_net.valid(),
+            epsilon,
+            NUM_ACTIONS,
+            &mut state.rng,
+            device,
+        );
+        let action1 = opponent.act(&p1_obs, &mut state.rng).to_int();
+
+        let step = env.step([action0, action1]);
+        let [p0_obs_next, p1_obs_next] = step.observations;
+
+        // Hitting max_iters ends the episode too, so nothing is left pending for the checkpoint
+        accumulator.push(
+            Experience {
                 state: p0_obs,
                 action: action0,
-                reward: rewards[0],
+                reward: step.rewards[0],
                 next_state: p0_obs_next,
-                is_done,
-            });
-
-            total_reward0 += rewards[0];
-            total_reward1 += rewards[1];
-
-            p0_obs = p0_obs_next;
-            p1_obs = p1_obs_next;
-
-            steps_done += 1;
+                is_done: step.terminated,
+                steps: 1,
+            },
+            step.is_done() || state.iters + 1 > config.max_iters,
+            &mut state.replay_buffer,
+        );
+
+        p0_obs = p0_obs_next;
+        p1_obs = p1_obs_next;
+        tracker.step(step.rewards[0]);
+
+        state.steps_done += 1;
+
+        let trained = state.steps_done > TRAIN_START
+            && state
+                .steps_done
+                .is_multiple_of(config.iters_per_training_step);
+        if trained {
+            (student.policy_net, stats) = train_step(
+                student.policy_net.clone(),
+                &student.target_net,
+                &mut state.replay_buffer,
+                &mut student.optimizer,
+                device,
+                config,
+            );
+            tracker.train(stats);
+        }
 
-            if steps_done > TRAIN_START && steps_done % config.iters_per_training_step == 0 {
-                student_net =
-                    train_step(student_net, &target_net, &mut replay_buffer, device, config);
-            }
+        student.after_step(state.steps_done, trained, config.target_update);
+
+        state.iters += 1;
+
+        if step.is_done() {
+            println!(
+                "->> Episode finished with final health 0:{} 1:{} iter: {} epsilon: {epsilon} loss: {:.4}",
+                env.state().players[0].health,
+                env.state().players[1].health,
+                state.iters,
+                stats.loss,
+            );
+            let episode_metrics =
+                tracker.finish(state.episode, 0, opponent.name(), &step.info, epsilon);
+            metrics.log(&episode_metrics).expect("Should log metrics");
+            return Some(step.info.winner);
+        }
 
-            if steps_done % TARGET_UPDATE == 0 {
-                target_net = student_net.clone();
-            }
+        if state.iters > config.max_iters {
+            return None;
+        }
+    }
+}
 
-            iters += 1;
+/// Evaluates the student when a benchmark is due after the episode that started at
+/// `previous_steps`. A new best is checkpointed to `best/` in `checkpoint_dir`, so it can be
+/// resumed or its `policy.mpk` used like any model.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn run_benchmark<B: AutodiffBackend>(
+    benchmark: Option<&Benchmark<B::InnerBackend>>,
+    previous_steps: usize,
+    student: &DqnLearner<B>,
+    state: &mut TrainerState,
+    config: &TrainingConfig,
+    metrics: &mut MetricsLogger,
+    checkpoint_dir: Option<&Path>,
+    device: &B::Device,
+) {
+    let Some(benchmark) = benchmark else {
+        return;
+    };
+    if !benchmark.is_due(previous_steps, state.steps_done) {
+        return;
+    }
 
-            episode_iters += 1;
-            if is_done || episode_iters > max_episode_iters {
-                println!(
-                    "->> Episode finished with final health 0:{} 1:{} iter: {iters} epsilon: {epsilon}",
-                    env.players[0].health, env.players[1].health,
-                );
-                break;
-            }
+    let result = benchmark.evaluate(
+        &Entrant::Model {
+            name: "student".to_string(),
+            model: student.policy_net.valid(),
+        },
+        &mut state.benchmark,
+        state.steps_done,
+        state.episode,
+        0,
+        config.seed,
+        device,
+    );
+    report(&result, metrics, checkpoint_dir).expect("Should log benchmark");
+    if result.best
+        && let Some(dir) = checkpoint_dir
+    {
+        save_checkpoint(&dir.join("best"), student, state, config)
+            .expect("Should save best checkpoint");
+    }
+}
 
-            if iters > config.max_iters {
-                break;
-            }
-        }
+/// Trains a student against a fixed pool of opponents.
+///
+/// Pass `Checkpoint::new` to start a run or `Checkpoint::load` to resume one. With a
+/// `checkpoint_dir` the run is saved every `checkpoint_interval` episodes and when it ends,
+/// every episode is logged to the metrics files there, and benchmark results go to
+/// `benchmark.jsonl` with the best-so-far run in `best/`.
+pub fn train_against<B: AutodiffBackend>(
+    checkpoint: Checkpoint<B>,
+    mut teachers: Vec<Box<dyn Agent>>,
+    device: &B::Device,
+    checkpoint_dir: Option<&Path>,
+) -> Checkpoint<B> {
+    let Checkpoint {
+        learner: mut student,
+        mut state,
+        config,
+    } = checkpoint;
+
+    let mut env = TimeLimit::new(GameEnv::new(), MAX_EPISODE_ITERS);
+    let mut metrics = MetricsLogger::for_run(checkpoint_dir, config.metrics, state.episode);
+    let benchmark = Benchmark::<B::InnerBackend>::load(&config.benchmark, device)
+        .expect("Should load benchmark models");
+
+    while state.episode < config.num_episodes && state.iters <= config.max_iters {
+        let episode = state.episode;
+        let previous_steps = state.steps_done;
+        let teacher_index = state.rng.random_range(0..teachers.len());
+        let teacher = &mut teachers[teacher_index];
+        println!(
+            "Beginning episode {episode} against teacher {} ({teacher_index})",
+            teacher.name()
+        );
+        play_episode(
+            &mut student,
+            teacher.as_mut(),
+            &mut state,
+            &config,
+            &mut env,
+            &mut metrics,
+            device,
+        );
 
         println!("Finishing episode {episode}");
-
-        if iters > config.max_iters {
-            break;
+        state.episode += 1;
+
+        run_benchmark(
+            benchmark.as_ref(),
+            previous_steps,
+            &student,
+            &mut state,
+            &config,
+            &mut metrics,
+            checkpoint_dir,
+            device,
+        );
+
+        // Checkpoints only happen between episodes, so there's no fight in progress to save
+        if let Some(dir) = checkpoint_dir
+            && config.checkpoint_interval > 0
+            && state.episode % config.checkpoint_interval == 0
+        {
+            save_checkpoint(dir, &student, &state, &config).expect("Should save checkpoint");
+            println!("Saved checkpoint to {}", dir.display());
         }
     }
 
-    student_net
+    if let Some(dir) = checkpoint_dir {
+        save_checkpoint(dir, &student, &state, &config).expect("Should save checkpoint");
+    }
+
+    Checkpoint {
+        learner: student,
+        state,
+        config,
+    }
 }
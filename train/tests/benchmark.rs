@@ -0,0 +1,57 @@
+use core::{bots::ScriptedBot, model::DQNConfig};
+
+use burn::backend::NdArray;
+use train::{
+    benchmark::{Benchmark, BenchmarkConfig, BenchmarkState, DEFAULT_ELO, expected_score},
+    evaluation::Entrant,
+};
+
+type TestBackend = NdArray<f32>;
+
+#[test]
+fn expected_scores_are_symmetric() {
+    assert_eq!(expected_score(1000.0, 1000.0), 0.5);
+    let favourite = expected_score(1400.0, 1000.0);
+    assert!((favourite - 10.0 / 11.0).abs() < 1e-5);
+    assert!((favourite + expected_score(1000.0, 1400.0) - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn evaluation_moves_elo_with_the_score() {
+    let device = Default::default();
+    let off = BenchmarkConfig::default();
+    assert!(
+        Benchmark::<TestBackend>::load(&off, &device)
+            .expect("Bots need no loading")
+            .is_none()
+    );
+
+    let config = BenchmarkConfig {
+        interval: 1000,
+        games: 2,
+        bots: vec![ScriptedBot::Idle],
+        ..Default::default()
+    };
+    let benchmark = Benchmark::<TestBackend>::load(&config, &device)
+        .expect("Bots need no loading")
+        .expect("Benchmark should be on");
+    assert!(!benchmark.is_due(0, 999));
+    assert!(benchmark.is_due(999, 1000));
+    // Long episodes can step over a multiple without landing on it
+    assert!(benchmark.is_due(900, 3900));
+
+    let student = DQNConfig::new(core::OBSERVATION_LENGTH, core::OUTPUT_SIZE).init(&device);
+    let mut state = BenchmarkState::default();
+    let student = Entrant::Model {
+        name: "student".to_string(),
+        model: student,
+    };
+    let result = benchmark.evaluate(&student, &mut state, 1000, 0, 0, 7, &device);
+
+    assert_eq!(result.opponents.len(), 1);
+    assert_eq!(result.opponents[0].games(), 2);
+    assert!(result.best, "The first evaluation is the best so far");
+    assert_eq!(state.best_score, Some(result.score));
+    let expected_elo = DEFAULT_ELO + config.k_factor * 2.0 * (result.score - 0.5);
+    assert!((state.elo - expected_elo).abs() < 1e-3);
+}
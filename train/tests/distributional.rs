@@ -0,0 +1,160 @@
+use core::{
+    OBSERVATION_LENGTH, OUTPUT_SIZE,
+    env::{BoxingEnv, GameEnv},
+    model::{DQN, DQNConfig, DistributionalHead, Risk, SavedModel, load_saved_model, save_dqn},
+};
+
+use burn::{
+    backend::{Autodiff, NdArray},
+    prelude::*,
+    record::{BinBytesRecorder, FullPrecisionSettings},
+    tensor::Distribution,
+};
+use rand::SeedableRng;
+use train::{
+    checkpoint::TrainerRng,
+    replay_buffer::{Experience, ReplayBuffer},
+    train::{LossFunction, TrainingConfig, train_step},
+};
+
+type TestBackend = NdArray<f32>;
+
+const C51: DistributionalHead = DistributionalHead::Categorical {
+    atoms: 51,
+    v_min: -10.0,
+    v_max: 10.0,
+};
+const QR: DistributionalHead = DistributionalHead::Quantile { quantiles: 8 };
+const TRANSITIONS: usize = 16;
+
+fn model<B: Backend>(head: DistributionalHead, risk: Risk) -> DQN<B> {
+    DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE)
+        .with_hidden_sizes(vec![32])
+        .with_dueling(true)
+        .with_distributional(Some(head))
+        .with_risk(risk)
+        .init(&Default::default())
+}
+
+fn observations() -> Tensor<TestBackend, 2> {
+    Tensor::random(
+        [8, OBSERVATION_LENGTH],
+        Distribution::Default,
+        &Default::default(),
+    )
+}
+
+#[test]
+fn caution_and_recklessness_bracket_the_mean() {
+    let observations = observations();
+    for head in [C51, QR] {
+        let neutral = model::<TestBackend>(head, Risk::Neutral);
+        let mean = neutral.forward(observations.clone());
+        neutral
+            .action_values(observations.clone())
+            .into_data()
+            .assert_eq(&mean.clone().into_data(), true);
+
+        let cautious = neutral.clone().with_risk(Risk::Cautious { alpha: 0.25 });
+        let reckless = neutral.clone().with_risk(Risk::Reckless { alpha: 0.25 });
+        let below = (mean.clone() - cautious.action_values(observations.clone()))
+            .min()
+            .into_scalar();
+        let above = (reckless.action_values(observations.clone()) - mean.clone())
+            .min()
+            .into_scalar();
+        assert!(
+            below >= -1e-4 && above >= -1e-4,
+            "{head:?}: {below} {above}"
+        );
+
+        // The whole distribution is just the mean again
+        let everything = neutral.with_risk(Risk::Cautious { alpha: 1.0 });
+        everything
+            .action_values(observations.clone())
+            .into_data()
+            .assert_approx_eq::<f32>(&mean.into_data(), Default::default());
+    }
+}
+
+#[test]
+fn distributional_models_load_with_their_head_and_risk() {
+    let device = Default::default();
+    let recorder = BinBytesRecorder::<FullPrecisionSettings>::default();
+    let saved = model::<TestBackend>(C51, Risk::Reckless { alpha: 0.1 });
+    let observations = observations();
+    let expected = saved.forward(observations.clone());
+    let bytes = save_dqn(saved, &recorder, ()).expect("Should save DQN");
+    let SavedModel::Dqn(loaded) =
+        load_saved_model::<TestBackend, _>(&recorder, bytes, &device).expect("Should load DQN")
+    else {
+        panic!("A distributional DQN should load as a DQN");
+    };
+
+    assert_eq!(loaded.config().distributional, Some(C51));
+    assert_eq!(loaded.config().risk, Risk::Reckless { alpha: 0.1 });
+    loaded
+        .forward(observations)
+        .into_data()
+        .assert_approx_eq::<f32>(&expected.into_data(), Default::default());
+}
+
+/// Knockouts worth 2 after every action, so every return distribution should collapse there
+fn knockouts() -> ReplayBuffer {
+    let mut env = GameEnv::new();
+    let mut buffer = ReplayBuffer::new(TRANSITIONS, TrainerRng::seed_from_u64(0));
+    let [mut observation, _] = env.reset(None);
+    for step in 0..TRANSITIONS {
+        let next = env.step([step % OUTPUT_SIZE, 0]).observations[0];
+        buffer.push(Experience {
+            state: observation,
+            action: step % OUTPUT_SIZE,
+            reward: 2.0,
+            next_state: next,
+            is_done: true,
+            steps: 1,
+        });
+        observation = next;
+    }
+    buffer
+}
+
+#[test]
+fn distributions_learn_their_targets() {
+    let device = Default::default();
+    for (head, loss) in [
+        (C51, LossFunction::Mse),
+        (QR, LossFunction::Huber { delta: 1.0 }),
+        (QR, LossFunction::Mse),
+    ] {
+        let config = TrainingConfig {
+            batch_size: TRANSITIONS,
+            learning_rate: 0.01,
+            loss,
+            ..Default::default()
+        };
+        let mut policy_net = model::<Autodiff<TestBackend>>(head, Risk::Neutral);
+        let target_net = policy_net.clone();
+        let mut optimizer = config.optimizer.init();
+        let mut buffer = knockouts();
+        let mut stats = Default::default();
+        for _ in 0..150 {
+            (policy_net, stats) = train_step(
+                policy_net,
+                &target_net,
+                &mut buffer,
+                &mut optimizer,
+                &device,
+                &config,
+            );
+        }
+
+        assert!(stats.loss.is_finite(), "{head:?}");
+        assert!(
+            (stats.mean_q - 2.0).abs() < 0.1,
+            "{head:?}: {}",
+            stats.mean_q
+        );
+        assert!(stats.td_error < 0.1, "{head:?}: {}", stats.td_error);
+    }
+}
@@ -0,0 +1,53 @@
+use core::{
+    OBSERVATION_LENGTH, OUTPUT_SIZE, Observation,
+    env::{BoxingEnv, GameEnv},
+    model::{DQN, DQNConfig},
+    select_actions,
+};
+
+use burn::{
+    backend::{Autodiff, NdArray},
+    module::AutodiffModule,
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+type TestBackend = Autodiff<NdArray<f32>>;
+
+/// A few different moments of one fight
+fn observations() -> Vec<Observation> {
+    let mut env = GameEnv::new();
+    let [first, _] = env.reset(None);
+    let mut observations = vec![first];
+    for step in 0..7 {
+        observations.push(env.step([step % OUTPUT_SIZE, 0]).observations[0]);
+    }
+    observations
+}
+
+#[test]
+fn greedy_actions_ignore_dropout() {
+    let device = Default::default();
+    let policy_net: DQN<TestBackend> = DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE)
+        .with_hidden_sizes(vec![64, 64])
+        .with_dropout(0.5)
+        .init(&device);
+    let observations = observations();
+    let mut rng = StdRng::seed_from_u64(0);
+
+    // What the trainers act with
+    let actor = policy_net.valid();
+    let first = select_actions(&observations, &actor, 0.0, OUTPUT_SIZE, &mut rng, &device);
+    for _ in 0..20 {
+        let actions = select_actions(&observations, &actor, 0.0, OUTPUT_SIZE, &mut rng, &device);
+        assert_eq!(actions, first);
+    }
+
+    // The training copy still drops units, so it's what the loss sees
+    let input = core::observations_to_tensor::<TestBackend>(&observations, &device);
+    let once = policy_net.forward(input.clone());
+    let again = policy_net.forward(input);
+    assert!(
+        (once - again).abs().max().into_scalar() > 0.0,
+        "dropout should still apply while training"
+    );
+}
@@ -0,0 +1,61 @@
+use core::{
+    OBSERVATION_LENGTH,
+    env::{BoxSpace, BoxingEnv, FrameStack, GameEnv, RewardScale, TimeLimit},
+};
+
+/// Steps both players forward with the same actions every time, so plain and wrapped
+/// envs see the same fight
+const ACTIONS: [usize; 2] = [1, 2];
+
+#[test]
+fn time_limit_truncates_at_max_steps() {
+    let mut env = TimeLimit::new(GameEnv::new(), 5);
+    for _ in 0..2 {
+        env.reset(None);
+        for _ in 0..4 {
+            let step = env.step(ACTIONS);
+            assert!(!step.truncated && !step.is_done());
+        }
+        let step = env.step(ACTIONS);
+        assert!(step.truncated && !step.terminated);
+        assert_eq!(step.info.winner, env.state().winner());
+    }
+}
+
+#[test]
+fn reward_scale_multiplies_both_rewards() {
+    let mut plain = GameEnv::new();
+    let mut scaled = RewardScale::new(GameEnv::new(), 2.5);
+    plain.reset(None);
+    scaled.reset(None);
+    for _ in 0..50 {
+        let expected = plain.step(ACTIONS).rewards.map(|reward| reward * 2.5);
+        assert_eq!(scaled.step(ACTIONS).rewards, expected);
+    }
+}
+
+#[test]
+fn frame_stack_pads_with_the_first_frame_and_shifts() {
+    let mut plain = GameEnv::new();
+    let mut stacked = FrameStack::new(GameEnv::new(), 3);
+    assert_eq!(stacked.observation_space(), BoxSpace::normalized(3));
+
+    let first = plain.reset(None)[0].normalize();
+    let frames = stacked.reset(None)[0].clone();
+    assert_eq!(frames.len(), 3 * OBSERVATION_LENGTH);
+    assert!(
+        frames
+            .chunks(OBSERVATION_LENGTH)
+            .all(|frame| frame == first)
+    );
+
+    let second = plain.step(ACTIONS).observations[0].normalize();
+    let frames = stacked.step(ACTIONS).observations[0].clone();
+    let expected: Vec<f32> = [first, first, second].concat();
+    assert_eq!(frames, expected);
+
+    let third = plain.step(ACTIONS).observations[0].normalize();
+    let frames = stacked.step(ACTIONS).observations[0].clone();
+    let expected: Vec<f32> = [first, second, third].concat();
+    assert_eq!(frames, expected);
+}
@@ -0,0 +1,69 @@
+use core::{
+    agent::{Agent, HumanAgent},
+    bots::ScriptedBot,
+};
+
+use rand::{SeedableRng, rngs::StdRng};
+use train::league::{League, LeagueConfig, PfspWeighting};
+
+const SAMPLES: usize = 20_000;
+
+fn league(weighting: PfspWeighting, max_snapshots: usize) -> League {
+    let fixed: Vec<Box<dyn Agent>> = vec![
+        Box::new(ScriptedBot::Pressure),
+        Box::new(ScriptedBot::OutBoxer),
+    ];
+    League::new(
+        fixed,
+        LeagueConfig {
+            weighting,
+            max_snapshots,
+            ..Default::default()
+        },
+    )
+}
+
+/// How often each opponent is picked, once the learner has won 8 of 8 fights against the
+/// first and lost 8 of 8 to the second
+fn sample_frequencies(weighting: PfspWeighting) -> [f32; 2] {
+    let mut league = league(weighting, 1);
+    for _ in 0..8 {
+        league.record(0, Some(0));
+        league.record(1, Some(1));
+    }
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut counts = [0; 2];
+    for _ in 0..SAMPLES {
+        counts[league.sample(&mut rng)] += 1;
+    }
+    counts.map(|count| count as f32 / SAMPLES as f32)
+}
+
+#[test]
+fn sampling_follows_the_pfsp_weights() {
+    // Win rates come to 0.9 and 0.1, so weights of 0.1^2 and 0.9^2
+    let frequencies = sample_frequencies(PfspWeighting::Hard { exponent: 2.0 });
+    let expected = 0.01 / (0.01 + 0.81);
+    assert!((frequencies[0] - expected).abs() < 0.005, "{frequencies:?}");
+
+    // Both are equally lopsided matchups
+    let frequencies = sample_frequencies(PfspWeighting::Variance);
+    assert!((frequencies[0] - 0.5).abs() < 0.02, "{frequencies:?}");
+}
+
+#[test]
+fn oldest_snapshot_is_evicted_and_fixed_opponents_stay() {
+    let mut league = league(PfspWeighting::Variance, 2);
+    for episode in 0..4 {
+        league.add_snapshot(Box::new(HumanAgent::new(format!("snapshot{episode}"))));
+    }
+    let names: Vec<&str> = league
+        .records()
+        .map(|record| record.name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        ["bot_pressure", "bot_out_boxer", "snapshot2", "snapshot3"]
+    );
+    assert_eq!(league.records().filter(|record| record.snapshot).count(), 2);
+}
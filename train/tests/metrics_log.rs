@@ -0,0 +1,87 @@
+use train::{
+    metrics::{EpisodeMetrics, MetricsConfig, MetricsLogger, Outcome, read_metrics},
+    tensorboard::crc32c,
+};
+
+fn episode(episode: usize) -> EpisodeMetrics {
+    EpisodeMetrics {
+        episode,
+        player: 0,
+        opponent: "bot_pressure".to_string(),
+        episode_return: 1.5,
+        length: 300,
+        outcome: Outcome::Win,
+        punches_thrown: 20,
+        punches_landed: 7,
+        epsilon: 0.5,
+        loss: 0.25,
+        mean_q: 2.0,
+        td_error: 0.125,
+    }
+}
+
+#[test]
+fn crc32c_matches_the_check_value() {
+    assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+}
+
+#[test]
+fn metrics_round_trip_and_resumed_runs_append() {
+    let dir = std::env::temp_dir().join(format!("boxing_metrics_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = MetricsConfig {
+        csv: true,
+        jsonl: true,
+        tensorboard: false,
+    };
+
+    let mut logger = MetricsLogger::create(&dir, config, 0).expect("Should open metrics files");
+    logger.log(&episode(0)).expect("Should log");
+    drop(logger);
+    // Reopening must not repeat the csv header
+    let mut logger = MetricsLogger::create(&dir, config, 1).expect("Should reopen metrics files");
+    logger.log(&episode(1)).expect("Should log");
+    drop(logger);
+
+    for name in ["metrics.csv", "metrics.jsonl"] {
+        let records = read_metrics(&dir.join(name)).expect("Should read metrics back");
+        assert_eq!(records, vec![episode(0), episode(1)], "{name}");
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn resuming_drops_episodes_logged_after_the_checkpoint() {
+    let dir = std::env::temp_dir().join(format!("boxing_metrics_resume_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = MetricsConfig {
+        csv: true,
+        jsonl: true,
+        tensorboard: true,
+    };
+
+    let mut logger = MetricsLogger::create(&dir, config, 0).expect("Should open metrics files");
+    for index in 0..4 {
+        logger.log(&episode(index)).expect("Should log");
+    }
+    drop(logger);
+    // Benchmarks count finished episodes, so the one at 2 ran before the checkpoint
+    let benchmarks: Vec<String> = (1..4)
+        .map(|index| format!(r#"{{"step":{},"episode":{index}}}"#, 100 * index))
+        .collect();
+    std::fs::write(dir.join("benchmark.jsonl"), benchmarks.join("\n") + "\n")
+        .expect("Should write benchmarks");
+    // The checkpoint was saved after episode 1, so 2 and 3 are played again
+    let mut logger = MetricsLogger::create(&dir, config, 2).expect("Should reopen metrics files");
+    logger.log(&episode(2)).expect("Should log");
+    drop(logger);
+
+    for name in ["metrics.csv", "metrics.jsonl"] {
+        let records = read_metrics(&dir.join(name)).expect("Should read metrics back");
+        assert_eq!(records, vec![episode(0), episode(1), episode(2)], "{name}");
+    }
+    let benchmark =
+        std::fs::read_to_string(dir.join("benchmark.jsonl")).expect("Should read benchmarks back");
+    assert_eq!(benchmark, benchmarks[..2].join("\n") + "\n");
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,227 @@
+use core::{
+    OBSERVATION_LENGTH, OUTPUT_SIZE,
+    bots::ScriptedBot,
+    env::{BoxingEnv, GameEnv},
+    model::DQNConfig,
+};
+
+use burn::backend::{Autodiff, NdArray};
+use rand::{SeedableRng, rngs::StdRng};
+use train::{
+    checkpoint::{Checkpoint, TrainerRng},
+    replay_buffer::{Experience, ReplayBuffer},
+    train::{DqnLearner, TrainingConfig, train_step},
+};
+
+type TestBackend = Autodiff<NdArray<f32>>;
+
+const BATCH_SIZE: usize = 64;
+const NUM_STEPS: usize = 20;
+
+fn config() -> TrainingConfig {
+    TrainingConfig {
+        gamma: 0.99,
+        batch_size: BATCH_SIZE,
+        learning_rate: 0.001,
+        num_episodes: 1,
+        max_iters: 0,
+        seed: 0,
+        iters_per_training_step: 1,
+        checkpoint_interval: 0,
+        ..Default::default()
+    }
+}
+
+/// Exactly one batch worth of transitions, so every sampled batch is the whole buffer
+fn filled_buffer() -> ReplayBuffer {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut env = GameEnv::new();
+    let mut buffer = ReplayBuffer::new(BATCH_SIZE, TrainerRng::seed_from_u64(0));
+    let [mut obs0, mut obs1] = env.reset(None);
+    for _ in 0..BATCH_SIZE {
+        let action0 = ScriptedBot::Random.select_action(&obs0, &mut rng);
+        let action1 = ScriptedBot::Pressure.select_action(&obs1, &mut rng);
+        let step = env.step([action0, action1]);
+        buffer.push(Experience {
+            state: obs0,
+            action: action0,
+            reward: step.rewards[0],
+            next_state: step.observations[0],
+            is_done: step.terminated,
+            steps: 1,
+        });
+        [obs0, obs1] = step.observations;
+    }
+    buffer
+}
+
+fn learner(config: &TrainingConfig) -> DqnLearner<TestBackend> {
+    let device = Default::default();
+    let policy_net = DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE).init(&device);
+    DqnLearner::new(policy_net, &device, config)
+}
+
+/// Same weights, fresh optimizer
+fn copy_of(learner: &DqnLearner<TestBackend>, config: &TrainingConfig) -> DqnLearner<TestBackend> {
+    DqnLearner {
+        policy_net: learner.policy_net.clone(),
+        target_net: learner.target_net.clone(),
+        optimizer: config.optimizer.init(),
+    }
+}
+
+fn run(learner: &mut DqnLearner<TestBackend>, buffer: &mut ReplayBuffer, steps: usize) -> Vec<f32> {
+    let config = config();
+    let device = Default::default();
+    (0..steps)
+        .map(|_| {
+            let stats;
+            (learner.policy_net, stats) = train_step(
+                learner.policy_net.clone(),
+                &learner.target_net,
+                buffer,
+                &mut learner.optimizer,
+                &device,
+                &config,
+            );
+            stats.loss
+        })
+        .collect()
+}
+
+fn assert_close(a: f32, b: f32) {
+    assert!(
+        (a - b).abs() <= 1e-4 * a.abs().max(1.0),
+        "expected {a} to be close to {b}"
+    );
+}
+
+#[test]
+fn persistent_optimizer_changes_loss_trajectory() {
+    let config = config();
+    let device = Default::default();
+    let mut buffer = filled_buffer();
+
+    let mut persistent = learner(&config);
+    let mut reinit = copy_of(&persistent, &config);
+    let persistent_losses = run(&mut persistent, &mut buffer, NUM_STEPS);
+
+    // The old behaviour: a fresh Adam on every step
+    let reinit_losses: Vec<f32> = (0..NUM_STEPS)
+        .map(|_| {
+            let stats;
+            (reinit.policy_net, stats) = train_step(
+                reinit.policy_net.clone(),
+                &reinit.target_net,
+                &mut buffer,
+                &mut config.optimizer.init(),
+                &device,
+                &config,
+            );
+            stats.loss
+        })
+        .collect();
+
+    // Identical until the optimizer has state to lose
+    assert_close(persistent_losses[0], reinit_losses[0]);
+    assert_close(persistent_losses[1], reinit_losses[1]);
+
+    let last = NUM_STEPS - 1;
+    let difference = (persistent_losses[last] - reinit_losses[last]).abs();
+    assert!(
+        difference > 1e-3 * persistent_losses[last].abs(),
+        "loss trajectories should diverge, got {persistent_losses:?} and {reinit_losses:?}"
+    );
+}
+
+#[test]
+fn resumed_learner_matches_uninterrupted_run() {
+    let config = config();
+    let device = Default::default();
+    let mut buffer = filled_buffer();
+    let half = NUM_STEPS / 2;
+
+    let mut uninterrupted = learner(&config);
+    let mut interrupted = copy_of(&uninterrupted, &config);
+    let uninterrupted_losses = run(&mut uninterrupted, &mut buffer, NUM_STEPS);
+
+    run(&mut interrupted, &mut buffer, half);
+    let dir = std::env::temp_dir().join(format!("rust_boxing_checkpoint_{}", std::process::id()));
+    interrupted.save(&dir).expect("Should save checkpoint");
+    let mut resumed =
+        DqnLearner::<TestBackend>::load(&dir, &device, &config).expect("Should load checkpoint");
+    std::fs::remove_dir_all(&dir).expect("Should clean up checkpoint");
+    let resumed_losses = run(&mut resumed, &mut buffer, NUM_STEPS - half);
+
+    for (resumed, uninterrupted) in resumed_losses.iter().zip(&uninterrupted_losses[half..]) {
+        assert_close(*resumed, *uninterrupted);
+    }
+}
+
+#[test]
+fn saving_over_a_checkpoint_replaces_it_and_nothing_else() {
+    let config = config();
+    let device = Default::default();
+    let dir = std::env::temp_dir().join(format!("rust_boxing_resave_{}", std::process::id()));
+    let mut checkpoint = Checkpoint::new(learner(&config), config.clone());
+    checkpoint.save(&dir).expect("Should save checkpoint");
+    std::fs::write(dir.join("metrics.csv"), "episode\n").expect("Should write metrics");
+
+    run(&mut checkpoint.learner, &mut filled_buffer(), 1);
+    checkpoint.state.episode = 2;
+    checkpoint.save(&dir).expect("Should save over checkpoint");
+    let loaded = Checkpoint::<TestBackend>::load(&dir, &device).expect("Should load checkpoint");
+    let staged = dir.join(".saving").exists();
+    let metrics = std::fs::read_to_string(dir.join("metrics.csv")).expect("Should read metrics");
+    std::fs::remove_dir_all(&dir).expect("Should clean up checkpoint");
+
+    assert_eq!(loaded.state.episode, 2);
+    assert!(!staged, "the staging directory should be gone");
+    assert_eq!(metrics, "episode\n");
+}
+
+/// Stages a save of `episode` in `dir` by hand, as if a crash had stopped `save_checkpoint`
+fn stage_save(dir: &std::path::Path, episode: usize, committed: bool) {
+    let config = config();
+    let mut checkpoint = Checkpoint::new(learner(&config), config);
+    checkpoint.state.episode = episode;
+    let staging = dir.join(".saving");
+    checkpoint
+        .save(&staging)
+        .expect("Should save staged checkpoint");
+    if committed {
+        std::fs::write(staging.join("complete"), "").expect("Should commit staged checkpoint");
+        // The crash came after the first file was moved
+        std::fs::rename(staging.join("policy.mpk"), dir.join("policy.mpk"))
+            .expect("Should move staged policy");
+    }
+}
+
+#[test]
+fn loading_finishes_committed_saves_and_drops_the_rest() {
+    let config = config();
+    let device = Default::default();
+    let dir = std::env::temp_dir().join(format!("rust_boxing_crash_{}", std::process::id()));
+    let mut checkpoint = Checkpoint::new(learner(&config), config.clone());
+    checkpoint.state.episode = 1;
+    checkpoint.save(&dir).expect("Should save checkpoint");
+
+    stage_save(&dir, 2, false);
+    let uncommitted = Checkpoint::<TestBackend>::load(&dir, &device)
+        .expect("Should load previous checkpoint")
+        .state
+        .episode;
+    let uncommitted_staged = dir.join(".saving").exists();
+
+    stage_save(&dir, 3, true);
+    let committed = Checkpoint::<TestBackend>::load(&dir, &device)
+        .expect("Should load committed checkpoint")
+        .state
+        .episode;
+    let committed_staged = dir.join(".saving").exists();
+    std::fs::remove_dir_all(&dir).expect("Should clean up checkpoint");
+
+    assert_eq!(uncommitted, 1);
+    assert_eq!(committed, 3);
+    assert!(!uncommitted_staged && !committed_staged);
+}
@@ -0,0 +1,50 @@
+use core::{OBSERVATION_LENGTH, OUTPUT_SIZE, model::DQNConfig};
+
+use burn::backend::{Autodiff, NdArray};
+use train::{
+    pbt::{PbtConfig, train_population},
+    train::TrainingConfig,
+};
+
+type TestBackend = Autodiff<NdArray<f32>>;
+
+const ROUND_ITERS: usize = 40;
+const NUM_ROUNDS: usize = 3;
+
+#[test]
+fn members_keep_training_every_round() {
+    let config = TrainingConfig {
+        batch_size: 8,
+        // A whole-run cap this low would stop every member after the first round
+        max_iters: ROUND_ITERS,
+        iters_per_training_step: 4,
+        checkpoint_interval: 0,
+        model: DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE).with_hidden_sizes(vec![16]),
+        pbt: PbtConfig {
+            population_size: 2,
+            round_episodes: 100,
+            round_iters: ROUND_ITERS,
+            num_rounds: NUM_ROUNDS,
+            eval_games: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let run_dir = std::env::temp_dir().join(format!("rust_boxing_pbt_{}", std::process::id()));
+    let names = vec!["a".to_string(), "b".to_string()];
+
+    let members =
+        train_population::<TestBackend>(&config, names, None, &[], &Default::default(), &run_dir);
+    std::fs::remove_dir_all(&run_dir).expect("Should clean up run dir");
+
+    // Rounds stop at their budget, so only a member that trained in every round gets past
+    // the budgets of the rounds before the last
+    for member in members {
+        let iters = member.checkpoint.state.iters;
+        assert!(
+            iters > (NUM_ROUNDS - 1) * ROUND_ITERS && iters <= NUM_ROUNDS * (ROUND_ITERS + 1),
+            "{}: {iters}",
+            member.name
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use core::{
+    OBSERVATION_LENGTH, OUTPUT_SIZE,
+    agent::Agent,
+    bots::ScriptedBot,
+    model::{DQNConfig, SavedModel, load_saved_model, save_dqn},
+    policy::{ActorCriticConfig, save_actor_critic},
+};
+
+use burn::{
+    backend::{Autodiff, NdArray},
+    record::{BinBytesRecorder, FullPrecisionSettings},
+};
+use train::{
+    ppo::{PpoConfig, train_ppo},
+    train::TrainingConfig,
+};
+
+type TestBackend = NdArray<f32>;
+
+#[test]
+fn saved_models_load_as_their_own_kind() {
+    let device = Default::default();
+    let recorder = BinBytesRecorder::<FullPrecisionSettings>::default();
+
+    let policy = ActorCriticConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE)
+        .with_hidden_sizes(vec![16])
+        .init::<TestBackend>(&device);
+    let bytes = save_actor_critic(policy, &recorder, ()).expect("Should save actor-critic");
+    let loaded = load_saved_model::<TestBackend, _>(&recorder, bytes, &device)
+        .expect("Should load actor-critic");
+    let SavedModel::ActorCritic(loaded) = loaded else {
+        panic!("An actor-critic should load as one");
+    };
+    assert_eq!(loaded.config().hidden_sizes, vec![16]);
+
+    let dqn = DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE).init::<TestBackend>(&device);
+    let bytes = save_dqn(dqn, &recorder, ()).expect("Should save DQN");
+    let loaded =
+        load_saved_model::<TestBackend, _>(&recorder, bytes, &device).expect("Should load DQN");
+    assert!(matches!(loaded, SavedModel::Dqn(_)));
+}
+
+#[test]
+fn updates_change_the_policy() {
+    let device = Default::default();
+    let config = TrainingConfig {
+        max_iters: 64,
+        ppo: PpoConfig {
+            model: ActorCriticConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE)
+                .with_hidden_sizes(vec![16]),
+            rollout_length: 32,
+            minibatch_size: 16,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let model = config.ppo.model.init::<Autodiff<TestBackend>>(&device);
+    let recorder = BinBytesRecorder::<FullPrecisionSettings>::default();
+    let before =
+        save_actor_critic(model.clone(), &recorder, ()).expect("Should save initial model");
+    let opponents: Vec<Box<dyn Agent>> = vec![Box::new(ScriptedBot::Pressure)];
+
+    let model = train_ppo(model, opponents, &device, &config, None);
+
+    let after = save_actor_critic(model, &recorder, ()).expect("Should save trained model");
+    assert_ne!(before, after, "PPO updates should move the weights");
+}
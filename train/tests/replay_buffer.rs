@@ -0,0 +1,191 @@
+use core::env::{BoxingEnv, GameEnv};
+
+use burn::backend::NdArray;
+use rand::SeedableRng;
+use train::{
+    checkpoint::TrainerRng,
+    replay_buffer::{Experience, Prioritization, ReplayBuffer, ReplayConfig},
+    sum_tree::SumTree,
+};
+
+type TestBackend = NdArray<f32>;
+
+const PRIORITIES: [f32; 4] = [1.0, 2.0, 3.0, 6.0];
+
+#[test]
+fn sum_tree_totals_and_finds_leaves_by_mass() {
+    let mut tree = SumTree::new(5);
+    for (index, value) in [1.0, 2.0, 3.0, 4.0, 5.0].into_iter().enumerate() {
+        tree.set(index, value);
+    }
+    assert_eq!(tree.total(), 15.0);
+    assert_eq!(tree.find(0.0), 0);
+    assert_eq!(tree.find(0.99), 0);
+    assert_eq!(tree.find(1.0), 1);
+    assert_eq!(tree.find(5.5), 2);
+    assert_eq!(tree.find(14.99), 4);
+    // Rounding can push the mass up to the total, which still lands on a real leaf
+    assert_eq!(tree.find(15.0), 4);
+
+    tree.set(2, 0.0);
+    assert_eq!(tree.total(), 12.0);
+    assert_eq!(tree.get(2), 0.0);
+    assert_eq!(tree.find(3.0), 3);
+}
+
+/// One transition per priority, with `priority_epsilon` 0 and `alpha` 1 so each is
+/// sampled in proportion to its priority
+fn buffer(prioritization: Prioritization, beta_start: f64, beta_steps: usize) -> ReplayBuffer {
+    buffer_with(ReplayConfig {
+        prioritization,
+        beta_start,
+        beta_steps,
+        ..Default::default()
+    })
+}
+
+fn buffer_with(config: ReplayConfig) -> ReplayBuffer {
+    let config = ReplayConfig {
+        alpha: 1.0,
+        priority_epsilon: 0.0,
+        ..config
+    };
+    let mut buffer =
+        ReplayBuffer::new(PRIORITIES.len(), TrainerRng::seed_from_u64(0)).with_config(config);
+    push_transitions(&mut buffer, PRIORITIES.len());
+    let indices: Vec<usize> = (0..PRIORITIES.len()).collect();
+    buffer.update_priorities(&indices, &PRIORITIES);
+    buffer
+}
+
+fn push_transitions(buffer: &mut ReplayBuffer, count: usize) {
+    let [observation, _] = GameEnv::new().reset(None);
+    for action in 0..count {
+        buffer.push(Experience {
+            state: observation,
+            action,
+            reward: 0.0,
+            next_state: observation,
+            is_done: true,
+            steps: 1,
+        });
+    }
+}
+
+/// Sampled indices with their importance sampling weights
+fn sample(buffer: &mut ReplayBuffer) -> (Vec<usize>, Vec<f32>) {
+    let batch = buffer.sample_batch_tensors::<TestBackend>(PRIORITIES.len(), &Default::default());
+    let weights = batch
+        .weights
+        .into_data()
+        .to_vec()
+        .expect("Should read weights");
+    (batch.indices, weights)
+}
+
+/// `(min probability / probability)^beta`, the weights scaled so the largest is 1
+fn expected_weights(indices: &[usize], beta: f64) -> Vec<f32> {
+    let lowest = indices
+        .iter()
+        .map(|&index| PRIORITIES[index])
+        .fold(f32::MAX, f32::min);
+    indices
+        .iter()
+        .map(|&index| (lowest as f64 / PRIORITIES[index] as f64).powf(beta) as f32)
+        .collect()
+}
+
+fn assert_weights(weights: &[f32], expected: &[f32]) {
+    for (weight, expected) in weights.iter().zip(expected) {
+        assert!(
+            (weight - expected).abs() < 1e-5,
+            "{weights:?} should be {expected:?}"
+        );
+    }
+}
+
+#[test]
+fn importance_weights_are_normalized_to_one() {
+    for prioritization in [Prioritization::Proportional, Prioritization::RankBased] {
+        let mut buffer = buffer(prioritization, 1.0, 1);
+        for _ in 0..10 {
+            let (indices, weights) = sample(&mut buffer);
+            let max = weights.iter().copied().fold(f32::MIN, f32::max);
+            assert_eq!(max, 1.0, "{prioritization:?}");
+            assert!(weights.iter().all(|&weight| weight > 0.0 && weight <= 1.0));
+            if prioritization == Prioritization::Proportional {
+                assert_weights(&weights, &expected_weights(&indices, 1.0));
+            }
+        }
+    }
+}
+
+#[test]
+fn beta_anneals_to_full_correction() {
+    let mut buffer = buffer(Prioritization::Proportional, 0.0, 2);
+    for beta in [0.0, 0.5, 1.0, 1.0] {
+        let (indices, weights) = sample(&mut buffer);
+        assert_weights(&weights, &expected_weights(&indices, beta));
+    }
+}
+
+#[test]
+fn rank_based_sampling_favours_the_highest_priorities() {
+    let mut buffer = buffer(Prioritization::RankBased, 1.0, 1);
+    let mut counts = [0; PRIORITIES.len()];
+    for _ in 0..200 {
+        for index in sample(&mut buffer).0 {
+            counts[index] += 1;
+        }
+    }
+    assert!(
+        counts.windows(2).all(|pair| pair[0] < pair[1]),
+        "{counts:?}"
+    );
+
+    // Priority changes reach the ranking at the next re-sort, well within 200 batches
+    buffer.update_priorities(&[0], &[100.0]);
+    let mut counts = [0; PRIORITIES.len()];
+    for _ in 0..200 {
+        for index in sample(&mut buffer).0 {
+            counts[index] += 1;
+        }
+    }
+    assert!(counts[0] > counts[3], "{counts:?}");
+}
+
+#[test]
+fn overwritten_slots_go_to_the_top_of_a_cached_ranking() {
+    let mut buffer = buffer_with(ReplayConfig {
+        prioritization: Prioritization::RankBased,
+        rank_resort_interval: 1000,
+        ..Default::default()
+    });
+    // Sorts the ranking, which puts slot 0 with the lowest priority last
+    sample(&mut buffer);
+
+    // The buffer is full, so this lands in slot 0 with the highest priority seen. The top
+    // rank holds about half the mass, so the first of 4 strata always picks it.
+    push_transitions(&mut buffer, 1);
+    for _ in 0..5 {
+        assert_eq!(sample(&mut buffer).0[0], 0);
+    }
+}
+
+#[test]
+fn priorities_skip_slots_overwritten_after_sampling() {
+    let mut buffer = buffer(Prioritization::Proportional, 1.0, 1);
+    let pushes = buffer.pushes();
+    // An actor overwrites slot 0 while the learner trains on a batch holding it
+    push_transitions(&mut buffer, 1);
+    buffer.update_sampled_priorities(&[0, 1], pushes, &[100.0, 50.0]);
+
+    let mut counts = [0; PRIORITIES.len()];
+    for _ in 0..200 {
+        for index in sample(&mut buffer).0 {
+            counts[index] += 1;
+        }
+    }
+    // Slot 0 keeps the priority it was pushed with, 6, far below slot 1's 50
+    assert!(counts[1] > 3 * counts[0], "{counts:?}");
+}
@@ -0,0 +1,85 @@
+use core::{OBSERVATION_LENGTH, OUTPUT_SIZE, agent::Agent, bots::ScriptedBot, model::DQNConfig};
+
+use burn::{
+    backend::{Autodiff, NdArray},
+    module::{Module, ModuleVisitor, ParamId},
+    prelude::{Backend, Tensor},
+};
+use train::{
+    checkpoint::Checkpoint,
+    replay_buffer::{Prioritization, ReplayConfig},
+    train::{DqnLearner, LossFunction, TargetUpdate, TrainingConfig, train_against},
+};
+
+type TestBackend = Autodiff<NdArray<f32>>;
+
+/// Every source of randomness in play: epsilon-greedy, a random teacher, prioritized
+/// sampling and dropout
+fn config(seed: u64) -> TrainingConfig {
+    TrainingConfig {
+        batch_size: 32,
+        num_episodes: 100,
+        max_iters: 400,
+        seed,
+        iters_per_training_step: 1,
+        checkpoint_interval: 0,
+        model: DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE)
+            .with_hidden_sizes(vec![64, 32])
+            .with_dropout(0.1),
+        replay: ReplayConfig {
+            prioritization: Prioritization::Proportional,
+            ..Default::default()
+        },
+        n_step: 3,
+        target_update: TargetUpdate::Polyak { tau: 0.01 },
+        loss: LossFunction::Huber { delta: 1.0 },
+        max_grad_norm: Some(10.0),
+        ..Default::default()
+    }
+}
+
+/// Parameter values only, ids are random even with a seeded backend
+struct Weights(Vec<f32>);
+
+impl<B: Backend> ModuleVisitor<B> for Weights {
+    fn visit_float<const D: usize>(&mut self, _id: ParamId, tensor: &Tensor<B, D>) {
+        let values = tensor
+            .to_data()
+            .to_vec::<f32>()
+            .expect("Weights should be f32");
+        self.0.extend(values);
+    }
+}
+
+/// Trains from scratch and returns the final policy weights
+fn train_weights(seed: u64) -> Vec<f32> {
+    let config = config(seed);
+    let device = Default::default();
+    TestBackend::seed(config.seed);
+    let learner = DqnLearner::<TestBackend>::new(config.model.init(&device), &device, &config);
+    let teachers: Vec<Box<dyn Agent>> = vec![Box::new(ScriptedBot::Random)];
+
+    let checkpoint = train_against(Checkpoint::new(learner, config), teachers, &device, None);
+    assert!(checkpoint.state.iters > 300, "Expected a few hundred steps");
+
+    let mut weights = Weights(vec![]);
+    checkpoint.learner.policy_net.visit(&mut weights);
+    weights.0
+}
+
+// The backend seed is global, so both runs stay in one test rather than racing in two
+#[test]
+fn same_seed_trains_identical_weights() {
+    let first = train_weights(3);
+    let second = train_weights(3);
+    // Bitwise, not approximately
+    let first_bits: Vec<u32> = first.iter().map(|weight| weight.to_bits()).collect();
+    let second_bits: Vec<u32> = second.iter().map(|weight| weight.to_bits()).collect();
+    assert!(
+        first_bits == second_bits,
+        "Runs with the same seed diverged"
+    );
+
+    let other_seed = train_weights(4);
+    assert!(first != other_seed, "Runs with different seeds matched");
+}
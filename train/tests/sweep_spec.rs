@@ -0,0 +1,94 @@
+use train::sweep::SweepSpec;
+
+fn spec(json: &str) -> SweepSpec {
+    serde_json::from_str(json).expect("Spec should parse")
+}
+
+#[test]
+fn grid_covers_every_combination_in_order() {
+    let spec = spec(
+        r#"{
+            "base": {"num_episodes": 10},
+            "search": "Grid",
+            "parameters": {
+                "learning_rate": {"Values": [0.0001, 0.001]},
+                "replay.alpha": {"Values": [0.4, 0.6, 0.8]}
+            }
+        }"#,
+    );
+    let runs = spec.runs().expect("Grid should expand");
+    assert_eq!(runs.len(), 6);
+
+    // Later parameters vary fastest
+    assert_eq!(runs[0].config.learning_rate, 0.0001);
+    assert_eq!(runs[0].config.replay.alpha, 0.4);
+    assert_eq!(runs[1].config.replay.alpha, 0.6);
+    assert_eq!(runs[5].config.learning_rate, 0.001);
+    assert_eq!(runs[5].config.replay.alpha, 0.8);
+    assert!(runs.iter().all(|run| run.config.num_episodes == 10));
+}
+
+#[test]
+fn random_search_is_seeded_and_in_range() {
+    let json = r#"{
+        "search": {"Random": {"samples": 5}},
+        "seed": 9,
+        "parameters": {
+            "learning_rate": {"LogUniform": {"low": 0.00001, "high": 0.01}},
+            "gamma": {"Uniform": {"low": 0.95, "high": 0.999}},
+            "iters_per_training_step": {"Values": [2, 4, 8]}
+        }
+    }"#;
+    let first = spec(json).runs().expect("Random search should expand");
+    let second = spec(json).runs().expect("Random search should expand");
+    assert_eq!(first.len(), 5);
+
+    for (a, b) in first.iter().zip(&second) {
+        assert_eq!(
+            a.overrides, b.overrides,
+            "Same seed should draw the same runs"
+        );
+        assert!((0.00001..=0.01).contains(&a.config.learning_rate));
+        assert!((0.95..=0.999).contains(&a.config.gamma));
+        assert!([2, 4, 8].contains(&a.config.iters_per_training_step));
+    }
+}
+
+#[test]
+fn unknown_fields_and_ranges_in_grids_are_rejected() {
+    let typo = spec(r#"{"search": "Grid", "parameters": {"learning_rat": {"Values": [0.1]}}}"#);
+    assert!(typo.runs().is_err());
+
+    let range = spec(
+        r#"{"search": "Grid", "parameters": {"gamma": {"Uniform": {"low": 0.9, "high": 0.99}}}}"#,
+    );
+    assert!(range.runs().is_err());
+}
+
+#[test]
+fn ranges_that_cannot_be_sampled_fail_to_parse() {
+    for (space, reason) in [
+        (
+            r#"{"LogUniform": {"low": 0.0, "high": 0.1}}"#,
+            "range needs",
+        ),
+        (
+            r#"{"LogUniform": {"low": -0.001, "high": 0.1}}"#,
+            "range needs",
+        ),
+        (
+            r#"{"LogUniform": {"low": 0.1, "high": 0.001}}"#,
+            "range needs",
+        ),
+        (r#"{"Uniform": {"low": 0.99, "high": 0.9}}"#, "range needs"),
+        (r#"{"Values": []}"#, "at least one value"),
+    ] {
+        let json = format!(
+            r#"{{"search": {{"Random": {{"samples": 2}}}}, "parameters": {{"learning_rate": {space}}}}}"#
+        );
+        let error = serde_json::from_str::<SweepSpec>(&json)
+            .err()
+            .unwrap_or_else(|| panic!("{space} should be rejected"));
+        assert!(error.to_string().contains(reason), "{error}");
+    }
+}
@@ -0,0 +1,75 @@
+use core::{
+    env::{BoxingEnv, GameEnv, TimeLimit},
+    vec_env::VecBoxingEnv,
+};
+
+const MAX_STEPS: usize = 3;
+
+fn envs(count: usize) -> Vec<TimeLimit<GameEnv>> {
+    (0..count)
+        .map(|_| TimeLimit::new(GameEnv::new(), MAX_STEPS))
+        .collect()
+}
+
+/// Different actions in every env and on every step, so the fights drift apart
+fn actions(step: usize, count: usize) -> Vec<[usize; 2]> {
+    (0..count)
+        .map(|env| [(step + env) % 24, (3 * step + env) % 24])
+        .collect()
+}
+
+#[test]
+fn finished_envs_reset_and_return_the_final_step() {
+    let mut plain = TimeLimit::new(GameEnv::new(), MAX_STEPS);
+    let mut vec_env = VecBoxingEnv::new(envs(1));
+    let first = plain.reset(None)[0].normalize();
+
+    for step in 0..MAX_STEPS {
+        let actions = actions(step, 1);
+        let expected = plain.step(actions[0]);
+        let result = vec_env.step(&actions).remove(0);
+
+        assert_eq!(result.is_done(), step + 1 == MAX_STEPS);
+        assert_eq!(result.rewards, expected.rewards);
+        assert_eq!(result.info.episode_iters, step + 1);
+        assert_eq!(result.info.winner, expected.info.winner);
+        // The step keeps the fight's last observation even once the env has moved on
+        assert_eq!(
+            result.observations[0].normalize(),
+            expected.observations[0].normalize()
+        );
+    }
+    assert_eq!(vec_env.observations()[0][0].normalize(), first);
+    assert_eq!(vec_env.envs()[0].state().num_punches, [0, 0]);
+}
+
+#[test]
+fn threaded_steps_match_sequential_steps() {
+    const NUM_ENVS: usize = 5;
+    let mut sequential = VecBoxingEnv::new(envs(NUM_ENVS));
+    let mut threaded = VecBoxingEnv::new(envs(NUM_ENVS)).with_num_threads(3);
+
+    for step in 0..4 * MAX_STEPS {
+        let actions = actions(step, NUM_ENVS);
+        let expected = sequential.step(&actions);
+        let results = threaded.step(&actions);
+        assert_eq!(results.len(), NUM_ENVS);
+        for (result, expected) in results.iter().zip(&expected) {
+            assert_eq!(result.rewards, expected.rewards);
+            assert_eq!(result.is_done(), expected.is_done());
+            assert_eq!(result.info.health, expected.info.health);
+        }
+        for (observations, expected) in threaded
+            .observations()
+            .iter()
+            .zip(sequential.observations())
+        {
+            for player in 0..2 {
+                assert_eq!(
+                    observations[player].normalize(),
+                    expected[player].normalize()
+                );
+            }
+        }
+    }
+}
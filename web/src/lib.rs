@@ -1,4 +1,10 @@
-use core::{Control, Fist, FistState, GameState, OUTPUT_SIZE, Player, model::DQN, select_action};
+use std::{cell::Cell, rc::Rc};
+
+use core::{
+    Control, Fist, FistState, GameState, Observation, Player,
+    agent::{Agent, HumanAgent},
+    env::{BoxingEnv, GameEnv},
+};
 
 extern crate web_sys;
 
@@ -6,7 +12,7 @@ use parry2d::math::Vector;
 use rand::rngs::ThreadRng;
 use wasm_bindgen::prelude::*;
 
-use crate::state::{FIGHTERS, MyBackend, MyDevice, build_and_load_model};
+use crate::state::{FIGHTERS, Fighter, MyDevice, build_and_load_model};
 
 mod state;
 
@@ -24,12 +30,13 @@ extern "C" {
 
 #[wasm_bindgen]
 pub struct Game {
-    game_state: GameState,
-    model0: DQN<MyBackend>,
-    model1: DQN<MyBackend>,
+    env: GameEnv,
+    observations: [Observation; 2],
+    /// Kept for the whole fight, so recurrent fighters remember it from one `step` to the next
+    players: [Box<dyn Agent>; 2],
+    // Set when player 0 is controlled from the page
+    human_input: Option<Rc<Cell<Control>>>,
     rng: ThreadRng,
-    #[wasm_bindgen(skip)]
-    device: MyDevice,
 }
 
 #[wasm_bindgen]
@@ -120,7 +127,7 @@ struct GameStateWeb {
 
 impl GameStateWeb {
     fn from_rust(game_state: &GameState) -> Self {
-        let is_done = game_state.players[0].health <= 0.0 || game_state.players[1].health <= 0.0;
+        let is_done = game_state.is_done();
         Self {
             player_0: PlayerWeb::from_rust(&game_state.players[0]),
             player_1: PlayerWeb::from_rust(&game_state.players[1]),
@@ -132,55 +139,70 @@ impl GameStateWeb {
 #[wasm_bindgen]
 impl Game {
     pub async fn new(player0_number: usize, player1_number: usize) -> Self {
-        let fighter0 = FIGHTERS
-            .iter()
-            .find(|f| f.number == player0_number)
-            .unwrap_or(&FIGHTERS[0]);
-        let fighter1 = FIGHTERS
-            .iter()
-            .find(|f| f.number == player1_number)
-            .unwrap_or(&FIGHTERS[0]);
+        let device: MyDevice = MyDevice::default();
+        let player0 = load_fighter(player0_number, 0.01, &device).await;
+        let player1 = load_fighter(player1_number, 0.005, &device).await;
+        Self::from_players([player0, player1], None)
+    }
 
+    /// Player 0 is driven by `set_human_control`, player 1 by the chosen fighter
+    pub async fn new_against_human(opponent_number: usize) -> Self {
         let device: MyDevice = MyDevice::default();
-        Self {
-            game_state: GameState::new(),
-            model0: build_and_load_model(fighter0.model_bytes, &device).await,
-            model1: build_and_load_model(fighter1.model_bytes, &device).await,
-            rng: rand::rng(),
-            device,
+        let human = HumanAgent::new("human");
+        let human_input = human.input();
+        let opponent = load_fighter(opponent_number, 0.005, &device).await;
+        Self::from_players([Box::new(human), opponent], Some(human_input))
+    }
+
+    pub fn set_human_control(&mut self, control: Control) {
+        if let Some(human_input) = &self.human_input {
+            human_input.set(control);
         }
     }
 
     pub fn step(&mut self) -> GameStateWeb {
-        let obs0 = self.game_state.get_observation(0);
-        let obs1 = self.game_state.get_observation(1);
-
-        let control0_int = select_action(
-            obs0,
-            &self.model0,
-            0.01,
-            OUTPUT_SIZE,
-            &mut self.rng,
-            &self.device,
-        );
-        let control0 = Control::from_int(control0_int);
-        let control1_int = select_action(
-            obs1,
-            &self.model1,
-            0.005,
-            OUTPUT_SIZE,
-            &mut self.rng,
-            &self.device,
-        );
-        let control1 = Control::from_int(control1_int);
-        self.game_state.step([control0, control1]);
-        let mut output = GameStateWeb::from_rust(&self.game_state);
+        let [obs0, obs1] = self.observations;
+
+        let control0 = self.players[0].act(&obs0, &mut self.rng);
+        let control1 = self.players[1].act(&obs1, &mut self.rng);
+        let step = self.env.step([control0.to_int(), control1.to_int()]);
+        self.observations = step.observations;
+        let mut output = GameStateWeb::from_rust(self.env.state());
         output.player_0.last_control = control0;
         output.player_1.last_control = control1;
         output
     }
 }
 
+impl Game {
+    fn from_players(
+        mut players: [Box<dyn Agent>; 2],
+        human_input: Option<Rc<Cell<Control>>>,
+    ) -> Self {
+        for player in &mut players {
+            player.reset();
+        }
+        let mut env = GameEnv::new();
+        let observations = env.reset(None);
+        Self {
+            env,
+            observations,
+            players,
+            human_input,
+            rng: rand::rng(),
+        }
+    }
+}
+
+async fn load_fighter(number: usize, epsilon: f32, device: &MyDevice) -> Box<dyn Agent> {
+    let fighter: &Fighter = FIGHTERS
+        .iter()
+        .find(|f| f.number == number)
+        .unwrap_or(&FIGHTERS[0]);
+    let model = build_and_load_model(fighter.model_bytes, device).await;
+    model.into_agent(fighter.name, epsilon, device)
+}
+
 #[wasm_bindgen]
 pub fn greet() {
     alert("Hello, {{project-name}}!");
@@ -1,12 +1,6 @@
-use core::{
-    OBSERVATION_LENGTH, OUTPUT_SIZE,
-    model::{DQN, DQNConfig},
-};
+use core::model::{SavedModel, load_saved_model};
 
-use burn::{
-    module::Module,
-    record::{BinBytesRecorder, FullPrecisionSettings, Recorder},
-};
+use burn::record::{BinBytesRecorder, FullPrecisionSettings};
 use wasm_bindgen::prelude::wasm_bindgen;
 
 pub type MyBackend = burn::backend::ndarray::NdArray<f32>;
@@ -115,13 +109,13 @@ pub static FIGHTERS: [Fighter; 8] = [
     },
 ];
 
-pub async fn build_and_load_model(bytes: &'static [u8], device: &MyDevice) -> DQN<MyBackend> {
-    let model: DQN<MyBackend> = DQNConfig::new(OBSERVATION_LENGTH, OUTPUT_SIZE).init(device);
-    let record = BinBytesRecorder::<FullPrecisionSettings, &'static [u8]>::default()
-        .load(bytes, device)
-        .expect("Failed to decode model");
-
-    model.load_record(record)
+/// Fighters can be DQNs or PPO actor-critics
+pub async fn build_and_load_model(
+    bytes: &'static [u8],
+    device: &MyDevice,
+) -> SavedModel<MyBackend> {
+    let recorder = BinBytesRecorder::<FullPrecisionSettings, &'static [u8]>::default();
+    load_saved_model(&recorder, bytes, device).expect("Failed to decode model")
 }
 
 #[wasm_bindgen]